@@ -1,6 +1,41 @@
 type BoxError = Box<dyn std::error::Error + Send + Sync + 'static>;
 type BoxResult<T> = Result<T, BoxError>;
 
-fn main() -> BoxResult<()> {
-    Ok(())
+const USAGE: &str = "\
+usage: cpp-deps-cli collate <manifest>
+       cpp-deps-cli make <clang|gcc> <fragment> <dep-file>...";
+
+fn main() -> std::process::ExitCode {
+    // NOTE: printed with `Display` rather than returned, so that the usage keeps its line breaks
+    if let Err(err) = self::run() {
+        eprintln!("error: {err}");
+        return std::process::ExitCode::FAILURE;
+    }
+    std::process::ExitCode::SUCCESS
+}
+
+fn run() -> BoxResult<()> {
+    let mut args = std::env::args().skip(1);
+    match args.next().as_deref() {
+        #[cfg(feature = "cc")]
+        Some("collate") => {
+            let manifest = args.next().ok_or(USAGE)?;
+            cpp_deps::CppDepsNinja::collate(manifest.as_str())?;
+            Ok(())
+        },
+        #[cfg(feature = "cc")]
+        Some("make") => {
+            let family = args.next().ok_or(USAGE)?;
+            let fragment = args.next().ok_or(USAGE)?;
+            let dep_paths = args.collect::<Vec<_>>();
+            cpp_deps::CppDepsMake::generate(&family, fragment.as_str(), &dep_paths)?;
+            Ok(())
+        },
+        #[cfg(not(feature = "cc"))]
+        Some(command @ ("collate" | "make")) => {
+            Err(format!("`{command}` requires cpp-deps-cli to be built with the `cc` feature").into())
+        },
+        Some(command) => Err(format!("unknown command `{command}`\n{USAGE}").into()),
+        None => Err(USAGE.into()),
+    }
 }
@@ -1,6 +1,13 @@
 use alloc::sync::Arc;
-use std::collections::{BTreeSet, HashMap, VecDeque};
+#[cfg(feature = "async")]
+use core::{
+    pin::Pin,
+    task::{Context, Poll},
+};
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet, VecDeque};
 
+#[cfg(feature = "async")]
+use futures_core::Stream;
 use p1689::r5::{
     self,
     yoke::{DepInfoNameYoke, DepInfoYoke, DepInfoYokeExt},
@@ -9,24 +16,54 @@ use qcell::{TCell, TCellOwner};
 #[cfg(test)]
 use ::{p1689::r5::Utf8Path, std::path::Path};
 
-use crate::{queue::TaskQueue, CppDepsItem, CppDepsSrc, Error, InnerError, InnerErrorKind};
+#[cfg(feature = "compile_commands")]
+use crate::compile_commands::CompileCommands;
+#[cfg(feature = "cc")]
+use crate::schedule::Priority;
+use crate::{
+    output::{CppDepsCommand, CppDepsExecution, CppDepsOutput},
+    queue::TaskQueue,
+    CppDepsCancel,
+    CppDepsEvent,
+    CppDepsItem,
+    CppDepsObserver,
+    CppDepsSrc,
+    Error,
+    InnerError,
+    InnerErrorKind,
+};
+
+#[derive(Clone, Copy, Default)]
+pub(crate) struct AnalyzerOptions {
+    pub(crate) keep_going: bool,
+    pub(crate) allow_duplicate_providers: bool,
+    pub(crate) emit_rerun_if_changed: bool,
+}
 
 pub(crate) enum AnalyzerItem<P> {
     Expects(usize),
+    Inputs(Vec<r5::Utf8PathBuf>),
     Analyze(AnalyzeNode<P>),
     #[cfg(feature = "cc")]
-    Resolve(ResolveNode),
+    Resolve(ResolveNode<P>),
+    Failure(Box<FailureNode>),
 }
 
 struct NodeOwner;
 
+struct BlockedNode<P> {
+    node: AnalyzeNode<P>,
+    skipped: bool,
+}
+
 enum GraphNode<P> {
     Blocking {
-        blocked: Vec<Arc<TCell<NodeOwner, AnalyzeNode<P>>>>,
+        blocked: Vec<Arc<TCell<NodeOwner, BlockedNode<P>>>>,
     },
     Resolved {
         phantom: core::marker::PhantomData<P>,
     },
+    Failed,
 }
 impl<P> Default for GraphNode<P> {
     fn default() -> Self {
@@ -40,12 +77,45 @@ impl<P> GraphNode<P> {
     };
 }
 
+struct Provider {
+    unit: Option<r5::Utf8PathBuf>,
+    // The source path of a module which is unique on its source path rather than its logical name.
+    unique_source_path: Option<r5::Utf8PathBuf>,
+}
+
+impl Provider {
+    // Two providers of a name conflict unless both are unique on their source paths and those paths differ.
+    fn conflicts(&self, other: &Self) -> bool {
+        match (&self.unique_source_path, &other.unique_source_path) {
+            (Some(lhs), Some(rhs)) => lhs == rhs,
+            _ => true,
+        }
+    }
+}
+
+// A unit being compiled, named by its source and the primary output of its rule, since a source may have several rules.
+#[derive(Clone, PartialEq, Eq, Hash)]
+struct PendingUnit {
+    src_path: r5::Utf8PathBuf,
+    primary_output: Option<r5::Utf8PathBuf>,
+}
+impl PendingUnit {
+    #[cfg(feature = "cc")]
+    fn new(src_path: &r5::Utf8Path, dep_info: &DepInfoYoke) -> Self {
+        Self {
+            src_path: src_path.to_owned(),
+            primary_output: dep_info.get().primary_output.as_deref().map(ToOwned::to_owned),
+        }
+    }
+}
+
 pub(crate) struct AnalyzeNode<P> {
-    #[allow(unused)]
     pub(crate) src_file: Option<Arc<CppDepsSrc<P>>>,
     pub(crate) dep_info: DepInfoYoke,
     pub(crate) bmi_dirs: BTreeSet<Arc<r5::Utf8PathBuf>>,
     pub(crate) bmi_maps: Vec<(DepInfoNameYoke, Arc<r5::Utf8PathBuf>)>,
+    pub(crate) bmi_inputs: BTreeSet<Arc<r5::Utf8PathBuf>>,
+    pub(crate) scan_execution: Option<CppDepsExecution>,
 }
 #[cfg(feature = "cc")]
 pub(crate) struct CompileNode<P> {
@@ -53,12 +123,34 @@ pub(crate) struct CompileNode<P> {
     pub(crate) dep_info: DepInfoYoke,
     pub(crate) bmi_dirs: BTreeSet<Arc<r5::Utf8PathBuf>>,
     pub(crate) bmi_maps: Vec<(DepInfoNameYoke, Arc<r5::Utf8PathBuf>)>,
+    pub(crate) bmi_inputs: BTreeSet<Arc<r5::Utf8PathBuf>>,
+    pub(crate) scan_execution: Option<CppDepsExecution>,
 }
-pub(crate) struct ResolveNode {
-    pub(crate) bmi_path: Option<r5::Utf8PathBuf>,
+pub(crate) struct ResolveNode<P> {
+    pub(crate) src_file: Option<Arc<CppDepsSrc<P>>>,
+    pub(crate) obj_path: Option<r5::Utf8PathBuf>,
+    pub(crate) bmi_outputs: Vec<(DepInfoNameYoke, r5::Utf8PathBuf)>,
+    pub(crate) bmi_paths: Vec<(DepInfoNameYoke, r5::Utf8PathBuf)>,
+    pub(crate) command: Option<CppDepsCommand>,
+    pub(crate) execution: Option<CppDepsExecution>,
     pub(crate) dep_info: DepInfoYoke,
     pub(crate) bmi_dirs: BTreeSet<Arc<r5::Utf8PathBuf>>,
     pub(crate) bmi_maps: Vec<(DepInfoNameYoke, Arc<r5::Utf8PathBuf>)>,
+    pub(crate) bmi_inputs: BTreeSet<Arc<r5::Utf8PathBuf>>,
+    pub(crate) scan_execution: Option<CppDepsExecution>,
+}
+
+pub(crate) enum FailureNode {
+    Analyze {
+        path: Option<r5::Utf8PathBuf>,
+        err: InnerError,
+    },
+    #[cfg(feature = "cc")]
+    Compile {
+        src_path: r5::Utf8PathBuf,
+        dep_info: DepInfoYoke,
+        err: InnerError,
+    },
 }
 
 pub(crate) enum WorkerItem<P, B> {
@@ -68,55 +160,378 @@ pub(crate) enum WorkerItem<P, B> {
     Expects(usize),
 }
 
+/// Outcome of every unit seen by a [`CppDepsAnalyzer`].
+///
+/// Units which were yielded by the analyzer are `succeeded`. Units whose compilation failed are `failed`, and units
+/// which were never compiled because something they (transitively) import failed are `skipped`. Items which could not
+/// be scanned or parsed at all are recorded by path in `failed_scans`.
+#[derive(Clone, Default)]
+pub struct CppDepsSummary {
+    succeeded: Vec<DepInfoYoke>,
+    failed: Vec<DepInfoYoke>,
+    failed_scans: Vec<r5::Utf8PathBuf>,
+    skipped: Vec<DepInfoYoke>,
+}
+impl CppDepsSummary {
+    pub fn succeeded(&self) -> &[DepInfoYoke] {
+        &self.succeeded
+    }
+
+    pub fn failed(&self) -> &[DepInfoYoke] {
+        &self.failed
+    }
+
+    pub fn failed_scans(&self) -> &[r5::Utf8PathBuf] {
+        &self.failed_scans
+    }
+
+    pub fn skipped(&self) -> &[DepInfoYoke] {
+        &self.skipped
+    }
+
+    pub fn is_success(&self) -> bool {
+        self.failed.is_empty() && self.failed_scans.is_empty() && self.skipped.is_empty()
+    }
+}
+impl core::fmt::Debug for CppDepsSummary {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        fn infos(infos: &[DepInfoYoke]) -> Vec<&r5::DepInfo<'_>> {
+            infos.iter().map(DepInfoYoke::get).collect()
+        }
+        f.debug_struct("CppDepsSummary")
+            .field("succeeded", &infos(&self.succeeded))
+            .field("failed", &infos(&self.failed))
+            .field("failed_scans", &self.failed_scans)
+            .field("skipped", &infos(&self.skipped))
+            .finish()
+    }
+}
+
+/// Why the analyzer could not find an ordering for the remaining blocked units.
+#[derive(Clone, Debug, Default)]
+pub struct CppDepsBlocked {
+    pub(crate) missing: Vec<CppDepsMissing>,
+    pub(crate) cycles: Vec<CppDepsCycle>,
+}
+impl CppDepsBlocked {
+    /// Modules which were required but never provided by any unit.
+    pub fn missing(&self) -> &[CppDepsMissing] {
+        &self.missing
+    }
+
+    /// Modules which (transitively) import themselves.
+    pub fn cycles(&self) -> &[CppDepsCycle] {
+        &self.cycles
+    }
+}
+impl core::fmt::Display for CppDepsBlocked {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        for missing in &self.missing {
+            write!(f, "module `{}` is never provided; required by", missing.name)?;
+            for unit in &missing.required_by {
+                write!(f, " `{unit}`")?;
+            }
+            writeln!(f)?;
+        }
+        for cycle in &self.cycles {
+            writeln!(f, "module import cycle: {}", cycle.names.join(" -> "))?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct CppDepsMissing {
+    pub(crate) name: String,
+    pub(crate) required_by: Vec<r5::Utf8PathBuf>,
+}
+impl CppDepsMissing {
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// The source paths (or primary outputs when no source is known) of the units requiring the module.
+    pub fn required_by(&self) -> &[r5::Utf8PathBuf] {
+        &self.required_by
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct CppDepsCycle {
+    pub(crate) names: Vec<String>,
+}
+impl CppDepsCycle {
+    /// The module names along the cycle, starting and ending with the same name (e.g., `foo -> bar -> foo`).
+    pub fn names(&self) -> &[String] {
+        &self.names
+    }
+}
+
+/// Two units providing the same module.
+#[derive(Clone, Debug)]
+pub struct CppDepsDuplicate {
+    pub(crate) name: String,
+    pub(crate) first: Option<r5::Utf8PathBuf>,
+    pub(crate) second: Option<r5::Utf8PathBuf>,
+}
+impl CppDepsDuplicate {
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// The source path (or primary output) of the unit which provided the module first.
+    pub fn first(&self) -> Option<&r5::Utf8Path> {
+        self.first.as_deref()
+    }
+
+    /// The source path (or primary output) of the unit which provided the module again.
+    pub fn second(&self) -> Option<&r5::Utf8Path> {
+        self.second.as_deref()
+    }
+}
+impl core::fmt::Display for CppDepsDuplicate {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        let unit = |unit: Option<&r5::Utf8Path>| unit.map(ToString::to_string).unwrap_or_else(|| "<unknown>".into());
+        writeln!(
+            f,
+            "module `{}` is provided by both `{}` and `{}`",
+            self.name,
+            unit(self.first()),
+            unit(self.second())
+        )
+    }
+}
+
+// The receivers of `CppDepsAnalyzer` as streams, built on the first poll.
+#[cfg(feature = "async")]
+struct AnalyzerStreams<P> {
+    failure: flume::r#async::RecvStream<'static, InnerError>,
+    // NOTE: boxed so that the analyzer itself doesn't need `P: 'static`
+    analyze: Pin<Box<dyn Stream<Item = AnalyzerItem<P>> + Send + Sync>>,
+    cancel: flume::r#async::RecvStream<'static, ()>,
+}
+
+// What `CppDepsAnalyzer::recv` received from one of its channels.
+enum Received<P> {
+    Item(Result<AnalyzerItem<P>, InnerError>),
+    FailureDisconnected,
+    AnalyzeDisconnected,
+    Cancelled,
+}
+
 pub struct CppDepsAnalyzer<P, B> {
     tasks: TaskQueue<P, B>,
     owner: TCellOwner<NodeOwner>,
     graph: HashMap<DepInfoNameYoke, GraphNode<P>>,
-    infos: VecDeque<DepInfoYoke>,
+    providers: HashMap<DepInfoNameYoke, Vec<Provider>>,
+    infos: VecDeque<CppDepsOutput>,
+    summary: CppDepsSummary,
+    options: AnalyzerOptions,
+    observer: Option<Arc<dyn CppDepsObserver>>,
+    cancel: CppDepsCancel,
+    inputs: BTreeSet<r5::Utf8PathBuf>,
+    env_vars: Vec<String>,
+    #[cfg(feature = "compile_commands")]
+    compile_commands: Option<CompileCommands>,
+    #[cfg(feature = "async")]
+    streams: Option<AnalyzerStreams<P>>,
+    finished: bool,
+    failure_disconnected: bool,
+    analyze_disconnected: bool,
+    rerun_emitted: bool,
     blocked_count: usize,
     analyze_count: usize,
     expects_count: Option<usize>,
-    pending_count: usize,
+    pending: HashSet<PendingUnit>,
 }
 
-impl<P, B> CppDepsAnalyzer<P, B> {
-    pub(crate) fn new(tasks: TaskQueue<P, B>) -> Self {
+impl<P, B> CppDepsAnalyzer<P, B>
+where
+    P: AsRef<r5::Utf8Path>,
+{
+    pub(crate) fn new(
+        tasks: TaskQueue<P, B>,
+        options: AnalyzerOptions,
+        observer: Option<Arc<dyn CppDepsObserver>>,
+        cancel: CppDepsCancel,
+        env_vars: Vec<String>,
+        #[cfg(feature = "compile_commands")] compile_commands: Option<CompileCommands>,
+    ) -> Self {
         Self {
             tasks,
             owner: TCellOwner::default(),
             graph: HashMap::default(),
+            providers: HashMap::default(),
             infos: VecDeque::default(),
+            summary: CppDepsSummary::default(),
+            options,
+            observer,
+            cancel,
+            inputs: BTreeSet::default(),
+            env_vars,
+            #[cfg(feature = "compile_commands")]
+            compile_commands,
+            #[cfg(feature = "async")]
+            streams: None,
+            finished: false,
+            failure_disconnected: false,
+            analyze_disconnected: false,
+            rerun_emitted: false,
             blocked_count: 0,
             analyze_count: 0,
             expects_count: None,
-            pending_count: 0,
+            pending: HashSet::default(),
+        }
+    }
+
+    /// The files the analysis read: sources, the headers they include and dependency files.
+    pub fn inputs(&self) -> &BTreeSet<r5::Utf8PathBuf> {
+        &self.inputs
+    }
+
+    /// Print the `cargo:rerun-if-changed` lines for the inputs seen so far, and the `cargo:rerun-if-env-changed` lines
+    /// for the environment variables the analysis consults.
+    ///
+    /// The variables are those locating the output directory and the target, the jobserver, and picking the compiler
+    /// and its flags, including their target-specific forms (e.g., `CXX_<target>`).
+    pub fn emit_rerun_if_changed(&self) {
+        for line in self.rerun_if_changed_lines() {
+            println!("{line}");
         }
     }
 
+    fn rerun_if_changed_lines(&self) -> Vec<String> {
+        let inputs = self
+            .inputs
+            .iter()
+            .map(|input| format!("cargo:rerun-if-changed={input}"));
+        let env_vars = self
+            .env_vars
+            .iter()
+            .map(|var| format!("cargo:rerun-if-env-changed={var}"));
+        inputs.chain(env_vars).collect()
+    }
+
+    /// The units processed so far. Complete once the analyzer has been exhausted.
+    pub fn summary(&self) -> &CppDepsSummary {
+        &self.summary
+    }
+
     fn analyze(&mut self, node: AnalyzeNode<P>) -> Result<(), InnerError> {
-        let node = Arc::new(self.owner.cell(node));
-        for key in node.ro(&self.owner).dep_info.requires() {
-            if let GraphNode::Blocking { ref mut blocked } = self.graph.entry(key).or_default() {
+        if let Err(err) = self.check_duplicates(&node) {
+            // The second provider is dropped; with `keep_going` the analysis carries on with the first one.
+            self.summary.failed.push(node.dep_info);
+            if !self.options.keep_going {
+                self.shutdown();
+            }
+            return Err(err);
+        }
+        if node
+            .dep_info
+            .requires()
+            .any(|key| matches!(self.graph.get(&key), Some(GraphNode::Failed)))
+        {
+            self.skip(node.dep_info);
+            return Ok(());
+        }
+        let node = Arc::new(self.owner.cell(BlockedNode { node, skipped: false }));
+        let mut requires = vec![];
+        for key in node.ro(&self.owner).node.dep_info.requires() {
+            if let GraphNode::Blocking { ref mut blocked } = self.graph.entry(key.clone()).or_default() {
                 blocked.push(node.clone());
+                requires.push(key);
             }
         }
-        if let Some(node) = Arc::into_inner(node).map(TCell::into_inner) {
+        let unit = self.observer.is_some().then(|| {
+            let node = &node.ro(&self.owner).node;
+            (Self::unit_path(node), names(node.dep_info.provides()))
+        });
+        if let Some(BlockedNode { node, .. }) = Arc::into_inner(node).map(TCell::into_inner) {
             self.enqueue(node)?;
         } else {
             self.blocked_count += 1;
+            if let Some((unit, provides)) = unit {
+                let requires = names(requires);
+                self.observe(|| CppDepsEvent::Blocked {
+                    unit,
+                    provides,
+                    requires,
+                });
+            }
+        }
+        Ok(())
+    }
+
+    // Check that no other unit already provides the same module. Modules which are unique on their source paths are
+    // kept apart by those paths, so providers of the same name are only rejected when their source paths match.
+    fn check_duplicates(&mut self, node: &AnalyzeNode<P>) -> Result<(), InnerError> {
+        if self.options.allow_duplicate_providers {
+            return Ok(());
+        }
+        let unit = Self::unit_path(node);
+        let provides = node
+            .dep_info
+            .provides()
+            .zip(&node.dep_info.get().provides)
+            .map(|(name, provided)| {
+                let desc = provided.desc.view();
+                let unique_source_path = matches!(desc.unique_by, r5::UniqueBy::SourcePath)
+                    .then_some(desc.source_path)
+                    .flatten()
+                    .map(ToOwned::to_owned);
+                let unit = unit.clone();
+                (name, Provider {
+                    unit,
+                    unique_source_path,
+                })
+            });
+        let provides = provides.collect::<Vec<_>>();
+        for (name, provider) in &provides {
+            let first = self.providers.get(name).into_iter().flatten();
+            if let Some(first) = first.into_iter().find(|first| first.conflicts(provider)) {
+                let duplicate = Box::new(CppDepsDuplicate {
+                    name: name.yoke.get().to_string(),
+                    first: first.unit.clone(),
+                    second: unit,
+                });
+                return Err(InnerError::new(InnerErrorKind::DuplicateModuleProvider { duplicate }));
+            }
+        }
+        for (name, provider) in provides {
+            self.providers.entry(name).or_default().push(provider);
         }
         Ok(())
     }
 
     #[cfg(feature = "cc")]
     fn compile(&mut self, node: CompileNode<P>) -> Result<(), InnerError> {
-        self.pending_count += 1;
+        self.pending
+            .insert(PendingUnit::new(node.src_file.src_path.as_ref(), &node.dep_info));
+        let dependents = self.dependents(&node.dep_info);
         self.tasks
             .compile_tx
-            .send(WorkerItem::Compile(node))
+            .send(WorkerItem::Compile(node), Priority::Compile { dependents })
             .map_err(|_| InnerError::new(InnerErrorKind::AnalyzerFailedSendingCompileItem))
     }
 
+    // Count the units transitively blocked on the names the unit provides. Only the units scanned so far are known, so
+    // this ranks the compilations by how much of the graph they are known to hold up.
+    #[cfg(feature = "cc")]
+    fn dependents(&self, dep_info: &DepInfoYoke) -> usize {
+        let mut seen = HashSet::new();
+        let mut names = dep_info.provides().collect::<Vec<_>>();
+        while let Some(name) = names.pop() {
+            if let Some(GraphNode::Blocking { blocked }) = self.graph.get(&name) {
+                for blocked in blocked {
+                    if seen.insert(Arc::as_ptr(blocked)) {
+                        names.extend(blocked.ro(&self.owner).node.dep_info.provides());
+                    }
+                }
+            }
+        }
+        seen.len()
+    }
+
     fn enqueue(&mut self, node: AnalyzeNode<P>) -> Result<(), InnerError> {
         #[cfg(feature = "cc")]
         if let Some(src_file) = node.src_file {
@@ -125,23 +540,151 @@ impl<P, B> CppDepsAnalyzer<P, B> {
                 dep_info: node.dep_info,
                 bmi_dirs: node.bmi_dirs,
                 bmi_maps: node.bmi_maps,
+                bmi_inputs: node.bmi_inputs,
+                scan_execution: node.scan_execution,
             })?;
             return Ok(());
         }
         self.resolve(ResolveNode {
-            bmi_path: None,
+            src_file: node.src_file,
+            obj_path: None,
+            bmi_outputs: Vec::new(),
+            bmi_paths: Vec::new(),
+            command: None,
+            execution: None,
             dep_info: node.dep_info,
             bmi_dirs: node.bmi_dirs,
             bmi_maps: node.bmi_maps,
+            bmi_inputs: node.bmi_inputs,
+            scan_execution: node.scan_execution,
         })
     }
 
-    fn error(&self) -> Result<Option<DepInfoYoke>, InnerError> {
-        Err(InnerError::new(InnerErrorKind::OrderingSolutionBlocked))
+    fn diagnose(&self) -> CppDepsBlocked {
+        let mut seen = HashSet::new();
+        let mut nodes = vec![];
+        let mut requirers = BTreeMap::<DepInfoNameYoke, Vec<&AnalyzeNode<P>>>::new();
+        for (name, node) in self.graph.iter() {
+            if let GraphNode::Blocking { blocked } = node {
+                for blocked in blocked.iter().map(|blocked| blocked.ro(&self.owner)) {
+                    if blocked.skipped {
+                        continue;
+                    }
+                    if seen.insert(core::ptr::from_ref(blocked)) {
+                        nodes.push(&blocked.node);
+                    }
+                    requirers.entry(name.clone()).or_default().push(&blocked.node);
+                }
+            }
+        }
+        let providers = nodes
+            .iter()
+            .flat_map(|&node| node.dep_info.provides().map(move |name| (name, node)))
+            .collect::<HashMap<_, _>>();
+
+        let missing = requirers
+            .iter()
+            .filter(|(name, _)| !providers.contains_key(*name))
+            .map(|(name, nodes)| CppDepsMissing {
+                name: name.yoke.get().to_string(),
+                required_by: nodes.iter().filter_map(|node| Self::unit_path(node)).collect(),
+            })
+            .collect();
+
+        // Depth-first search over the names provided by blocked units, recording each back edge as a cycle.
+        fn visit<P>(
+            name: &DepInfoNameYoke,
+            providers: &HashMap<DepInfoNameYoke, &AnalyzeNode<P>>,
+            stack: &mut Vec<DepInfoNameYoke>,
+            done: &mut HashSet<DepInfoNameYoke>,
+            cycles: &mut BTreeSet<Vec<String>>,
+        ) {
+            if done.contains(name) {
+                return;
+            }
+            if let Some(start) = stack.iter().position(|entry| entry == name) {
+                let mut cycle = stack[start ..]
+                    .iter()
+                    .map(|name| name.yoke.get().to_string())
+                    .collect::<Vec<_>>();
+                // Rotate so that equivalent cycles found from different starting names are only reported once.
+                if let Some(min) = cycle.iter().enumerate().min_by_key(|(_, name)| *name).map(|(i, _)| i) {
+                    cycle.rotate_left(min);
+                }
+                cycle.push(cycle[0].clone());
+                cycles.insert(cycle);
+                return;
+            }
+            if let Some(node) = providers.get(name) {
+                stack.push(name.clone());
+                for required in node.dep_info.requires() {
+                    visit(&required, providers, stack, done, cycles);
+                }
+                stack.pop();
+            }
+            done.insert(name.clone());
+        }
+        let mut names = providers.keys().collect::<Vec<_>>();
+        names.sort();
+        let mut stack = vec![];
+        let mut done = HashSet::new();
+        let mut cycles = BTreeSet::new();
+        for name in names {
+            visit(name, &providers, &mut stack, &mut done, &mut cycles);
+        }
+        let cycles = cycles.into_iter().map(|names| CppDepsCycle { names }).collect();
+
+        CppDepsBlocked { missing, cycles }
+    }
+
+    fn error(&self) -> Result<Option<CppDepsOutput>, InnerError> {
+        let blocked = Box::new(self.diagnose());
+        Err(InnerError::new(InnerErrorKind::OrderingSolutionBlocked { blocked }))
+    }
+
+    fn fail(&mut self, node: FailureNode) -> InnerError {
+        match node {
+            FailureNode::Analyze { path, err } => {
+                self.analyze_count += 1;
+                self.summary.failed_scans.extend(path);
+                err
+            },
+            #[cfg(feature = "cc")]
+            FailureNode::Compile {
+                src_path,
+                dep_info,
+                err,
+            } => {
+                self.pending.remove(&PendingUnit::new(&src_path, &dep_info));
+                self.fail_names(dep_info.provides().collect());
+                self.summary.failed.push(dep_info);
+                err
+            },
+        }
+    }
+
+    // Mark the names as failed and transitively skip every node blocked on them.
+    fn fail_names(&mut self, mut names: VecDeque<DepInfoNameYoke>) {
+        while let Some(name) = names.pop_front() {
+            if matches!(self.graph.get(&name), Some(GraphNode::Resolved { .. })) {
+                continue;
+            }
+            if let Some(GraphNode::Blocking { blocked }) = self.graph.insert(name, GraphNode::Failed) {
+                for blocked in blocked.into_iter() {
+                    let blocked = blocked.rw(&mut self.owner);
+                    if !blocked.skipped {
+                        blocked.skipped = true;
+                        self.blocked_count -= 1;
+                        names.extend(blocked.node.dep_info.provides());
+                        self.summary.skipped.push(blocked.node.dep_info.clone());
+                    }
+                }
+            }
+        }
     }
 
     fn is_finished(&self) -> bool {
-        0 == self.pending_count && Some(self.analyze_count) == self.expects_count
+        self.pending.is_empty() && Some(self.analyze_count) == self.expects_count
     }
 
     fn recv(&mut self) -> Option<Result<AnalyzerItem<P>, InnerError>> {
@@ -149,10 +692,29 @@ impl<P, B> CppDepsAnalyzer<P, B> {
         if self.is_finished() {
             self.shutdown();
         }
-        let result = flume::Selector::new()
-            .recv(&self.tasks.failure_rx, |result| result.ok().map(Err))
-            .recv(&self.tasks.analyze_rx, |result| result.ok().map(Ok))
-            .wait();
+        // NOTE: the failures may disconnect while items are still queued, so the analysis ends once both channels do
+        let result = loop {
+            if self.failure_disconnected && self.analyze_disconnected {
+                break None;
+            }
+            let mut selector = flume::Selector::new();
+            if !self.failure_disconnected {
+                selector = selector.recv(&self.tasks.failure_rx, |result| {
+                    result.map_or(Received::FailureDisconnected, |err| Received::Item(Err(err)))
+                });
+            }
+            if !self.analyze_disconnected {
+                selector = selector.recv(&self.tasks.analyze_rx, |result| {
+                    result.map_or(Received::AnalyzeDisconnected, |item| Received::Item(Ok(item)))
+                });
+            }
+            match selector.recv(self.cancel.wake_rx(), |_| Received::Cancelled).wait() {
+                Received::Item(item) => break Some(item),
+                Received::FailureDisconnected => self.failure_disconnected = true,
+                Received::AnalyzeDisconnected => self.analyze_disconnected = true,
+                Received::Cancelled => break None,
+            }
+        };
         // Release the channels if the analyzer is failing so that we don't deadlock.
         if matches!(result, Some(Err(..))) {
             self.shutdown()
@@ -160,81 +722,244 @@ impl<P, B> CppDepsAnalyzer<P, B> {
         result
     }
 
-    fn resolve(&mut self, node: ResolveNode) -> Result<(), InnerError> {
+    fn resolve(&mut self, node: ResolveNode<P>) -> Result<(), InnerError> {
         let mut queue = VecDeque::from([node]);
         while let Some(mut resolved) = queue.pop_front() {
-            if let Some(provided) = resolved.dep_info.provides().next() {
-                if let Some(bmi_path) = resolved.bmi_path {
-                    resolved.bmi_maps.push((provided.clone(), Arc::new(bmi_path)));
-                }
+            let bmi_maps = core::mem::take(&mut resolved.bmi_paths)
+                .into_iter()
+                .map(|(name, bmi_path)| (name, Arc::new(bmi_path)));
+            resolved.bmi_maps.extend(bmi_maps);
+            // Dependents are (transitively) compiled against every BMI produced upstream.
+            let bmi_inputs = resolved.bmi_outputs.iter().map(|(_, path)| Arc::new(path.clone()));
+            resolved.bmi_inputs.extend(bmi_inputs);
+            let mut unblocked = vec![];
+            for provided in resolved.dep_info.provides() {
                 if let Some(GraphNode::Blocking { blocked }) = self.graph.insert(provided, GraphNode::RESOLVED) {
-                    for blocked in blocked.into_iter() {
-                        {
-                            let blocked = blocked.rw(&mut self.owner);
-                            blocked.bmi_dirs.extend(resolved.bmi_dirs.iter().cloned());
-                            blocked.bmi_maps.extend(resolved.bmi_maps.iter().cloned());
-                        }
-                        if let Some(blocked) = Arc::into_inner(blocked).map(TCell::into_inner) {
-                            self.blocked_count -= 1;
-                            #[cfg(feature = "cc")]
-                            if let Some(src_file) = blocked.src_file {
-                                self.compile(CompileNode {
-                                    src_file,
-                                    dep_info: blocked.dep_info,
-                                    bmi_dirs: blocked.bmi_dirs,
-                                    bmi_maps: blocked.bmi_maps,
-                                })?;
-                                continue;
-                            }
-                            queue.push_back(ResolveNode {
-                                bmi_path: None,
-                                dep_info: blocked.dep_info,
-                                bmi_dirs: blocked.bmi_dirs,
-                                bmi_maps: blocked.bmi_maps,
-                            });
-                        }
+                    unblocked.extend(blocked);
+                }
+            }
+            // A node waiting on several of the provided names only needs to be updated once.
+            let mut seen = HashSet::new();
+            unblocked.retain(|blocked| seen.insert(Arc::as_ptr(blocked)));
+            for blocked in unblocked.into_iter() {
+                {
+                    let blocked = &mut blocked.rw(&mut self.owner).node;
+                    blocked.bmi_dirs.extend(resolved.bmi_dirs.iter().cloned());
+                    blocked.bmi_maps.extend(resolved.bmi_maps.iter().cloned());
+                    blocked.bmi_inputs.extend(resolved.bmi_inputs.iter().cloned());
+                }
+                if let Some(BlockedNode { node: blocked, skipped }) = Arc::into_inner(blocked).map(TCell::into_inner) {
+                    // Skipped nodes were already accounted for when they were skipped.
+                    if skipped {
+                        continue;
+                    }
+                    self.blocked_count -= 1;
+                    self.observe(|| CppDepsEvent::Unblocked {
+                        unit: Self::unit_path(&blocked),
+                        provides: names(blocked.dep_info.provides()),
+                    });
+                    #[cfg(feature = "cc")]
+                    if let Some(src_file) = blocked.src_file {
+                        self.compile(CompileNode {
+                            src_file,
+                            dep_info: blocked.dep_info,
+                            bmi_dirs: blocked.bmi_dirs,
+                            bmi_maps: blocked.bmi_maps,
+                            bmi_inputs: blocked.bmi_inputs,
+                            scan_execution: blocked.scan_execution,
+                        })?;
+                        continue;
                     }
+                    queue.push_back(ResolveNode {
+                        src_file: blocked.src_file,
+                        obj_path: None,
+                        bmi_outputs: Vec::new(),
+                        bmi_paths: Vec::new(),
+                        command: None,
+                        execution: None,
+                        dep_info: blocked.dep_info,
+                        bmi_dirs: blocked.bmi_dirs,
+                        bmi_maps: blocked.bmi_maps,
+                        bmi_inputs: blocked.bmi_inputs,
+                        scan_execution: blocked.scan_execution,
+                    });
                 }
             }
-            self.infos.push_back(resolved.dep_info);
+            self.summary.succeeded.push(resolved.dep_info.clone());
+            let output = CppDepsOutput {
+                dep_info: resolved.dep_info,
+                src_path: resolved.src_file.map(|src_file| src_file.src_path.as_ref().to_owned()),
+                obj_path: resolved.obj_path,
+                bmi_paths: resolved
+                    .bmi_outputs
+                    .into_iter()
+                    .map(|(name, path)| (name.yoke.get().to_string(), path))
+                    .collect(),
+                command: resolved.command,
+                execution: resolved.execution,
+                scan_execution: resolved.scan_execution,
+            };
+            #[cfg(feature = "compile_commands")]
+            if let Some(compile_commands) = &mut self.compile_commands {
+                compile_commands.push(&output)?;
+            }
+            self.infos.push_back(output);
         }
         Ok(())
     }
 
+    fn observe(&self, event: impl FnOnce() -> CppDepsEvent) {
+        if let Some(observer) = &self.observer {
+            observer.event(event());
+        }
+    }
+
+    fn observe_finished(&self) {
+        self.observe(|| CppDepsEvent::Finished {
+            succeeded: self.summary.succeeded.len(),
+            failed: self.summary.failed.len() + self.summary.failed_scans.len(),
+            skipped: self.summary.skipped.len(),
+        });
+    }
+
+    // NOTE: the analysis ends here, so the `cargo:` lines are printed even when it ends with an error
     fn shutdown(&mut self) {
+        self.rerun_if_changed();
         self.tasks.shutdown()
     }
 
-    fn step(&mut self) -> Result<Option<DepInfoYoke>, InnerError> {
-        while self.infos.is_empty() {
-            match self.recv().transpose()? {
-                Some(AnalyzerItem::Expects(count)) => {
-                    if self.expects_count.replace(count).is_some() {
-                        return Err(InnerError::new(InnerErrorKind::AnalyzerAlreadyReceivedExpectsCount));
-                    }
-                },
-                Some(AnalyzerItem::Analyze(node)) => {
-                    self.analyze_count += 1;
-                    self.analyze(node)?;
-                },
+    fn rerun_if_changed(&mut self) {
+        if self.options.emit_rerun_if_changed && !core::mem::replace(&mut self.rerun_emitted, true) {
+            self.emit_rerun_if_changed();
+        }
+    }
+
+    fn skip(&mut self, dep_info: DepInfoYoke) {
+        let names = dep_info.provides().collect();
+        self.summary.skipped.push(dep_info);
+        self.fail_names(names);
+    }
+
+    // Skip the nodes left blocked at the end, e.g., because the provider of a name they need failed to scan.
+    fn skip_blocked(&mut self) {
+        let blocked = self
+            .graph
+            .values_mut()
+            .filter_map(|node| match node {
+                GraphNode::Blocking { blocked } => Some(core::mem::take(blocked)),
+                _ => None,
+            })
+            .flatten()
+            .collect::<Vec<_>>();
+        for blocked in blocked {
+            let blocked = blocked.rw(&mut self.owner);
+            if !blocked.skipped {
+                blocked.skipped = true;
+                self.blocked_count -= 1;
+                // NOTE: a skipped unit is never compiled, so it must not keep the analysis waiting
                 #[cfg(feature = "cc")]
-                Some(AnalyzerItem::Resolve(node)) => {
-                    self.pending_count -= 1;
-                    self.resolve(node)?;
-                },
+                if let Some(src_file) = &blocked.node.src_file {
+                    self.pending
+                        .remove(&PendingUnit::new(src_file.src_path.as_ref(), &blocked.node.dep_info));
+                }
+                self.summary.skipped.push(blocked.node.dep_info.clone());
+            }
+        }
+    }
+
+    fn step(&mut self) -> Result<Option<CppDepsOutput>, InnerError> {
+        while self.infos.is_empty() && !self.cancel.is_cancelled() {
+            match self.recv().transpose()? {
+                Some(item) => self.handle(item)?,
                 None => break,
             }
         }
+        self.pop_info()
+    }
+
+    // End the analysis with a single `Cancelled` error, without waiting for the workers.
+    fn cancelled(&mut self) -> Result<Option<CppDepsOutput>, InnerError> {
+        if core::mem::replace(&mut self.finished, true) {
+            return Ok(None);
+        }
+        self.rerun_if_changed();
+        self.observe_finished();
+        Err(InnerError::new(InnerErrorKind::Cancelled))
+    }
+
+    fn handle(&mut self, item: AnalyzerItem<P>) -> Result<(), InnerError> {
+        match item {
+            AnalyzerItem::Inputs(inputs) => {
+                self.inputs.extend(inputs);
+            },
+            AnalyzerItem::Expects(count) => {
+                if self.expects_count.replace(count).is_some() {
+                    return Err(InnerError::new(InnerErrorKind::AnalyzerAlreadyReceivedExpectsCount));
+                }
+            },
+            AnalyzerItem::Analyze(node) => {
+                self.analyze_count += 1;
+                self.analyze(node)?;
+            },
+            #[cfg(feature = "cc")]
+            AnalyzerItem::Resolve(node) => {
+                if let Some(src_file) = &node.src_file {
+                    self.pending
+                        .remove(&PendingUnit::new(src_file.src_path.as_ref(), &node.dep_info));
+                }
+                self.resolve(node)?;
+            },
+            AnalyzerItem::Failure(node) => {
+                let err = self.fail(*node);
+                // Without `keep_going` the first failure ends the analysis.
+                if !self.options.keep_going {
+                    self.shutdown();
+                }
+                return Err(err);
+            },
+        }
+        Ok(())
+    }
+
+    fn pop_info(&mut self) -> Result<Option<CppDepsOutput>, InnerError> {
+        if self.cancel.is_cancelled() {
+            return self.cancelled();
+        }
         if let Some(info) = self.infos.pop_front() {
             return Ok(Some(info));
         }
         self.validate_graph()
     }
 
-    fn validate_graph(&self) -> Result<Option<DepInfoYoke>, InnerError> {
+    fn unit_path(node: &AnalyzeNode<P>) -> Option<r5::Utf8PathBuf> {
+        if let Some(src_file) = &node.src_file {
+            return Some(src_file.src_path.as_ref().to_owned());
+        }
+        let primary_output = node.dep_info.get().primary_output.as_deref();
+        primary_output.map(ToOwned::to_owned)
+    }
+
+    fn validate_graph(&mut self) -> Result<Option<CppDepsOutput>, InnerError> {
+        if core::mem::replace(&mut self.finished, true) {
+            return Ok(None);
+        }
+        self.rerun_if_changed();
+        if self.options.keep_going && !(self.summary.failed.is_empty() && self.summary.failed_scans.is_empty()) {
+            self.skip_blocked();
+            self.observe_finished();
+            let summary = Box::new(self.summary.clone());
+            return Err(InnerError::new(InnerErrorKind::AnalyzerFinishedWithFailures {
+                summary,
+            }));
+        }
+        self.observe_finished();
         if self.blocked_count > 0 {
             return self.error();
         }
+        #[cfg(feature = "compile_commands")]
+        if let Some(compile_commands) = &mut self.compile_commands {
+            compile_commands.write()?;
+        }
         Ok(None)
     }
 
@@ -242,45 +967,140 @@ impl<P, B> CppDepsAnalyzer<P, B> {
     pub(crate) fn validate_order<'i>(
         self,
         src_root: &'i Path,
-        mut expected_outputs: BTreeSet<&'i Utf8Path>,
+        expected_outputs: BTreeSet<&'i Utf8Path>,
     ) -> crate::testing::BoxResult<()> {
-        use alloc::collections::BTreeSet;
-        let mut valid = BTreeSet::new();
-        for result in self.into_iter() {
-            let dep_info = result?;
-            if let Some(primary_output) = dep_info.get().primary_output.as_deref() {
-                // NOTE: the `dep_text` tests don't append the tempdir prefix (though maybe they should)
-                let primary_output = primary_output.strip_prefix(src_root).unwrap_or(primary_output);
-                if !expected_outputs.remove(primary_output) {
-                    return Err("unexpected output or duplicate".into());
-                }
-            }
-            for provide in dep_info.provides() {
-                valid.insert(provide);
-            }
-            for require in dep_info.requires() {
-                if !valid.contains(&require) {
-                    return Err("missing requirement".into());
-                }
+        validate_outputs(self, src_root, expected_outputs)
+    }
+}
+
+pub(crate) fn names(names: impl IntoIterator<Item = DepInfoNameYoke>) -> Vec<String> {
+    names.into_iter().map(|name| name.yoke.get().to_string()).collect()
+}
+
+#[cfg(test)]
+pub(crate) fn validate_outputs<'i>(
+    outputs: impl IntoIterator<Item = Result<CppDepsOutput, Error>>,
+    src_root: &'i Path,
+    mut expected_outputs: BTreeSet<&'i Utf8Path>,
+) -> crate::testing::BoxResult<()> {
+    use alloc::collections::BTreeSet;
+    let mut valid = BTreeSet::new();
+    for result in outputs {
+        let output = result?;
+        let dep_info = output.dep_info();
+        if let Some(primary_output) = dep_info.get().primary_output.as_deref() {
+            // NOTE: the `dep_text` tests don't append the tempdir prefix (though maybe they should)
+            let primary_output = primary_output.strip_prefix(src_root).unwrap_or(primary_output);
+            if !expected_outputs.remove(primary_output) {
+                return Err("unexpected output or duplicate".into());
             }
         }
-        if !expected_outputs.is_empty() {
-            return Err("missing expected output".into());
+        for provide in dep_info.provides() {
+            valid.insert(provide);
+        }
+        for require in dep_info.requires() {
+            if !valid.contains(&require) {
+                return Err("missing requirement".into());
+            }
         }
-        Ok(())
     }
+    if !expected_outputs.is_empty() {
+        return Err("missing expected output".into());
+    }
+    Ok(())
 }
 
-impl<P, B> Iterator for CppDepsAnalyzer<P, B> {
-    type Item = Result<DepInfoYoke, Error>;
+impl<P, B> Iterator for CppDepsAnalyzer<P, B>
+where
+    P: AsRef<r5::Utf8Path>,
+{
+    type Item = Result<CppDepsOutput, Error>;
 
     fn next(&mut self) -> Option<Self::Item> {
         self.step().map_err(Error::from).transpose()
     }
 }
 
+// NOTE: nothing is structurally pinned; the receiver streams are `Unpin`
+#[cfg(feature = "async")]
+impl<P, B> Unpin for CppDepsAnalyzer<P, B> {}
+
+#[cfg(feature = "async")]
+impl<P, B> CppDepsAnalyzer<P, B>
+where
+    P: AsRef<r5::Utf8Path> + Send + Sync + 'static,
+{
+    // Like `recv`, but registers the task to be woken instead of blocking.
+    fn poll_recv(&mut self, cx: &mut Context<'_>) -> Poll<Option<Result<AnalyzerItem<P>, InnerError>>> {
+        if self.is_finished() {
+            self.shutdown();
+        }
+        let streams = self.streams.get_or_insert_with(|| AnalyzerStreams {
+            failure: self.tasks.failure_rx.clone().into_stream(),
+            analyze: Box::pin(self.tasks.analyze_rx.clone().into_stream()),
+            cancel: self.cancel.wake_rx().clone().into_stream(),
+        });
+        if Pin::new(&mut streams.cancel).poll_next(cx).is_ready() {
+            return Poll::Ready(None);
+        }
+        if !self.failure_disconnected {
+            match Pin::new(&mut streams.failure).poll_next(cx) {
+                Poll::Ready(Some(err)) => {
+                    self.shutdown();
+                    return Poll::Ready(Some(Err(err)));
+                },
+                Poll::Ready(None) => self.failure_disconnected = true,
+                Poll::Pending => {},
+            }
+        }
+        if !self.analyze_disconnected {
+            match streams.analyze.as_mut().poll_next(cx) {
+                Poll::Ready(Some(item)) => return Poll::Ready(Some(Ok(item))),
+                Poll::Ready(None) => self.analyze_disconnected = true,
+                Poll::Pending => {},
+            }
+        }
+        // NOTE: like `recv`, the analysis only ends once both channels are disconnected
+        if self.failure_disconnected && self.analyze_disconnected {
+            Poll::Ready(None)
+        } else {
+            Poll::Pending
+        }
+    }
+}
+
+/// Yields the same results as the [`Iterator`], waiting without blocking the thread.
+///
+/// The scans and compilations still run on the worker threads, so the stream can be polled from any executor.
+#[cfg(feature = "async")]
+impl<P, B> Stream for CppDepsAnalyzer<P, B>
+where
+    P: AsRef<r5::Utf8Path> + Send + Sync + 'static,
+{
+    type Item = Result<CppDepsOutput, Error>;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let this = self.get_mut();
+        while this.infos.is_empty() && !this.cancel.is_cancelled() {
+            match this.poll_recv(cx) {
+                Poll::Ready(Some(Ok(item))) => {
+                    if let Err(err) = this.handle(item) {
+                        return Poll::Ready(Some(Err(Error::from(err))));
+                    }
+                },
+                Poll::Ready(Some(Err(err))) => return Poll::Ready(Some(Err(Error::from(err)))),
+                Poll::Ready(None) => break,
+                Poll::Pending => return Poll::Pending,
+            }
+        }
+        Poll::Ready(this.pop_info().map_err(Error::from).transpose())
+    }
+}
+
 #[cfg(test)]
 mod test {
+    use std::collections::BTreeSet;
+
     use crate::testing::BoxResult;
 
     #[test]
@@ -297,6 +1117,99 @@ mod test {
         validate.run()
     }
 
+    #[cfg(feature = "async")]
+    #[test]
+    fn analyze_stream() -> BoxResult<()> {
+        let paths = crate::testing::corpus::dep_text::items();
+        let validate = crate::testing::corpus::dep_text::validate_order(paths)?;
+        validate.run_stream()
+    }
+
+    #[cfg(feature = "async")]
+    #[test]
+    fn analyze_stream_reverse() -> BoxResult<()> {
+        let paths = crate::testing::corpus::dep_text::items().rev();
+        let validate = crate::testing::corpus::dep_text::validate_order(paths)?;
+        validate.run_stream()
+    }
+
+    // The stream goes on after the failures disconnect, as long as analyze items may still arrive.
+    #[cfg(feature = "async")]
+    #[test]
+    fn analyze_stream_failure_disconnected() -> BoxResult<()> {
+        use futures_util::StreamExt;
+        use p1689::r5::{self, yoke::DepFileYokeExt};
+        let crate::CppDepsItem::DepText { dep_path, dep_text, .. } = crate::testing::corpus::dep_text::bar() else {
+            return Err("expected dep text".into());
+        };
+        let dep_cart: r5::yoke::DepFileCart = alloc::sync::Arc::new(dep_text.into_owned());
+        let dep_file = yoke::Yoke::<&'static _, r5::yoke::DepFileCart>::attach_to_cart(dep_cart, |cart| cart)
+            .try_map_project(|dep_text, _| {
+                let state = r5::parsers::State::default();
+                let mut stream = r5::parsers::ParseStream::new(dep_path.as_ref(), dep_text.as_ref(), state);
+                r5::parsers::dep_file(&mut stream).map_err(|_| "invalid dep text")
+            })?;
+        let (failure_tx, failure_rx) = flume::bounded(0);
+        let (analyze_tx, analyze_rx) = flume::unbounded();
+        let tasks = crate::queue::TaskQueue::<_, Vec<u8>>::from_receivers(failure_rx, analyze_rx);
+        let analyzer = super::CppDepsAnalyzer::new(
+            tasks,
+            super::AnalyzerOptions::default(),
+            None,
+            crate::CppDepsCancel::default(),
+            vec![],
+            #[cfg(feature = "compile_commands")]
+            None,
+        );
+        drop(failure_tx);
+        let sender = std::thread::spawn(move || {
+            std::thread::sleep(core::time::Duration::from_millis(50));
+            for dep_info in dep_file.rules() {
+                let node = super::AnalyzeNode::<&'static r5::Utf8Path> {
+                    src_file: None,
+                    dep_info,
+                    bmi_dirs: BTreeSet::default(),
+                    bmi_maps: Vec::default(),
+                    bmi_inputs: BTreeSet::default(),
+                    scan_execution: None,
+                };
+                analyze_tx.send(super::AnalyzerItem::Analyze(node)).ok();
+            }
+            analyze_tx.send(super::AnalyzerItem::Expects(1)).ok();
+        });
+        let outputs = futures_executor::block_on(StreamExt::collect::<Vec<_>>(analyzer));
+        sender.join().map_err(|_| "sender panicked")?;
+        let outputs = outputs.into_iter().collect::<Result<Vec<_>, _>>()?;
+        assert_eq!(outputs.len(), 1);
+        assert_eq!(
+            outputs[0].dep_info().get().primary_output.as_deref(),
+            Some(r5::Utf8Path::new("bar.o"))
+        );
+        Ok(())
+    }
+
+    #[cfg(feature = "async")]
+    #[test]
+    fn analyze_stream_incomplete_blocked() -> BoxResult<()> {
+        use futures_util::StreamExt;
+        let paths = crate::testing::corpus::dep_text::items()
+            .enumerate()
+            .filter_map(|(i, path)| (i != 3).then_some(path));
+        let validate = crate::testing::corpus::dep_text::validate_order(paths)?;
+        let mut analyzer = validate.cpp_deps.into_iter();
+        let err = futures_executor::block_on(async {
+            while let Some(result) = StreamExt::next(&mut analyzer).await {
+                if let Err(err) = result {
+                    return Some(err);
+                }
+            }
+            None
+        })
+        .ok_or("expected an error")?;
+        assert!(err.blocked().is_some());
+        Ok(())
+    }
+
     #[test]
     #[should_panic]
     fn analyze_incomplete() {
@@ -310,6 +1223,330 @@ mod test {
         inner().unwrap()
     }
 
+    #[test]
+    fn analyze_incomplete_blocked() -> BoxResult<()> {
+        let paths = crate::testing::corpus::dep_text::items()
+            .enumerate()
+            .filter_map(|(i, path)| (i != 3).then_some(path));
+        let validate = crate::testing::corpus::dep_text::validate_order(paths)?;
+        let err = validate
+            .cpp_deps
+            .into_iter()
+            .find_map(Result::err)
+            .ok_or("expected error")?;
+        let blocked = err.blocked().ok_or("missing diagnostics")?;
+        assert_eq!(blocked.missing().len(), 1);
+        assert_eq!(blocked.missing()[0].name(), "foo");
+        assert_eq!(blocked.missing()[0].required_by(), ["main.o"]);
+        assert!(blocked.cycles().is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn analyze_cycle_blocked() -> BoxResult<()> {
+        let paths = [
+            crate::testing::corpus::dep_text::foo_bar_cycle(),
+            crate::testing::corpus::dep_text::bar_foo_cycle(),
+            crate::testing::corpus::dep_text::main(),
+        ];
+        let validate = crate::testing::corpus::dep_text::validate_order(paths)?;
+        let err = validate
+            .cpp_deps
+            .into_iter()
+            .find_map(Result::err)
+            .ok_or("expected error")?;
+        let blocked = err.blocked().ok_or("missing diagnostics")?;
+        assert!(blocked.missing().is_empty());
+        assert_eq!(blocked.cycles().len(), 1);
+        assert_eq!(blocked.cycles()[0].names(), ["bar", "foo", "bar"]);
+        Ok(())
+    }
+
+    #[test]
+    fn analyze_multiple_provides() -> BoxResult<()> {
+        let paths = [
+            crate::testing::corpus::dep_text::main(),
+            crate::testing::corpus::dep_text::foo(),
+            crate::testing::corpus::dep_text::foo_parts(),
+            crate::testing::corpus::dep_text::bar(),
+        ];
+        let validate = crate::testing::corpus::dep_text::validate_order(paths)?;
+        let mut outputs = vec![];
+        for result in validate.cpp_deps.into_iter() {
+            let output = result?;
+            // Each provided module needs its own BMI, wherever the compiler writes them.
+            #[cfg(feature = "cc")]
+            for family in [
+                crate::compiler::CompilerFamily::Clang,
+                crate::compiler::CompilerFamily::Gcc,
+            ] {
+                use p1689::r5::{yoke::DepInfoYokeExt, Utf8Path};
+                let obj_path = Utf8Path::new("out/unit.o");
+                let bmi_dir = Some(Utf8Path::new("out/bmi"));
+                let bmi_paths = output
+                    .dep_info()
+                    .provides()
+                    .map(|name| family.bmi_file_dst(obj_path, name.yoke.get(), bmi_dir))
+                    .collect::<Vec<_>>();
+                assert_eq!(bmi_paths.iter().collect::<BTreeSet<_>>().len(), bmi_paths.len());
+            }
+            outputs.extend(
+                output
+                    .dep_info()
+                    .get()
+                    .primary_output
+                    .as_deref()
+                    .map(ToString::to_string),
+            );
+        }
+        assert_eq!(outputs.len(), 4);
+        assert_eq!(outputs.last().map(String::as_str), Some("main.o"));
+        Ok(())
+    }
+
+    #[test]
+    fn analyze_duplicate() -> BoxResult<()> {
+        let paths =
+            crate::testing::corpus::dep_text::items().chain([crate::testing::corpus::dep_text::bar_duplicate()]);
+        let validate = crate::testing::corpus::dep_text::validate_order(paths)?;
+        let err = validate
+            .cpp_deps
+            .into_iter()
+            .find_map(Result::err)
+            .ok_or("expected error")?;
+        let duplicate = err.duplicate().ok_or("missing duplicate")?;
+        assert_eq!(duplicate.name(), "bar");
+        let mut units = [duplicate.first(), duplicate.second()].map(|unit| unit.map(ToString::to_string));
+        units.sort();
+        assert_eq!(units, [Some("bar.o".into()), Some("baz/bar.o".into())]);
+        let message = err.to_string();
+        assert!(message.starts_with("error: a module has more than one provider\n"));
+        assert_eq!(message.matches(&duplicate.to_string()).count(), 1);
+        Ok(())
+    }
+
+    #[test]
+    fn analyze_duplicate_keep_going() -> BoxResult<()> {
+        let paths =
+            crate::testing::corpus::dep_text::items().chain([crate::testing::corpus::dep_text::bar_duplicate()]);
+        let mut validate = crate::testing::corpus::dep_text::validate_order(paths)?;
+        validate.cpp_deps.keep_going(true);
+        let mut outputs = 0;
+        let mut errors = vec![];
+        for result in validate.cpp_deps.into_iter() {
+            match result {
+                Ok(_) => outputs += 1,
+                Err(err) => errors.push(err),
+            }
+        }
+        assert_eq!(outputs, 5);
+        assert_eq!(errors.len(), 2);
+        assert_eq!(errors[0].duplicate().ok_or("missing duplicate")?.name(), "bar");
+        let summary = errors[1].summary().ok_or("missing summary")?;
+        assert_eq!(summary.succeeded().len(), 5);
+        assert_eq!(summary.failed().len(), 1);
+        Ok(())
+    }
+
+    #[test]
+    fn analyze_duplicate_unique_on_source_path() -> BoxResult<()> {
+        let item = |dir: &'static str, source_path: &str| {
+            let dep_text = format!(
+                r#"{{
+                "rules": [
+                    {{
+                        "primary-output": "{dir}/foo.o",
+                        "provides": [
+                            {{
+                                "logical-name": "foo",
+                                "source-path": "{source_path}",
+                                "unique-on-source-path": true,
+                                "is-interface": true
+                            }}
+                        ],
+                        "requires": []
+                    }}
+                ],
+                "version": 0,
+                "revision": 0
+            }}"#
+            );
+            crate::CppDepsItem::DepText {
+                src_file: None,
+                dep_path: p1689::r5::Utf8PathBuf::from(format!("{dir}/foo.ddi")),
+                dep_text: dep_text.into_bytes(),
+            }
+        };
+        let items = [item("lhs", "lhs/foo.cppm"), item("rhs", "rhs/foo.cppm")];
+        let validate = crate::testing::ValidateOrder::new("", items, BTreeSet::new())?;
+        let outputs = validate.cpp_deps.into_iter().collect::<Result<Vec<_>, _>>()?;
+        assert_eq!(outputs.len(), 2);
+        let items = [item("lhs", "foo.cppm"), item("rhs", "foo.cppm")];
+        let validate = crate::testing::ValidateOrder::new("", items, BTreeSet::new())?;
+        let err = validate
+            .cpp_deps
+            .into_iter()
+            .find_map(Result::err)
+            .ok_or("expected error")?;
+        assert_eq!(err.duplicate().ok_or("missing duplicate")?.name(), "foo");
+        Ok(())
+    }
+
+    #[test]
+    fn analyze_inputs() -> BoxResult<()> {
+        let tmp_dir = tempdir::TempDir::new("cpp-deps")?;
+        let tmp_dir = p1689::r5::Utf8Path::from_path(tmp_dir.path()).ok_or("non-UTF-8 temp dir")?;
+        let mut dep_paths = Vec::new();
+        for item in crate::testing::corpus::dep_text::items() {
+            let crate::CppDepsItem::DepText { dep_path, dep_text, .. } = item else {
+                return Err("expected dep text".into());
+            };
+            let dep_path = tmp_dir.join(dep_path.as_ref());
+            std::fs::create_dir_all(dep_path.parent().ok_or("missing parent")?)?;
+            std::fs::write(&dep_path, dep_text)?;
+            dep_paths.push(dep_path);
+        }
+        let items = dep_paths.iter().cloned().map(|dep_path| crate::CppDepsItem::DepFile {
+            src_file: None,
+            dep_path,
+        });
+        let validate = crate::testing::corpus::dep_text::validate_order::<_, Vec<u8>, _>(items)?;
+        let mut analyzer = validate.cpp_deps.into_iter();
+        for result in analyzer.by_ref() {
+            result?;
+        }
+        assert!(analyzer.inputs().iter().eq(dep_paths.iter().collect::<BTreeSet<_>>()));
+        let lines = analyzer.rerun_if_changed_lines();
+        for dep_path in &dep_paths {
+            assert!(lines.contains(&format!("cargo:rerun-if-changed={dep_path}")));
+        }
+        #[cfg(feature = "cc")]
+        for var in [
+            "OUT_DIR",
+            "TARGET",
+            "HOST",
+            "CARGO_MAKEFLAGS",
+            "MAKEFLAGS",
+            "MFLAGS",
+            "CXX_x86_64-unknown-linux-gnu",
+            "CXX_x86_64_unknown_linux_gnu",
+            "HOST_CXX",
+            "CXX",
+            "CXXFLAGS_x86_64-unknown-linux-gnu",
+            "CXXFLAGS_x86_64_unknown_linux_gnu",
+            "HOST_CXXFLAGS",
+            "CXXFLAGS",
+        ] {
+            assert!(lines.contains(&format!("cargo:rerun-if-env-changed={var}")));
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn analyze_duplicate_allowed() -> BoxResult<()> {
+        let paths =
+            crate::testing::corpus::dep_text::items().chain([crate::testing::corpus::dep_text::bar_duplicate()]);
+        let mut validate = crate::testing::corpus::dep_text::validate_order(paths)?;
+        validate.cpp_deps.allow_duplicate_providers(true);
+        let count = validate
+            .cpp_deps
+            .into_iter()
+            .try_fold(0, |count, result| result.map(|_| count + 1))?;
+        assert_eq!(count, 6);
+        Ok(())
+    }
+
+    // A failed compilation leaves nothing pending, so the analysis still finishes with the summary.
+    #[cfg(feature = "cc")]
+    #[test]
+    fn compile_keep_going() -> BoxResult<()> {
+        #[derive(Default)]
+        struct FailingExecutor(crate::testing::RecordingExecutor);
+        impl crate::CppDepsExecutor for FailingExecutor {
+            fn execute(&self, command: &crate::CppDepsCommand) -> std::io::Result<crate::CppDepsExecution> {
+                let args = command.args().filter_map(|arg| arg.to_str()).collect::<Vec<_>>();
+                // NOTE: only the compilation writes an object with `-o`
+                if args.contains(&"-o") && args.iter().any(|arg| arg.ends_with("part1.cppm")) {
+                    return Ok(crate::CppDepsExecution::new(Some(1), vec![], b"error\n".to_vec()));
+                }
+                self.0.execute(command)
+            }
+        }
+        let paths = crate::testing::corpus::src_file::items()?;
+        let mut validate = crate::testing::corpus::src_file::validate_order(paths)?;
+        validate.cpp_deps.executor(FailingExecutor::default());
+        validate.cpp_deps.keep_going(true);
+        let results = validate.cpp_deps.into_iter().collect::<Vec<_>>();
+        let summary = results
+            .iter()
+            .find_map(|result| result.as_ref().err()?.summary())
+            .ok_or("missing summary")?;
+        assert_eq!(summary.succeeded().len(), 2);
+        assert_eq!(summary.failed().len(), 1);
+        assert_eq!(summary.skipped().len(), 2);
+        Ok(())
+    }
+
+    #[test]
+    fn analyze_keep_going() -> BoxResult<()> {
+        let paths = [
+            crate::testing::corpus::dep_text::bar(),
+            crate::testing::corpus::dep_text::foo_part1_invalid(),
+            crate::testing::corpus::dep_text::foo_part2(),
+            crate::testing::corpus::dep_text::foo(),
+            crate::testing::corpus::dep_text::main(),
+        ];
+        let mut validate = crate::testing::corpus::dep_text::validate_order(paths)?;
+        validate.cpp_deps.keep_going(true);
+        let mut outputs = vec![];
+        let mut errors = vec![];
+        for result in validate.cpp_deps.into_iter() {
+            match result {
+                Ok(output) => outputs.extend(
+                    output
+                        .dep_info()
+                        .get()
+                        .primary_output
+                        .as_deref()
+                        .map(ToString::to_string),
+                ),
+                Err(err) => errors.push(err),
+            }
+        }
+        outputs.sort();
+        assert_eq!(outputs, ["bar.o", "foo/part2.o"]);
+        assert_eq!(errors.len(), 2);
+        let summary = errors[1].summary().ok_or("missing summary")?;
+        assert_eq!(summary.succeeded().len(), 2);
+        assert!(summary.failed().is_empty());
+        assert_eq!(summary.failed_scans(), ["foo/part1.ddi"]);
+        let mut skipped = summary
+            .skipped()
+            .iter()
+            .filter_map(|dep_info| dep_info.get().primary_output.as_deref().map(ToString::to_string))
+            .collect::<Vec<_>>();
+        skipped.sort();
+        assert_eq!(skipped, ["foo.o", "main.o"]);
+        Ok(())
+    }
+
+    #[test]
+    #[should_panic]
+    fn analyze_keep_going_disabled() {
+        fn inner() -> BoxResult<()> {
+            let paths = [
+                crate::testing::corpus::dep_text::bar(),
+                crate::testing::corpus::dep_text::foo_part1_invalid(),
+                crate::testing::corpus::dep_text::foo_part2(),
+                crate::testing::corpus::dep_text::foo(),
+                crate::testing::corpus::dep_text::main(),
+            ];
+            let validate = crate::testing::corpus::dep_text::validate_order(paths)?;
+            validate.run()
+        }
+        inner().unwrap()
+    }
+
     #[test]
     #[should_panic]
     fn analyze_cycle() {
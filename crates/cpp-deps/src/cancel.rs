@@ -0,0 +1,141 @@
+use alloc::sync::Arc;
+use core::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Mutex, PoisonError};
+
+/// A handle to cancel an analysis from another thread, obtained from [`CppDeps::cancel_handle`](crate::CppDeps::cancel_handle).
+///
+/// Cancelling stops the workers from taking up new items, kills the commands still running, when their executor
+/// supports it, and makes the [`CppDepsAnalyzer`](crate::CppDepsAnalyzer) return an error for which
+/// [`Error::is_cancelled`](crate::Error::is_cancelled) holds. Cancelling again, or after the analysis ended, does
+/// nothing.
+#[derive(Clone)]
+pub struct CppDepsCancel {
+    inner: Arc<CancelInner>,
+}
+
+struct CancelInner {
+    cancelled: AtomicBool,
+    // NOTE: never sent on, dropping it disconnects the receivers to wake the analyzer
+    wake_tx: Mutex<Option<flume::Sender<()>>>,
+    wake_rx: flume::Receiver<()>,
+}
+
+impl Default for CppDepsCancel {
+    fn default() -> Self {
+        let (wake_tx, wake_rx) = flume::bounded(0);
+        let inner = Arc::new(CancelInner {
+            cancelled: AtomicBool::new(false),
+            wake_tx: Mutex::new(Some(wake_tx)),
+            wake_rx,
+        });
+        Self { inner }
+    }
+}
+
+impl CppDepsCancel {
+    pub fn cancel(&self) {
+        self.inner.cancelled.store(true, Ordering::SeqCst);
+        let wake_tx = self.inner.wake_tx.lock().unwrap_or_else(PoisonError::into_inner).take();
+        drop(wake_tx);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.inner.cancelled.load(Ordering::SeqCst)
+    }
+
+    /// A receiver which disconnects once the analysis is cancelled.
+    pub(crate) fn wake_rx(&self) -> &flume::Receiver<()> {
+        &self.inner.wake_rx
+    }
+}
+
+impl core::fmt::Debug for CppDepsCancel {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.debug_struct("CppDepsCancel")
+            .field("cancelled", &self.is_cancelled())
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    #[cfg(feature = "cc")]
+    use core::sync::atomic::{AtomicUsize, Ordering};
+
+    use crate::testing::BoxResult;
+
+    #[test]
+    fn cancel_analyze() -> BoxResult<()> {
+        let paths = crate::testing::corpus::dep_text::items();
+        let validate = crate::testing::corpus::dep_text::validate_order(paths)?;
+        let cancel = validate.cpp_deps.cancel_handle();
+        cancel.cancel();
+        let results = validate.cpp_deps.into_iter().collect::<Vec<_>>();
+        assert_eq!(results.len(), 1);
+        assert!(results[0].as_ref().is_err_and(crate::Error::is_cancelled));
+        Ok(())
+    }
+
+    // Cancelling wakes an analyzer waiting on items which never come.
+    #[cfg(feature = "sink")]
+    #[test]
+    fn cancel_waiting() -> BoxResult<()> {
+        #[allow(unused_mut)]
+        let mut cpp_deps = crate::CppDeps::<p1689::r5::Utf8PathBuf, Vec<u8>>::new()?;
+        #[cfg(feature = "cc")]
+        let out_dir = tempdir::TempDir::new("cpp-deps")?;
+        #[cfg(feature = "cc")]
+        crate::testing::configure(&mut cpp_deps, out_dir.path())?;
+        let sink = cpp_deps.sink();
+        let cancel = cpp_deps.cancel_handle();
+        let canceller = std::thread::spawn({
+            let cancel = cancel.clone();
+            move || {
+                std::thread::sleep(core::time::Duration::from_millis(20));
+                cancel.cancel();
+            }
+        });
+        let mut analyzer = cpp_deps.into_iter();
+        let err = Iterator::next(&mut analyzer)
+            .and_then(Result::err)
+            .ok_or("expected a cancellation")?;
+        assert!(err.is_cancelled());
+        assert!(Iterator::next(&mut analyzer).is_none());
+        canceller.join().map_err(|_| "canceller panicked")?;
+        drop(analyzer);
+        drop(sink);
+        Ok(())
+    }
+
+    #[cfg(feature = "cc")]
+    #[test]
+    fn cancel_compile() -> BoxResult<()> {
+        let paths = crate::testing::corpus::src_file::items()?;
+        let mut validate = crate::testing::corpus::src_file::validate_order(paths)?;
+        validate.cpp_deps.executor(crate::testing::RecordingExecutor::default());
+        validate.cpp_deps.parallelism(1)?;
+        let cancel = validate.cpp_deps.cancel_handle();
+        let compiles = alloc::sync::Arc::new(AtomicUsize::new(0));
+        // NOTE: cancel as soon as the first compilation finishes
+        validate.cpp_deps.observer({
+            let cancel = cancel.clone();
+            let compiles = compiles.clone();
+            move |event| match event {
+                crate::CppDepsEvent::CompileStarted { .. } => {
+                    compiles.fetch_add(1, Ordering::SeqCst);
+                },
+                crate::CppDepsEvent::CompileFinished { .. } => cancel.cancel(),
+                _ => {},
+            }
+        });
+        let results = validate.cpp_deps.into_iter().collect::<Vec<_>>();
+        let err = results
+            .iter()
+            .find_map(|result| result.as_ref().err())
+            .ok_or("expected a cancellation")?;
+        assert!(err.is_cancelled());
+        assert!(results.last().is_some_and(Result::is_err));
+        assert_eq!(compiles.load(Ordering::SeqCst), 1);
+        Ok(())
+    }
+}
@@ -0,0 +1,163 @@
+use alloc::sync::Arc;
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+
+use p1689::r5::{
+    self,
+    yoke::{DepFileCart, DepFileYokeExt, DepInfoYoke, DepInfoYokeExt},
+};
+use yoke::Yoke;
+
+use crate::{
+    analyzer::{CppDepsBlocked, CppDepsCycle, CppDepsDuplicate, CppDepsMissing},
+    compiler::CompilerFamily,
+    InnerError,
+    InnerErrorKind,
+};
+
+/// A scanned unit taking part in a collation, identified by the object file it compiles to.
+pub(crate) struct CollateUnit {
+    pub(crate) obj_path: r5::Utf8PathBuf,
+    pub(crate) rules: Vec<DepInfoYoke>,
+}
+impl CollateUnit {
+    /// Read and parse the rules of the P1689 file at `dep_path`.
+    pub(crate) fn load(dep_path: &r5::Utf8Path, obj_path: r5::Utf8PathBuf) -> Result<Self, InnerError> {
+        let dep_text = std::fs::read(dep_path).map_err(|err| InnerError::new(InnerErrorKind::FileRead { err }))?;
+        let cart = Arc::new(dep_text) as DepFileCart;
+        let dep_file =
+            Yoke::<&'static _, DepFileCart>::attach_to_cart(cart, |cart| cart).try_map_project(|dep_text, _| {
+                let state = r5::parsers::State::default();
+                let mut stream = r5::parsers::ParseStream::new(dep_path, dep_text.as_ref(), state);
+                r5::parsers::dep_file(&mut stream).map_err(|_| InnerError::new(InnerErrorKind::DepFileParse))
+            })?;
+        let rules = dep_file.rules().collect();
+        Ok(Self { obj_path, rules })
+    }
+
+    pub(crate) fn is_interface(&self) -> bool {
+        self.rules
+            .iter()
+            .any(|rule| rule.get().provides.iter().any(|provided| provided.is_interface))
+    }
+}
+
+/// The modules each unit provides and the modules it imports transitively, with the index of the providing unit.
+pub(crate) struct Collated {
+    pub(crate) provides: Vec<Vec<(String, r5::Utf8PathBuf)>>,
+    pub(crate) imports: Vec<BTreeMap<String, (usize, r5::Utf8PathBuf)>>,
+}
+
+// Resolves the transitive imports of every unit, recording missing providers and import cycles along the way.
+struct Collation<'a> {
+    units: &'a [CollateUnit],
+    providers: HashMap<String, (usize, r5::Utf8PathBuf)>,
+    imports: Vec<Option<BTreeMap<String, (usize, r5::Utf8PathBuf)>>>,
+    path: Vec<(usize, Option<String>)>,
+    missing: BTreeMap<String, Vec<r5::Utf8PathBuf>>,
+    cycles: BTreeSet<Vec<String>>,
+}
+impl Collation<'_> {
+    fn visit(&mut self, unit: usize, via: Option<String>) -> BTreeMap<String, (usize, r5::Utf8PathBuf)> {
+        if let Some(imports) = &self.imports[unit] {
+            return imports.clone();
+        }
+        self.path.push((unit, via));
+        let mut imports = BTreeMap::new();
+        let requires = self.units[unit].rules.iter().flat_map(|rule| rule.requires());
+        for name in requires.map(|name| name.yoke.get().to_string()).collect::<Vec<_>>() {
+            let Some(provided) = self.providers.get(&name).cloned() else {
+                let required_by = self.units[unit].obj_path.clone();
+                self.missing.entry(name).or_default().push(required_by);
+                continue;
+            };
+            if let Some(start) = self.path.iter().position(|(visiting, _)| *visiting == provided.0) {
+                let mut names = self.path[start + 1 ..]
+                    .iter()
+                    .filter_map(|(_, via)| via.clone())
+                    .collect::<Vec<_>>();
+                names.push(name);
+                let min = names
+                    .iter()
+                    .enumerate()
+                    .min_by_key(|(_, name)| *name)
+                    .map_or(0, |(i, _)| i);
+                names.rotate_left(min);
+                names.push(names[0].clone());
+                self.cycles.insert(names);
+                continue;
+            }
+            imports.extend(self.visit(provided.0, Some(name.clone())));
+            imports.insert(name, provided);
+        }
+        self.path.pop();
+        self.imports[unit] = Some(imports.clone());
+        imports
+    }
+}
+
+/// Resolve the providers of the modules imported by the units.
+///
+/// Duplicate providers, missing providers and import cycles are reported like they are by the
+/// [`CppDepsAnalyzer`](crate::CppDepsAnalyzer).
+pub(crate) fn collate(
+    family: CompilerFamily,
+    bmi_dir: Option<&r5::Utf8Path>,
+    units: &[CollateUnit],
+) -> Result<Collated, InnerError> {
+    let mut providers = HashMap::<String, (usize, r5::Utf8PathBuf)>::new();
+    let mut provides = vec![vec![]; units.len()];
+    for (i, unit) in units.iter().enumerate() {
+        for rule in &unit.rules {
+            for (name, provided) in rule.provides().zip(rule.get().provides.iter()) {
+                let name = name.yoke.get().to_string();
+                let bmi_path = match provided.desc.view().compiled_module_path {
+                    Some(path) => path.to_owned(),
+                    None => family.bmi_file_dst(&unit.obj_path, &name, bmi_dir),
+                };
+                if let Some((first, _)) = providers.get(&name) {
+                    let duplicate = Box::new(CppDepsDuplicate {
+                        name,
+                        first: Some(units[*first].obj_path.clone()),
+                        second: Some(unit.obj_path.clone()),
+                    });
+                    return Err(InnerError::new(InnerErrorKind::DuplicateModuleProvider { duplicate }));
+                }
+                provides[i].push((name.clone(), bmi_path.clone()));
+                providers.insert(name, (i, bmi_path));
+            }
+        }
+    }
+
+    let mut collation = Collation {
+        units,
+        providers,
+        imports: vec![None; units.len()],
+        path: vec![],
+        missing: BTreeMap::new(),
+        cycles: BTreeSet::new(),
+    };
+    let imports = (0 .. units.len()).map(|i| collation.visit(i, None)).collect::<Vec<_>>();
+    if !collation.missing.is_empty() || !collation.cycles.is_empty() {
+        let missing = collation.missing.into_iter();
+        let blocked = Box::new(CppDepsBlocked {
+            missing: missing
+                .map(|(name, required_by)| CppDepsMissing { name, required_by })
+                .collect(),
+            cycles: collation
+                .cycles
+                .into_iter()
+                .map(|names| CppDepsCycle { names })
+                .collect(),
+        });
+        return Err(InnerError::new(InnerErrorKind::OrderingSolutionBlocked { blocked }));
+    }
+    Ok(Collated { provides, imports })
+}
+
+// NOTE: leave unchanged files alone so that the build tool doesn't rerun what depends on them
+pub(crate) fn write_if_changed(path: &r5::Utf8Path, text: &str) -> Result<(), InnerError> {
+    if std::fs::read(path).is_ok_and(|bytes| bytes == text.as_bytes()) {
+        return Ok(());
+    }
+    std::fs::write(path, text).map_err(|err| InnerError::new(InnerErrorKind::FileWrite { err }))
+}
@@ -0,0 +1,380 @@
+use std::collections::HashSet;
+
+use p1689::r5;
+use serde_json::Value;
+
+use crate::{collate::write_if_changed, CppDepsCommand, CppDepsOutput, CppDepsSrc, InnerError, InnerErrorKind};
+
+pub(crate) const COMPILE_COMMANDS_FILE_NAME: &str = "compile_commands.json";
+
+// NOTE: C sources and other languages in the database are skipped since they can't import modules
+const CXX_EXTENSIONS: &[&str] = &["c++", "c++m", "cc", "ccm", "cpp", "cppm", "cxx", "cxxm", "ixx", "mpp"];
+
+// Arguments which are replaced by cpp-deps for each step, and whether they take a value, either separate (`-o foo.o`)
+// or joined (`-ofoo.o`).
+const REPLACED_ARGS: &[(&str, bool)] = &[
+    ("-c", false),
+    ("-o", true),
+    ("-MD", false),
+    ("-MMD", false),
+    ("-MF", true),
+    ("-MT", true),
+    ("-MQ", true),
+    ("-fmodules-ts", false),
+    ("-fmodule-output", false),
+];
+const REPLACED_ARG_PREFIXES: &[&str] = &[
+    "-fdeps-",
+    "-fmodule-file=",
+    "-fmodule-mapper=",
+    "-fmodule-output=",
+    "-fprebuilt-module-path=",
+];
+
+/// Read the C++ sources under `src_base` from a `compile_commands.json` compilation database, each with the command of
+/// its entry.
+///
+/// Relative files are resolved against the directory of their entry. Only the first entry for a file is used.
+pub(crate) fn load(
+    path: &r5::Utf8Path,
+    src_base: &r5::Utf8Path,
+) -> Result<Vec<CppDepsSrc<r5::Utf8PathBuf>>, InnerError> {
+    let text = std::fs::read_to_string(path).map_err(|err| InnerError::new(InnerErrorKind::FileRead { err }))?;
+    let value = serde_json::from_str::<Value>(&text)
+        .map_err(|err| InnerError::new(InnerErrorKind::CompileCommandsJson { err }))?;
+    let entries = value
+        .as_array()
+        .ok_or_else(|| InnerError::new(InnerErrorKind::CompileCommandsInvalidEntry))?;
+
+    let mut seen = HashSet::new();
+    let mut src_files = vec![];
+    for entry in entries {
+        let Some((src_path, command)) = self::entry(entry) else {
+            return Err(InnerError::new(InnerErrorKind::CompileCommandsInvalidEntry));
+        };
+        let is_cxx = src_path.extension().is_some_and(|ext| CXX_EXTENSIONS.contains(&ext));
+        if !is_cxx || !src_path.starts_with(src_base) || !seen.insert(src_path.clone()) {
+            continue;
+        }
+        let src_file = CppDepsSrc::new(src_base.to_path_buf(), src_path).with_command(command);
+        src_files.push(src_file);
+    }
+    Ok(src_files)
+}
+
+fn entry(entry: &Value) -> Option<(r5::Utf8PathBuf, CppDepsCommand)> {
+    let directory = r5::Utf8Path::new(entry.get("directory")?.as_str()?);
+    let file = entry.get("file")?.as_str()?;
+    let args = match (entry.get("arguments"), entry.get("command")) {
+        (Some(arguments), _) => arguments
+            .as_array()?
+            .iter()
+            .map(|arg| arg.as_str().map(ToOwned::to_owned))
+            .collect::<Option<Vec<_>>>()?,
+        (None, Some(command)) => split_command(command.as_str()?)?,
+        (None, None) => return None,
+    };
+    let (program, args) = args.split_first()?;
+    let src_path = directory.join(file);
+    let command = CppDepsCommand {
+        program: program.into(),
+        args: unit_args(args, directory, &src_path)
+            .into_iter()
+            .map(Into::into)
+            .collect(),
+        envs: vec![],
+        current_dir: Some(directory.as_std_path().to_path_buf()),
+    };
+    Some((src_path, command))
+}
+
+// The arguments of an entry without the source, the outputs, the dependency files and the module flags.
+fn unit_args(args: &[String], directory: &r5::Utf8Path, src_path: &r5::Utf8Path) -> Vec<String> {
+    let mut unit_args = vec![];
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        if directory.join(arg) == src_path {
+            continue;
+        }
+        if let Some((_, takes_value)) = REPLACED_ARGS.iter().find(|(name, _)| name == arg) {
+            if *takes_value {
+                args.next();
+            }
+            continue;
+        }
+        if REPLACED_ARGS
+            .iter()
+            .any(|(name, takes_value)| *takes_value && arg.starts_with(name))
+        {
+            continue;
+        }
+        if REPLACED_ARG_PREFIXES.iter().any(|prefix| arg.starts_with(prefix)) {
+            continue;
+        }
+        unit_args.push(arg.clone());
+    }
+    unit_args
+}
+
+// Split a `command` the way a POSIX shell would, without expansions. Returns `None` for unbalanced quotes.
+fn split_command(command: &str) -> Option<Vec<String>> {
+    let mut args = vec![];
+    let mut arg = None::<String>;
+    let mut chars = command.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '\'' => {
+                let arg = arg.get_or_insert_with(String::new);
+                loop {
+                    match chars.next()? {
+                        '\'' => break,
+                        c => arg.push(c),
+                    }
+                }
+            },
+            '"' => {
+                let arg = arg.get_or_insert_with(String::new);
+                loop {
+                    match chars.next()? {
+                        '"' => break,
+                        '\\' => {
+                            let c = chars.next()?;
+                            if !matches!(c, '"' | '\\' | '$' | '`') {
+                                arg.push('\\');
+                            }
+                            arg.push(c);
+                        },
+                        c => arg.push(c),
+                    }
+                }
+            },
+            '\\' => {
+                if let Some(c) = chars.next() {
+                    arg.get_or_insert_with(String::new).push(c);
+                }
+            },
+            c if c.is_whitespace() => args.extend(arg.take()),
+            c => arg.get_or_insert_with(String::new).push(c),
+        }
+    }
+    args.extend(arg);
+    Some(args)
+}
+
+/// The compilation database written by the analyzer, collecting the command of every compiled unit.
+pub(crate) struct CompileCommands {
+    path: r5::Utf8PathBuf,
+    entries: Vec<Value>,
+}
+impl CompileCommands {
+    pub(crate) fn new(path: r5::Utf8PathBuf) -> Self {
+        Self { path, entries: vec![] }
+    }
+
+    pub(crate) fn push(&mut self, output: &CppDepsOutput) -> Result<(), InnerError> {
+        let (Some(src_path), Some(obj_path), Some(command)) = (output.src_path(), output.obj_path(), output.command())
+        else {
+            return Ok(());
+        };
+        // NOTE: the environment can't be expressed in a compilation database, which clangd doesn't need anyway
+        let directory = match command.current_dir() {
+            Some(dir) => dir.to_path_buf(),
+            None => std::env::current_dir().map_err(|err| InnerError::new(InnerErrorKind::EnvCurrentDir { err }))?,
+        };
+        let arguments = core::iter::once(command.program())
+            .chain(command.args())
+            .map(|arg| Value::from(arg.to_string_lossy()))
+            .collect::<Vec<_>>();
+        self.entries.push(serde_json::json!({
+            "directory": directory.to_string_lossy(),
+            "file": src_path.as_str(),
+            "arguments": arguments,
+            "output": obj_path.as_str(),
+        }));
+        Ok(())
+    }
+
+    /// Write the entries sorted by file, leaving the database alone when it's unchanged.
+    pub(crate) fn write(&mut self) -> Result<(), InnerError> {
+        self.entries
+            .sort_by(|lhs, rhs| lhs["file"].as_str().cmp(&rhs["file"].as_str()));
+        let text = serde_json::to_string_pretty(&self.entries)
+            .map_err(|err| InnerError::new(InnerErrorKind::CompileCommandsJson { err }))?;
+        write_if_changed(&self.path, &text)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use p1689::r5;
+
+    use crate::{testing::BoxResult, CppDepsItem};
+
+    #[test]
+    fn split_command() {
+        let args = super::split_command(r#"clang++ -DNAME='"a b"' "-I dir" a\ b.cpp "" -D\$X "\q""#);
+        assert_eq!(
+            args,
+            Some(
+                ["clang++", r#"-DNAME="a b""#, "-I dir", "a b.cpp", "", "-D$X", r"\q"]
+                    .map(String::from)
+                    .to_vec()
+            )
+        );
+        assert_eq!(super::split_command("clang++ 'a"), None);
+    }
+
+    #[test]
+    fn load() -> BoxResult<()> {
+        let tmp_dir = tempdir::TempDir::new("cpp-deps")?;
+        let dir = r5::Utf8Path::from_path(tmp_dir.path()).ok_or("non-UTF-8 temp dir")?;
+        let src_base = dir.join("src");
+        let database = serde_json::json!([
+            {
+                "directory": dir.join("build").as_str(),
+                "file": src_base.join("foo.cppm").as_str(),
+                "arguments": [
+                    "clang++", "-std=c++23", "-Iinclude", "-fmodule-output=foo.pcm", "-MD", "-MF", "foo.d",
+                    "-o", "foo.o", "-c", src_base.join("foo.cppm").as_str(),
+                ],
+            },
+            {
+                "directory": src_base.as_str(),
+                "file": "main.cpp",
+                "command": "g++ -std=c++20 -DGREETING='\"hello world\"' -c main.cpp -ofoo/main.o",
+            },
+            { "directory": src_base.as_str(), "file": "foo.cppm", "command": "clang++ -std=c++26 -c foo.cppm" },
+            { "directory": src_base.as_str(), "file": "util.c", "command": "cc -c util.c" },
+            { "directory": dir.as_str(), "file": "other/bar.cppm", "command": "clang++ -c other/bar.cppm" },
+        ]);
+        let path = dir.join("compile_commands.json");
+        std::fs::write(&path, database.to_string())?;
+
+        let src_files = super::load(&path, &src_base)?;
+        assert_eq!(src_files.len(), 2);
+        let args = |src_file: &crate::CppDepsSrc<r5::Utf8PathBuf>| -> BoxResult<Vec<String>> {
+            let command = src_file.command().ok_or("missing command")?;
+            Ok(command.args().map(|arg| arg.to_string_lossy().into_owned()).collect())
+        };
+
+        assert_eq!(src_files[0].src_path, src_base.join("foo.cppm"));
+        let command = src_files[0].command().ok_or("missing command")?;
+        assert_eq!(command.program(), "clang++");
+        assert_eq!(command.current_dir(), Some(dir.join("build").as_std_path()));
+        assert_eq!(args(&src_files[0])?, ["-std=c++23", "-Iinclude"]);
+
+        assert_eq!(src_files[1].src_path, src_base.join("main.cpp"));
+        assert_eq!(args(&src_files[1])?, ["-std=c++20", r#"-DGREETING="hello world""#]);
+        Ok(())
+    }
+
+    #[test]
+    fn load_invalid() -> BoxResult<()> {
+        let tmp_dir = tempdir::TempDir::new("cpp-deps")?;
+        let dir = r5::Utf8Path::from_path(tmp_dir.path()).ok_or("non-UTF-8 temp dir")?;
+        let path = dir.join("compile_commands.json");
+        std::fs::write(&path, r#"[{ "directory": "/", "file": "main.cpp" }]"#)?;
+        assert!(super::load(&path, dir).is_err());
+        std::fs::write(&path, "{")?;
+        assert!(super::load(&path, dir).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn ninja() -> BoxResult<()> {
+        let metadata = cargo_metadata::MetadataCommand::new().exec()?;
+        let src_base = r5::Utf8Path::new(metadata.workspace_root.as_str()).join("examples");
+        let src_dir = src_base.join("gnu-make");
+        let entries = ["bar.cppm", "foo.cppm", "foo/part1.cppm", "foo/part2.cppm", "main.cpp"].map(|file| {
+            serde_json::json!({
+                "directory": src_dir.as_str(),
+                "file": file,
+                "arguments": ["clang++", "-std=gnu++23", "-DFROM_DATABASE", "-c", file],
+            })
+        });
+        let validate = crate::testing::corpus::src_file::validate_order(core::iter::empty::<
+            CppDepsItem<r5::Utf8PathBuf, Vec<u8>>,
+        >())?;
+        let out_dir = r5::Utf8Path::from_path(validate.out_dir.path()).ok_or("non-UTF-8 temp dir")?;
+        let path = out_dir.join("compile_commands.json");
+        std::fs::write(&path, serde_json::Value::from(entries.to_vec()).to_string())?;
+
+        let mut cpp_deps = validate.cpp_deps;
+        cpp_deps.compile_commands(&path, &src_base)?;
+        let ninja = cpp_deps.ninja(&std::process::Command::new("cpp-deps-cli"))?;
+        assert_eq!(ninja.obj_paths().len(), 5);
+        let text = std::fs::read_to_string(ninja.path())?;
+        let scans = text.lines().filter(|line| line.contains(" -DFROM_DATABASE "));
+        assert_eq!(scans.count(), 10);
+        assert!(text.contains(&format!("cd {src_dir} && ")));
+        Ok(())
+    }
+
+    #[test]
+    fn write() -> BoxResult<()> {
+        let tmp_dir = tempdir::TempDir::new("cpp-deps")?;
+        let dir = r5::Utf8Path::from_path(tmp_dir.path()).ok_or("non-UTF-8 temp dir")?;
+        let Some(CppDepsItem::DepText { dep_text, .. }) = crate::testing::corpus::dep_text::items().next() else {
+            return Err("expected dep text".into());
+        };
+        let dep_path = dir.join("main.ddi");
+        std::fs::write(&dep_path, dep_text)?;
+        let unit = crate::collate::CollateUnit::load(&dep_path, dir.join("main.o"))?;
+        let dep_info = unit.rules.into_iter().next().ok_or("missing rule")?;
+
+        let mut cmd = std::process::Command::new("clang++");
+        cmd.args(["-fmodule-file=foo=/out/foo.pcm", "-c", "main.cpp", "-o", "/out/main.o"]);
+        cmd.current_dir(dir.as_std_path());
+        let output = crate::CppDepsOutput {
+            dep_info,
+            src_path: Some(dir.join("main.cpp")),
+            obj_path: Some(r5::Utf8PathBuf::from("/out/main.o")),
+            bmi_paths: vec![],
+            command: Some(crate::CppDepsCommand::from(&cmd)),
+            execution: None,
+            scan_execution: None,
+        };
+
+        let path = dir.join(super::COMPILE_COMMANDS_FILE_NAME);
+        let mut compile_commands = super::CompileCommands::new(path.clone());
+        compile_commands.push(&output)?;
+        compile_commands.write()?;
+        let database = serde_json::from_str::<serde_json::Value>(&std::fs::read_to_string(&path)?)?;
+        assert_eq!(
+            database,
+            serde_json::json!([{
+                "directory": dir.as_str(),
+                "file": dir.join("main.cpp").as_str(),
+                "arguments": ["clang++", "-fmodule-file=foo=/out/foo.pcm", "-c", "main.cpp", "-o", "/out/main.o"],
+                "output": "/out/main.o",
+            }])
+        );
+
+        // Loading the database back yields the same unit, without the flags cpp-deps adds itself.
+        let src_files = super::load(&path, dir)?;
+        assert_eq!(src_files.len(), 1);
+        let command = src_files[0].command().ok_or("missing command")?;
+        assert_eq!(command.args().count(), 0);
+        Ok(())
+    }
+
+    #[test]
+    fn compile_write() -> BoxResult<()> {
+        let paths = crate::testing::corpus::src_file::items()?;
+        let mut validate = crate::testing::corpus::src_file::validate_order(paths)?;
+        validate.cpp_deps.write_compile_commands(true);
+        let outputs = validate.cpp_deps.into_iter().collect::<Result<Vec<_>, _>>()?;
+        let path = validate.out_dir.path().join(super::COMPILE_COMMANDS_FILE_NAME);
+        let database = serde_json::from_str::<serde_json::Value>(&std::fs::read_to_string(path)?)?;
+        let entries = database.as_array().ok_or("expected an array")?;
+        assert_eq!(entries.len(), outputs.len());
+        let main = entries
+            .iter()
+            .find(|entry| entry["file"].as_str().is_some_and(|file| file.ends_with("main.cpp")))
+            .ok_or("missing main.cpp")?;
+        let output = main["output"].as_str().ok_or("expected output")?;
+        let arguments = main["arguments"].as_array().ok_or("expected arguments")?;
+        assert!(arguments.iter().any(|arg| arg.as_str() == Some(output)));
+        Ok(())
+    }
+}
@@ -1,134 +1,494 @@
 use alloc::sync::Arc;
+use core::time::Duration;
 use std::{collections::BTreeSet, process::Command};
 
-use p1689::r5::{self, yoke::DepInfoNameYoke};
+use p1689::r5::{
+    self,
+    yoke::{DepInfoNameYoke, DepInfoYoke, DepInfoYokeExt},
+};
 
+#[cfg(feature = "jobserver")]
+use crate::jobs::Jobserver;
 #[cfg(feature = "memchr")]
 use crate::Finders;
-use crate::{InnerError, InnerErrorKind};
+use crate::{
+    executor::{CppDepsExecutor, CppDepsJob},
+    layout::CppDepsLayout,
+    output::{CppDepsCommand, CppDepsExecution},
+    probe::CppDepsProbe,
+    state::BuildState,
+    CppDepsCancel,
+    InnerError,
+    InnerErrorKind,
+};
 
-pub(crate) struct Compiler {
-    tool: crate::vendor::cc::Tool,
-    family: CompilerFamily,
-    out_dir: Arc<r5::Utf8PathBuf>,
-    #[cfg(feature = "memchr")]
-    finders: Finders,
+/// How to configure the [`Compiler`], kept until the analysis starts so that nothing is read from the environment
+/// before then.
+#[derive(Clone)]
+pub(crate) struct CompilerConfig {
+    build: crate::vendor::cc::Build,
+    out_dir: Option<r5::Utf8PathBuf>,
+    target: Option<String>,
+    host: Option<String>,
+    layout: CppDepsLayout,
+    executor: Arc<dyn CppDepsExecutor>,
+    cancel: CppDepsCancel,
+    timeout: Option<Duration>,
+    probe: bool,
+    #[cfg(feature = "jobserver")]
+    jobserver: Option<Arc<Jobserver>>,
 }
-impl Compiler {
-    pub(crate) fn new(mut build: crate::vendor::cc::Build) -> Result<Self, InnerError> {
+impl CompilerConfig {
+    pub(crate) fn new(
+        executor: Arc<dyn CppDepsExecutor>,
+        cancel: CppDepsCancel,
+        #[cfg(feature = "jobserver")] jobserver: Option<Arc<Jobserver>>,
+    ) -> Self {
+        Self {
+            build: crate::vendor::cc::Build::default(),
+            out_dir: None,
+            target: None,
+            host: None,
+            layout: CppDepsLayout::default(),
+            executor,
+            cancel,
+            timeout: None,
+            probe: true,
+            #[cfg(feature = "jobserver")]
+            jobserver,
+        }
+    }
+
+    /// The environment variables consulted when detecting the compiler and running the commands, in the order `cc`
+    /// looks the compiler and its flags up.
+    pub(crate) fn env_vars(&self) -> Vec<String> {
+        let mut env_vars = ["OUT_DIR", "TARGET", "HOST", "CARGO_MAKEFLAGS", "MAKEFLAGS", "MFLAGS"]
+            .map(String::from)
+            .to_vec();
+        let target = self.target.clone().or_else(|| std::env::var("TARGET").ok());
+        let host = self.host.clone().or_else(|| std::env::var("HOST").ok());
+        let kind = if host == target { "HOST" } else { "TARGET" };
+        for var in ["CXX", "CXXFLAGS"] {
+            if let Some(target) = &target {
+                env_vars.push(format!("{var}_{target}"));
+                env_vars.push(format!("{var}_{}", target.replace('-', "_")));
+            }
+            env_vars.push(format!("{kind}_{var}"));
+            env_vars.push(String::from(var));
+        }
+        env_vars
+    }
+
+    /// Detect the compiler like [`CompilerConfig::detect`], and probe it unless probing was disabled.
+    pub(crate) fn resolve(&self) -> Result<Compiler, InnerError> {
+        let compiler = self.detect()?;
+        if !self.probe {
+            return Ok(compiler);
+        }
+        let probe = crate::probe::probe(&compiler)?;
+        Ok(Compiler { probe, ..compiler })
+    }
+
+    /// Detect the compiler and its output directory, falling back to the `OUT_DIR`, `TARGET` and `HOST` environment
+    /// variables for whatever isn't configured.
+    pub(crate) fn detect(&self) -> Result<Compiler, InnerError> {
+        let mut build = self.build.clone();
         build.cpp(true);
-        build.std("c++20");
-        let tool = build
-            .try_get_compiler()
-            .map_err(|err| InnerError::new(InnerErrorKind::CcTryGetCompiler { err }))?;
+        if let Some(target) = &self.target {
+            build.target(target);
+        }
+        if let Some(host) = &self.host {
+            build.host(host);
+        }
+        let out_dir = match &self.out_dir {
+            Some(out_dir) => out_dir.clone(),
+            None => std::env::var("OUT_DIR")
+                .map(r5::Utf8PathBuf::from)
+                .map_err(|err| InnerError::new(InnerErrorKind::EnvVar { err }))?,
+        };
+        build.out_dir(out_dir.as_std_path());
+        let get_compiler = |build: &crate::vendor::cc::Build| {
+            build
+                .try_get_compiler()
+                .map_err(|err| InnerError::new(InnerErrorKind::CcTryGetCompiler { err }))
+        };
+        let mut tool = get_compiler(&build)?;
+        // NOTE: the standard may come from `std`, `flag` or `CXXFLAGS`; the compilers honor the last one given
+        if self::cxx_std_flag(&tool).is_none() {
+            build.std("c++20");
+            tool = get_compiler(&build)?;
+        }
         let family = CompilerFamily::try_from(&tool)?;
-        let out_dir = std::env::var("OUT_DIR").map_err(|err| InnerError::new(InnerErrorKind::EnvVar { err }))?;
-        let out_dir = r5::Utf8PathBuf::from(out_dir);
+        if let Some(flag) = self::cxx_std_flag(&tool) {
+            self::check_cxx_std(flag)?;
+        }
         let out_dir = Arc::from(out_dir);
         #[cfg(feature = "memchr")]
         let finders = Finders::new()?;
-        Ok(Self {
+        Ok(Compiler {
+            config: CompilerConfig { build, ..self.clone() },
             tool,
             family,
             out_dir,
+            probe: CppDepsProbe::default(),
             #[cfg(feature = "memchr")]
             finders,
         })
     }
 
-    pub(crate) fn bmi_path(
+    pub(crate) fn set_build(&mut self, build: crate::vendor::cc::Build) {
+        self.build = build;
+    }
+
+    pub(crate) fn set_out_dir(&mut self, out_dir: r5::Utf8PathBuf) {
+        self.out_dir = Some(out_dir);
+    }
+
+    pub(crate) fn set_target(&mut self, target: String) {
+        self.target = Some(target);
+    }
+
+    pub(crate) fn set_host(&mut self, host: String) {
+        self.host = Some(host);
+    }
+
+    pub(crate) fn set_layout(&mut self, layout: CppDepsLayout) {
+        self.layout = layout;
+    }
+
+    pub(crate) fn set_timeout(&mut self, timeout: Option<Duration>) {
+        self.timeout = timeout;
+    }
+
+    pub(crate) fn set_probe(&mut self, probe: bool) {
+        self.probe = probe;
+    }
+
+    #[cfg(feature = "jobserver")]
+    pub(crate) fn jobserver(&self) -> Option<&Arc<Jobserver>> {
+        self.jobserver.as_ref()
+    }
+
+    #[cfg(feature = "jobserver")]
+    pub(crate) fn set_jobserver(&mut self, jobserver: Option<Arc<Jobserver>>) {
+        self.jobserver = jobserver;
+    }
+
+    pub(crate) fn set_executor(&mut self, executor: Arc<dyn CppDepsExecutor>) {
+        self.executor = executor;
+    }
+}
+
+#[derive(Clone)]
+pub(crate) struct Compiler {
+    config: CompilerConfig,
+    tool: crate::vendor::cc::Tool,
+    family: CompilerFamily,
+    out_dir: Arc<r5::Utf8PathBuf>,
+    probe: CppDepsProbe,
+    #[cfg(feature = "memchr")]
+    finders: Finders,
+}
+impl Compiler {
+    pub(crate) fn out_dir(&self) -> &r5::Utf8Path {
+        &self.out_dir
+    }
+
+    pub(crate) fn probe(&self) -> &CppDepsProbe {
+        &self.probe
+    }
+
+    pub(crate) fn family(&self) -> CompilerFamily {
+        self.family
+    }
+
+    /// The `-std=` or `/std:` flag selecting the C++ standard, if any.
+    pub(crate) fn cxx_std(&self) -> Option<&str> {
+        self::cxx_std_flag(&self.tool)
+    }
+
+    /// The directory of the BMIs, if they are named after their module. See [`CppDepsLayout::with_bmi_dir`].
+    pub(crate) fn bmi_dir(&self) -> Option<r5::Utf8PathBuf> {
+        self.config.layout.bmi_dir(&self.out_dir)
+    }
+
+    /// The scan command for a source and the P1689 file it produces, without running it.
+    pub(crate) fn dep_file_cmd_for(
         &self,
-        mut path: r5::Utf8PathBuf,
-        provides: &[r5::ProvidedModuleDesc<'_>],
-    ) -> Option<r5::Utf8PathBuf> {
+        base: &r5::Utf8Path,
+        src: &r5::Utf8Path,
+        command: Option<&CppDepsCommand>,
+    ) -> Result<(r5::Utf8PathBuf, Command), InnerError> {
+        let dst = self.dep_file_dst(base, src);
+        let cmd = self.dep_file_cmd(src, &dst, command)?;
+        Ok((dst, cmd))
+    }
+
+    /// The Ninja compile command for a source and the object file it produces, without running it.
+    pub(crate) fn obj_file_cmd_for(
+        &self,
+        base: &r5::Utf8Path,
+        src: &r5::Utf8Path,
+        command: Option<&CppDepsCommand>,
+        module_map: &r5::Utf8Path,
+    ) -> Result<(r5::Utf8PathBuf, Command), InnerError> {
+        let dst = self.obj_file_dst(base, src);
+        let cmd = self.family.obj_file_cmd_ninja(self.cxx(command), src, &dst, module_map);
+        Ok((dst, cmd))
+    }
+
+    /// Archive the objects into a static library under the output directory and emit the `cargo:rustc-link-*` lines.
+    ///
+    /// This goes through [`cc::Build::try_compile`](crate::vendor::cc::Build::try_compile), so the archiver is
+    /// detected the same way as for other `cc` builds, and any plain files added to the `cc::Build` are compiled and
+    /// archived along with the objects.
+    pub(crate) fn archive<'a>(
+        &self,
+        lib_name: &str,
+        obj_paths: impl IntoIterator<Item = &'a r5::Utf8Path>,
+    ) -> Result<(), InnerError> {
+        let mut build = self.config.build.clone();
+        for obj_path in obj_paths {
+            build.object(obj_path);
+        }
+        build
+            .try_compile(lib_name)
+            .map_err(|err| InnerError::new(InnerErrorKind::CcTryCompile { err }))
+    }
+
+    /// The BMI paths of the modules provided by a compiled rule which need an explicit `-fmodule-file` mapping.
+    ///
+    /// A module's `compiled-module-path` is used when given. Otherwise the BMI is expected next to the object file, or
+    /// in the BMI directory of the layout, which only needs a mapping when the name can't be found through
+    /// `-fprebuilt-module-path`, i.e., when it's dotted.
+    pub(crate) fn bmi_paths(
+        &self,
+        obj_path: &r5::Utf8Path,
+        dep_info: &DepInfoYoke,
+    ) -> Vec<(DepInfoNameYoke, r5::Utf8PathBuf)> {
+        let mut bmi_paths = vec![];
+        let bmi_dir = self.bmi_dir();
         if self.tool.is_like_clang() {
-            for provided in provides {
-                let name = provided.desc.logical_name();
-                if self.module_name_is_dotted(&name) {
-                    let ext = self.family.bmi_file_ext();
-                    path.set_extension(ext);
-                    return Some(path);
+            let provides = &dep_info.get().provides;
+            for (name, provided) in dep_info.provides().zip(provides.iter()) {
+                if let Some(path) = provided.desc.view().compiled_module_path {
+                    bmi_paths.push((name, path.to_owned()));
+                } else if self.module_name_is_dotted(name.yoke.get()) {
+                    let path = self.family.bmi_file_dst(obj_path, name.yoke.get(), bmi_dir.as_deref());
+                    bmi_paths.push((name, path));
                 }
             }
         }
-        None
+        bmi_paths
     }
 
-    fn dep_file_dst(&self, base: &r5::Utf8Path, src: &r5::Utf8Path) -> Result<r5::Utf8PathBuf, InnerError> {
+    /// The BMI written for each module provided by a compiled rule.
+    ///
+    /// The compiler resolves relative paths, like the `gcm.cache` of gcc, against the working directory of the command,
+    /// so they are resolved the same way here.
+    pub(crate) fn bmi_outputs(
+        &self,
+        obj_path: &r5::Utf8Path,
+        dep_info: &DepInfoYoke,
+        command: &CppDepsCommand,
+    ) -> Vec<(DepInfoNameYoke, r5::Utf8PathBuf)> {
+        let provides = &dep_info.get().provides;
+        let bmi_dir = self.bmi_dir();
+        let current_dir = command.current_dir().and_then(r5::Utf8Path::from_path);
+        dep_info
+            .provides()
+            .zip(provides.iter())
+            .map(|(name, provided)| {
+                let path = match provided.desc.view().compiled_module_path {
+                    Some(path) => path.to_owned(),
+                    None => self.family.bmi_file_dst(obj_path, name.yoke.get(), bmi_dir.as_deref()),
+                };
+                let path = match current_dir {
+                    Some(dir) if path.is_relative() => dir.join(path),
+                    _ => path,
+                };
+                (name, path)
+            })
+            .collect()
+    }
+
+    fn dep_file_dst(&self, base: &r5::Utf8Path, src: &r5::Utf8Path) -> r5::Utf8PathBuf {
         let ext = self.family.dep_file_ext();
-        let src = src
-            .strip_prefix(base)
-            .map_err(|err| InnerError::new(InnerErrorKind::PathStripPrefix { err }))?;
-        let dst = self.out_dir.join(src).with_extension(ext);
-        Ok(dst)
+        self.config
+            .layout
+            .scan_path(&self.out_dir, base, src)
+            .with_extension(ext)
+    }
+
+    // The compiler invocation a unit's commands are built from: the source's own command, if any, or the configured tool.
+    pub(crate) fn cxx(&self, command: Option<&CppDepsCommand>) -> Command {
+        command.map_or_else(|| self.tool.to_command(), CppDepsCommand::to_command)
     }
 
-    fn dep_file_cmd(&self, src: &r5::Utf8Path, dst: &r5::Utf8Path) -> Result<Command, InnerError> {
-        let cxx = self.tool.to_command();
+    pub(crate) fn dep_file_cmd(
+        &self,
+        src: &r5::Utf8Path,
+        dst: &r5::Utf8Path,
+        command: Option<&CppDepsCommand>,
+    ) -> Result<Command, InnerError> {
+        let cxx = self.cxx(command);
         self.family.dep_file_cmd(cxx, src, dst)
     }
 
-    fn obj_file_dst(&self, base: &r5::Utf8Path, src: &r5::Utf8Path) -> Result<r5::Utf8PathBuf, InnerError> {
+    fn obj_file_dst(&self, base: &r5::Utf8Path, src: &r5::Utf8Path) -> r5::Utf8PathBuf {
         let ext = self.family.obj_file_ext();
-        let src = src
-            .strip_prefix(base)
-            .map_err(|err| InnerError::new(InnerErrorKind::PathStripPrefix { err }))?;
-        let dst = self.out_dir.join(src).with_extension(ext);
-        Ok(dst)
+        self.config
+            .layout
+            .obj_path(&self.out_dir, base, src)
+            .with_extension(ext)
     }
 
     fn obj_file_cmd(
         &self,
         src: &r5::Utf8Path,
         dst: &r5::Utf8Path,
+        command: Option<&CppDepsCommand>,
         dep_info: &r5::DepInfo<'_>,
         bmi_dirs: &mut BTreeSet<Arc<r5::Utf8PathBuf>>,
         bmi_maps: &[(DepInfoNameYoke, Arc<r5::Utf8PathBuf>)],
     ) -> Result<Command, InnerError> {
-        let cxx = self.tool.to_command();
-        self.family.obj_file_cmd(cxx, src, dst, dep_info, bmi_dirs, bmi_maps)
+        self.probe.require_module_output()?;
+        let cxx = self.cxx(command);
+        let bmi_dir = self.bmi_dir();
+        self.family
+            .obj_file_cmd(cxx, src, dst, dep_info, bmi_dir.as_deref(), bmi_dirs, bmi_maps)
     }
 
+    /// Scan the source file, returning the path of the P1689 file, the headers the source includes and the output of
+    /// the scan.
+    ///
+    /// The scan is skipped when the build state shows that neither the source, its headers nor the command changed.
     pub(crate) fn compile_dep_file(
         &self,
         base: &r5::Utf8Path,
         path: &r5::Utf8Path,
-    ) -> Result<r5::Utf8PathBuf, InnerError> {
+        command: Option<&CppDepsCommand>,
+        state: Option<&BuildState>,
+    ) -> Result<(r5::Utf8PathBuf, Vec<r5::Utf8PathBuf>, Option<CppDepsExecution>), InnerError> {
         let src = path;
-        let dst = self.dep_file_dst(base, src)?;
-        let cmd = self.dep_file_cmd(src, &dst)?;
-        Self::compile(self, base, src, dst, cmd)
+        let dst = self.dep_file_dst(base, src);
+        let cmd = self.dep_file_cmd(src, &dst, command)?;
+        let command = CppDepsCommand::from(&cmd);
+        if let Some(headers) = state.and_then(|state| state.scanned(src, &command, &dst)) {
+            return Ok((dst, headers, None));
+        }
+        self.probe.require_scan()?;
+        let mut execution = self.execute(&command)?;
+        // NOTE: written once the scan succeeded so that a failing scan doesn't truncate the previous one
+        if self.family.dep_file_to_stdout() {
+            let stdout = core::mem::take(&mut execution.stdout);
+            std::fs::write(&dst, stdout).map_err(|err| InnerError::new(InnerErrorKind::FileWrite { err }))?;
+        }
+        // NOTE: the make-style dependencies are best effort; a scanner which doesn't write them only loses headers
+        let headers = std::fs::read_to_string(dst.with_extension(MAKE_DEPS_EXT))
+            .map(|text| make_deps_prerequisites(&text))
+            .unwrap_or_default()
+            .into_iter()
+            .filter(|path| path != src)
+            .collect::<Vec<_>>();
+        if let Some(state) = state {
+            state.record_scan(src, &command, &dst, &headers)?;
+        }
+        Ok((dst, headers, Some(execution)))
     }
 
-    // FIXME: where does `cc` put the object files?
+    /// Compile the unit, returning the path of the object file and the command.
+    ///
+    /// The compilation is skipped when the build state shows that the unit was scanned as unchanged, the command and
+    /// the upstream BMIs are the same, and the outputs still exist.
+    #[allow(clippy::too_many_arguments)]
     pub(crate) fn compile_obj_file(
         &self,
-        dep_info: &r5::DepInfo<'_>,
+        dep_info: &DepInfoYoke,
         base: &r5::Utf8Path,
         path: &r5::Utf8Path,
+        command: Option<&CppDepsCommand>,
         bmi_dirs: &mut BTreeSet<Arc<r5::Utf8PathBuf>>,
         bmi_maps: &[(DepInfoNameYoke, Arc<r5::Utf8PathBuf>)],
-    ) -> Result<r5::Utf8PathBuf, InnerError> {
+        bmi_inputs: &BTreeSet<Arc<r5::Utf8PathBuf>>,
+        state: Option<&BuildState>,
+    ) -> Result<(r5::Utf8PathBuf, CppDepsCommand, Option<CppDepsExecution>), InnerError> {
         let src = path;
-        let dst = self.obj_file_dst(base, src)?;
-        let cmd = self.obj_file_cmd(src, &dst, dep_info, bmi_dirs, bmi_maps)?;
-        Self::compile(self, base, path, dst, cmd)
+        let dst = self.obj_file_dst(base, src);
+        let cmd = self.obj_file_cmd(src, &dst, command, dep_info.get(), bmi_dirs, bmi_maps)?;
+        let command = CppDepsCommand::from(&cmd);
+        self.create_obj_dirs(&dst)?;
+        let Some(state) = state else {
+            let execution = self.execute(&command)?;
+            return Ok((dst, command, Some(execution)));
+        };
+        let outputs = core::iter::once(dst.clone())
+            .chain(
+                self.bmi_outputs(&dst, dep_info, &command)
+                    .into_iter()
+                    .map(|(_, path)| path),
+            )
+            .collect::<Vec<_>>();
+        if state.compiled(src, &command, bmi_inputs, &outputs) {
+            return Ok((dst, command, None));
+        }
+        let execution = self.execute(&command)?;
+        state.record_compile(src, &command, bmi_inputs, outputs)?;
+        Ok((dst, command, Some(execution)))
+    }
+
+    // The directories the compilation writes to. They are created when compiling rather than when building the
+    // command, so that dry runs leave them alone.
+    fn create_obj_dirs(&self, dst: &r5::Utf8Path) -> Result<(), InnerError> {
+        let bmi_dir = match self.family {
+            CompilerFamily::Clang => self.bmi_dir(),
+            CompilerFamily::Gcc => None,
+        };
+        for dir in dst.parent().map(r5::Utf8Path::to_path_buf).into_iter().chain(bmi_dir) {
+            std::fs::create_dir_all(dir).map_err(|err| InnerError::new(InnerErrorKind::FsCreateDirAll { err }))?;
+        }
+        Ok(())
     }
 
-    fn compile(
+    /// The compile command for the unit and the path of the object file it would produce, without running it.
+    pub(crate) fn plan_obj_file(
         &self,
-        _root: &r5::Utf8Path, // NOTE: use for error
-        _src: &r5::Utf8Path,  // NOTE: use for error
-        dst: r5::Utf8PathBuf,
-        mut cmd: Command,
-    ) -> Result<r5::Utf8PathBuf, InnerError> {
-        let status = cmd
-            .status()
-            .map_err(|err| InnerError::new(InnerErrorKind::CommandStatus { err }))?;
-        if !status.success() {
-            return Err(InnerError::new(InnerErrorKind::CommandCompilerNonZeroExit));
+        dep_info: &DepInfoYoke,
+        base: &r5::Utf8Path,
+        path: &r5::Utf8Path,
+        command: Option<&CppDepsCommand>,
+        bmi_dirs: &mut BTreeSet<Arc<r5::Utf8PathBuf>>,
+        bmi_maps: &[(DepInfoNameYoke, Arc<r5::Utf8PathBuf>)],
+    ) -> Result<(r5::Utf8PathBuf, CppDepsCommand, Option<CppDepsExecution>), InnerError> {
+        let dst = self.obj_file_dst(base, path);
+        let cmd = self.obj_file_cmd(path, &dst, command, dep_info.get(), bmi_dirs, bmi_maps)?;
+        Ok((dst, CppDepsCommand::from(&cmd), None))
+    }
+
+    // Run the command with the executor. A failure carries the command along with its captured output.
+    pub(crate) fn execute(&self, command: &CppDepsCommand) -> Result<CppDepsExecution, InnerError> {
+        #[cfg(feature = "jobserver")]
+        let _slot = self.config.jobserver.as_ref().map(|jobserver| jobserver.acquire());
+        let job = CppDepsJob {
+            cancel: &self.config.cancel,
+            timeout: self.config.timeout,
+            #[cfg(feature = "jobserver")]
+            jobserver: self.config.jobserver.as_ref().map(|jobserver| jobserver.client()),
+        };
+        let execution = match self.config.executor.execute_job(command, &job) {
+            Ok(execution) => execution,
+            Err(_) if job.is_cancelled() => return Err(InnerError::new(InnerErrorKind::Cancelled)),
+            Err(err) if err.kind() == std::io::ErrorKind::TimedOut => {
+                let timed_out = Box::new((command.clone(), self.config.timeout));
+                return Err(InnerError::new(InnerErrorKind::CommandTimedOut { timed_out }));
+            },
+            Err(err) => return Err(InnerError::new(InnerErrorKind::CommandStatus { err })),
+        };
+        if !execution.success() {
+            let failed = Box::new((command.clone(), execution));
+            return Err(InnerError::new(InnerErrorKind::CommandCompilerNonZeroExit { failed }));
         }
-        Ok(dst)
+        Ok(execution)
     }
 
     #[cfg(feature = "memchr")]
@@ -142,12 +502,119 @@ impl Compiler {
     }
 }
 
+const MAKE_DEPS_EXT: &str = "d";
+
+/// Parse the prerequisites of the first rule of a make-style dependency file as written by `-MD -MF`.
+///
+/// Only the first rule is considered since GCC appends extra rules for modules when scanning with `-fmodules-ts`.
+fn make_deps_prerequisites(text: &str) -> Vec<r5::Utf8PathBuf> {
+    let mut paths = Vec::new();
+    let mut path = String::new();
+    let mut seen_target = false;
+    let mut chars = text.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => match chars.peek() {
+                Some('\n') => {
+                    chars.next();
+                    if !path.is_empty() {
+                        paths.push(r5::Utf8PathBuf::from(core::mem::take(&mut path)));
+                    }
+                },
+                Some('\r') => {
+                    chars.next();
+                    chars.next_if_eq(&'\n');
+                    if !path.is_empty() {
+                        paths.push(r5::Utf8PathBuf::from(core::mem::take(&mut path)));
+                    }
+                },
+                Some(&next @ (' ' | '#' | '\\')) => {
+                    chars.next();
+                    path.push(next);
+                },
+                _ => path.push(c),
+            },
+            '$' if chars.peek() == Some(&'$') => {
+                chars.next();
+                path.push('$');
+            },
+            // NOTE: a colon followed by whitespace ends the targets; others belong to paths like `C:\foo`
+            ':' if !seen_target && chars.peek().map_or(true, |next| next.is_whitespace()) => {
+                seen_target = true;
+                path.clear();
+                paths.clear();
+            },
+            '\n' | '\r' => {
+                if seen_target {
+                    break;
+                }
+                path.clear();
+            },
+            ' ' | '\t' => {
+                if !path.is_empty() {
+                    paths.push(r5::Utf8PathBuf::from(core::mem::take(&mut path)));
+                }
+            },
+            _ => path.push(c),
+        }
+    }
+    if seen_target && !path.is_empty() {
+        paths.push(r5::Utf8PathBuf::from(path));
+    }
+    paths
+}
+
+const CXX_STD_PREFIXES: [&str; 3] = ["-std=", "-std:", "/std:"];
+
+// The last flag selecting the C++ standard, e.g., `-std=gnu++23` or `/std:c++latest`.
+fn cxx_std_flag(tool: &crate::vendor::cc::Tool) -> Option<&str> {
+    tool.args()
+        .iter()
+        .filter_map(|arg| arg.to_str())
+        .filter(|arg| CXX_STD_PREFIXES.iter().any(|prefix| arg.starts_with(prefix)))
+        .last()
+}
+
+// The year of the standard selected by a `-std=` or `/std:` flag, or `None` for an unknown or non-C++ standard.
+fn cxx_std_year(flag: &str) -> Option<u16> {
+    let std = CXX_STD_PREFIXES.iter().find_map(|prefix| flag.strip_prefix(prefix))?;
+    let version = ["c++", "gnu++"].iter().find_map(|prefix| std.strip_prefix(prefix))?;
+    match version {
+        "98" => Some(1998),
+        "03" => Some(2003),
+        "0x" | "11" => Some(2011),
+        "1y" | "14" => Some(2014),
+        "1z" | "17" => Some(2017),
+        "2a" | "20" => Some(2020),
+        "2b" | "23" => Some(2023),
+        "2c" | "26" | "latest" => Some(2026),
+        _ => None,
+    }
+}
+
+// Reject a standard known to come without modules. Whether the compiler accepts the standard is probed instead, since
+// an unknown one may well be newer, e.g., `c++2d`.
+fn check_cxx_std(flag: &str) -> Result<(), InnerError> {
+    if self::cxx_std_year(flag).is_some_and(|year| year < 2020) {
+        let std = Box::from(flag);
+        return Err(InnerError::new(InnerErrorKind::CompilerStdBelowCxx20 { std }));
+    }
+    Ok(())
+}
+
 #[derive(Clone, Copy)]
-enum CompilerFamily {
+pub(crate) enum CompilerFamily {
     Clang,
     Gcc,
 }
 impl CompilerFamily {
+    pub(crate) fn as_str(&self) -> &'static str {
+        match self {
+            CompilerFamily::Clang => "clang",
+            CompilerFamily::Gcc => "gcc",
+        }
+    }
+
     fn bmi_file_ext(&self) -> &str {
         match self {
             CompilerFamily::Clang => "pcm",
@@ -155,6 +622,26 @@ impl CompilerFamily {
         }
     }
 
+    /// The BMI of a module, named after the module in `bmi_dir` if any, otherwise where the compiler writes it.
+    pub(crate) fn bmi_file_dst(
+        &self,
+        obj_path: &r5::Utf8Path,
+        name: &str,
+        bmi_dir: Option<&r5::Utf8Path>,
+    ) -> r5::Utf8PathBuf {
+        match (self, bmi_dir) {
+            (CompilerFamily::Clang, Some(bmi_dir)) => {
+                bmi_dir.join(format!("{}.{}", crate::layout::mangle(name), self.bmi_file_ext()))
+            },
+            // NOTE: `-fmodule-output` writes the BMI next to the object file
+            (CompilerFamily::Clang, None) => obj_path.with_extension(self.bmi_file_ext()),
+            // NOTE: the default module mapper writes to `gcm.cache` in the working directory
+            (CompilerFamily::Gcc, _) => {
+                r5::Utf8PathBuf::from("gcm.cache").join(format!("{}.gcm", crate::layout::mangle(name)))
+            },
+        }
+    }
+
     fn dep_file_ext(&self) -> &str {
         match self {
             CompilerFamily::Clang => "ddi",
@@ -180,6 +667,7 @@ impl CompilerFamily {
     ) -> Result<Command, InnerError> {
         cxx.args(["-c", src.as_str()]);
         cxx.args(["-o", dst.with_extension(self.obj_file_ext()).as_str()]);
+        cxx.args(["-MD", "-MF", dst.with_extension(MAKE_DEPS_EXT).as_str()]);
 
         let mut scan_deps = Command::new("clang-scan-deps"); // FIXME: make the specific command configurable
         scan_deps.arg("-format=p1689");
@@ -195,13 +683,17 @@ impl CompilerFamily {
             scan_deps.env(key, val);
         }
 
-        let file = std::fs::File::create(dst).map_err(|err| InnerError::new(InnerErrorKind::FileCreate { err }))?;
-        let stdio = std::process::Stdio::from(file);
-        scan_deps.stdout(stdio);
-
         Ok(scan_deps)
     }
 
+    // Whether the scanner prints the P1689 file instead of writing it to the destination.
+    pub(crate) fn dep_file_to_stdout(&self) -> bool {
+        match self {
+            CompilerFamily::Clang => true,
+            CompilerFamily::Gcc => false,
+        }
+    }
+
     fn dep_file_cmd_gcc(
         &self,
         mut cxx: Command,
@@ -214,11 +706,7 @@ impl CompilerFamily {
 
         cxx.arg("-E");
         cxx.arg("-MD");
-
-        #[cfg(target_family = "unix")]
-        cxx.args(["-MF", "/dev/null"]);
-        #[cfg(target_family = "windows")]
-        cxx.args(["-MF", "NUL"]);
+        cxx.args(["-MF", dst.with_extension(MAKE_DEPS_EXT).as_str()]);
 
         cxx.args(["-x", "c++"]);
         cxx.args(["-c", src.as_str()]);
@@ -226,30 +714,29 @@ impl CompilerFamily {
         Ok(cxx)
     }
 
-    fn obj_file_ext(&self) -> &str {
+    pub(crate) fn obj_file_ext(&self) -> &str {
         match self {
             CompilerFamily::Clang => "o",
             CompilerFamily::Gcc => "o",
         }
     }
 
+    #[allow(clippy::too_many_arguments)]
     fn obj_file_cmd(
         &self,
         cxx: Command,
         src: &r5::Utf8Path,
         dst: &r5::Utf8Path,
         dep_info: &r5::DepInfo<'_>,
+        bmi_dir: Option<&r5::Utf8Path>,
         bmi_dirs: &mut BTreeSet<Arc<r5::Utf8PathBuf>>,
         bmi_maps: &[(DepInfoNameYoke, Arc<r5::Utf8PathBuf>)],
     ) -> Result<Command, InnerError> {
-        let parent = if let Some(dir) = dst.parent() {
-            std::fs::create_dir_all(dir).map_err(|err| InnerError::new(InnerErrorKind::FsCreateDirAll { err }))?;
-            Some(dir.to_path_buf())
-        } else {
-            None
-        };
+        let parent = dst.parent().map(r5::Utf8Path::to_path_buf);
         match self {
-            CompilerFamily::Clang => self.obj_file_cmd_clang(cxx, src, dst, dep_info, parent, bmi_dirs, bmi_maps),
+            CompilerFamily::Clang => {
+                self.obj_file_cmd_clang(cxx, src, dst, dep_info, bmi_dir, parent, bmi_dirs, bmi_maps)
+            },
             CompilerFamily::Gcc => self.obj_file_cmd_gcc(cxx, src, dst),
         }
     }
@@ -261,39 +748,182 @@ impl CompilerFamily {
         src: &r5::Utf8Path,
         dst: &r5::Utf8Path,
         dep_info: &r5::DepInfo<'_>,
+        bmi_dir: Option<&r5::Utf8Path>,
         parent: Option<r5::Utf8PathBuf>,
         bmi_dirs: &mut BTreeSet<Arc<r5::Utf8PathBuf>>,
         bmi_maps: &[(DepInfoNameYoke, Arc<r5::Utf8PathBuf>)],
     ) -> Result<Command, InnerError> {
-        cxx.arg("-fmodule-output"); // TODO: support two-phase via `--precompile`
+        // TODO: support two-phase via `--precompile`
+        match &dep_info.provides[..] {
+            [] => {
+                cxx.arg("-fmodule-output");
+            },
+            [provided] => {
+                let desc = provided.desc.view();
+                match (desc.compiled_module_path, bmi_dir) {
+                    (Some(path), _) => cxx.arg(format!("-fmodule-output={path}")),
+                    (None, Some(bmi_dir)) => {
+                        let bmi_path = self.bmi_file_dst(dst, desc.logical_name, Some(bmi_dir));
+                        cxx.arg(format!("-fmodule-output={bmi_path}"))
+                    },
+                    (None, None) => cxx.arg("-fmodule-output"),
+                };
+            },
+            // NOTE: without an explicit path each BMI would be written next to the object, so they'd collide
+            provides => {
+                let mut bmi_paths = BTreeSet::new();
+                for provided in provides {
+                    let desc = provided.desc.view();
+                    let bmi_path = match (desc.compiled_module_path, bmi_dir) {
+                        (Some(path), _) => Some(path.to_owned()),
+                        (None, Some(bmi_dir)) => Some(self.bmi_file_dst(dst, desc.logical_name, Some(bmi_dir))),
+                        (None, None) => None,
+                    };
+                    let Some(bmi_path) = bmi_path.filter(|path| bmi_paths.insert(path.clone())) else {
+                        let provides = provides
+                            .iter()
+                            .map(|provided| provided.desc.view().logical_name.to_string())
+                            .collect();
+                        return Err(InnerError::new(InnerErrorKind::CompilerMultipleModuleOutputs {
+                            provides,
+                        }));
+                    };
+                    cxx.arg(format!("-fmodule-output={bmi_path}"));
+                }
+            },
+        }
+
+        let is_interface = dep_info.provides.iter().any(|provided| provided.is_interface);
+        let dirs = bmi_dirs.iter().map(|dir| dir.as_path());
+        let maps = bmi_maps
+            .iter()
+            .map(|(name, path)| (name.yoke.get().as_ref(), path.as_path()));
+        cxx.args(self.module_args_clang(is_interface, dirs, maps));
+        cxx.args(["-c", src.as_str()]);
+        cxx.args(["-o", dst.as_str()]);
 
-        for dir in bmi_dirs.iter() {
-            cxx.arg(format!("-fprebuilt-module-path={dir}"));
+        // NOTE: the dependents look up the BMI by its module name in the BMI directory, if any
+        if let Some(dir) = bmi_dir.map(r5::Utf8Path::to_path_buf).or(parent) {
+            bmi_dirs.insert(Arc::new(dir));
         }
 
+        Ok(cxx)
+    }
+
+    // The flags which locate the BMIs of the imported modules and select the language of the unit.
+    fn module_args_clang<'a>(
+        &self,
+        is_interface: bool,
+        bmi_dirs: impl IntoIterator<Item = &'a r5::Utf8Path>,
+        bmi_maps: impl IntoIterator<Item = (&'a str, &'a r5::Utf8Path)>,
+    ) -> Vec<String> {
+        let mut args = vec![];
+        for dir in bmi_dirs {
+            args.push(format!("-fprebuilt-module-path={dir}"));
+        }
         for (name, path) in bmi_maps {
-            let name = name.yoke.get();
-            cxx.arg(format!("-fmodule-file={name}={path}"));
+            args.push(format!("-fmodule-file={name}={path}"));
         }
-
-        if dep_info
-            .provides
-            .first()
-            .map(|provided| provided.is_interface)
-            .unwrap_or(false)
-        {
-            cxx.args(["-x", "c++-module"]);
+        if is_interface {
+            args.extend(["-x".into(), "c++-module".into()]);
         } else {
-            cxx.args(["-x", "c++"]);
+            args.extend(["-x".into(), "c++".into()]);
+        }
+        args
+    }
+
+    /// The contents of the module map written by the Ninja collation step for a unit.
+    ///
+    /// For clang this is a response file with the same flags used for in-process compilation, with the BMI of the
+    /// provided module and every imported module mapped explicitly. For gcc this is a module mapper file listing the
+    /// BMIs of both the provided and the imported modules.
+    pub(crate) fn module_map<'a>(
+        &self,
+        is_interface: bool,
+        provides: impl IntoIterator<Item = (&'a str, &'a r5::Utf8Path)>,
+        imports: impl IntoIterator<Item = (&'a str, &'a r5::Utf8Path)>,
+    ) -> String {
+        let mut text = String::new();
+        match self {
+            CompilerFamily::Clang => {
+                let mut args = provides
+                    .into_iter()
+                    .map(|(_, path)| format!("-fmodule-output={path}"))
+                    .collect::<Vec<_>>();
+                args.extend(self.module_args_clang(is_interface, [], imports));
+                for arg in args {
+                    text.push('"');
+                    text.push_str(&arg.replace('\\', "\\\\").replace('"', "\\\""));
+                    text.push_str("\"\n");
+                }
+            },
+            CompilerFamily::Gcc => {
+                // NOTE: relative BMI paths are otherwise resolved against the `gcm.cache` repository
+                text.push_str("$root .\n");
+                for (name, path) in provides.into_iter().chain(imports) {
+                    text.push_str(&format!("{name} {path}\n"));
+                }
+            },
+        }
+        text
+    }
+
+    /// The compile command of a Ninja compile edge, which reads the module flags from the collated module map.
+    pub(crate) fn obj_file_cmd_ninja(
+        &self,
+        mut cxx: Command,
+        src: &r5::Utf8Path,
+        dst: &r5::Utf8Path,
+        module_map: &r5::Utf8Path,
+    ) -> Command {
+        match self {
+            CompilerFamily::Clang => {
+                cxx.arg("-fmodule-output");
+                cxx.arg(format!("@{module_map}"));
+            },
+            CompilerFamily::Gcc => {
+                cxx.arg("-fmodules-ts");
+                cxx.arg(format!("-fmodule-mapper={module_map}"));
+                cxx.args(["-x", "c++"]);
+            },
         }
         cxx.args(["-c", src.as_str()]);
         cxx.args(["-o", dst.as_str()]);
+        cxx
+    }
 
-        if let Some(dir) = parent {
-            bmi_dirs.insert(Arc::new(dir));
+    /// The module flags of a unit compiled by a generated Makefile, along with the module map they refer to, if any.
+    ///
+    /// For clang every imported module is mapped explicitly on the command line. For gcc the flags point at a module
+    /// mapper file, since it can't be given the BMI paths otherwise.
+    pub(crate) fn module_flags_make<'a>(
+        &self,
+        is_interface: bool,
+        provides: &'a [(String, r5::Utf8PathBuf)],
+        imports: impl IntoIterator<Item = (&'a str, &'a r5::Utf8Path)>,
+        module_map: &r5::Utf8Path,
+    ) -> (Vec<String>, Option<String>) {
+        match self {
+            CompilerFamily::Clang => {
+                let mut flags = vec![];
+                if !provides.is_empty() {
+                    flags.push("-fmodule-output".into());
+                }
+                flags.extend(self.module_args_clang(is_interface, [], imports));
+                (flags, None)
+            },
+            CompilerFamily::Gcc => {
+                let provides = provides.iter().map(|(name, path)| (name.as_str(), path.as_path()));
+                let text = self.module_map(is_interface, provides, imports);
+                let flags = vec![
+                    "-fmodules-ts".into(),
+                    format!("-fmodule-mapper={module_map}"),
+                    "-x".into(),
+                    "c++".into(),
+                ];
+                (flags, Some(text))
+            },
         }
-
-        Ok(cxx)
     }
 
     fn obj_file_cmd_gcc(
@@ -312,6 +942,17 @@ impl CompilerFamily {
         Ok(cxx)
     }
 }
+impl core::str::FromStr for CompilerFamily {
+    type Err = InnerError;
+
+    fn from_str(name: &str) -> Result<Self, Self::Err> {
+        match name {
+            "clang" => Ok(CompilerFamily::Clang),
+            "gcc" => Ok(CompilerFamily::Gcc),
+            _ => Err(InnerError::new(InnerErrorKind::CompilerFamilyTryFromUnknownFamily)),
+        }
+    }
+}
 #[cfg(feature = "cc")]
 impl TryFrom<&crate::vendor::cc::Tool> for CompilerFamily {
     type Error = InnerError;
@@ -358,5 +999,385 @@ mod test {
         inner().unwrap()
     }
 
+    #[test]
+    fn compile_archive() -> BoxResult<()> {
+        let paths = crate::testing::corpus::src_file::items()?;
+        let validate = crate::testing::corpus::src_file::validate_order(paths)?;
+        let outputs = validate.cpp_deps.compile("cpp-deps-test")?;
+        assert_eq!(outputs.len(), 5);
+        assert!(outputs.iter().all(|output| output.obj_path().is_some()));
+        assert!(validate.out_dir.path().join("libcpp-deps-test.a").exists());
+        Ok(())
+    }
+
+    #[test]
+    fn compile_incremental() -> BoxResult<()> {
+        fn modified(outputs: &[crate::CppDepsOutput]) -> BoxResult<Vec<std::time::SystemTime>> {
+            let paths = outputs.iter().filter_map(crate::CppDepsOutput::obj_path);
+            Ok(paths
+                .map(|path| std::fs::metadata(path)?.modified())
+                .collect::<Result<_, _>>()?)
+        }
+        let paths = crate::testing::corpus::src_file::items()?;
+        let mut validate = crate::testing::corpus::src_file::validate_order(paths)?;
+        validate.cpp_deps.incremental(true);
+        let outputs = validate.cpp_deps.into_iter().collect::<Result<Vec<_>, _>>()?;
+        assert!(validate.out_dir.path().join("cpp-deps.state").exists());
+
+        // Rebuild in the same output directory: every unit is yielded again without being recompiled.
+        let mut cpp_deps = crate::CppDeps::new()?;
+        cpp_deps.items(crate::testing::corpus::src_file::items()?)?;
+        crate::testing::configure(&mut cpp_deps, validate.out_dir.path())?;
+        cpp_deps.incremental(true);
+        let rebuilt = cpp_deps.into_iter().collect::<Result<Vec<_>, _>>()?;
+        assert_eq!(rebuilt.len(), outputs.len());
+        let mut expected = modified(&outputs)?;
+        let mut actual = modified(&rebuilt)?;
+        expected.sort();
+        actual.sort();
+        assert_eq!(expected, actual);
+        Ok(())
+    }
+
+    #[test]
+    fn explicit_out_dir() -> BoxResult<()> {
+        let paths = crate::testing::corpus::src_file::items()?;
+        let mut validate = crate::testing::corpus::src_file::validate_order(paths)?;
+        validate.cpp_deps.executor(crate::testing::RecordingExecutor::default());
+        let out_dir = tempdir::TempDir::new("cpp-deps")?;
+        validate
+            .cpp_deps
+            .out_dir(p1689::r5::Utf8Path::from_path(out_dir.path()).ok_or("non-UTF-8 temp dir")?);
+        let outputs = validate.cpp_deps.into_iter().collect::<Result<Vec<_>, _>>()?;
+        assert_eq!(outputs.len(), 5);
+        let obj_paths = outputs
+            .iter()
+            .filter_map(crate::CppDepsOutput::obj_path)
+            .collect::<Vec<_>>();
+        assert_eq!(obj_paths.len(), 5);
+        assert!(obj_paths.iter().all(|path| path.starts_with(out_dir.path())));
+        Ok(())
+    }
+
+    // Without an output directory, nor `OUT_DIR` outside of a build script, the analyzer fails instead of `new`.
+    #[test]
+    fn missing_out_dir() -> BoxResult<()> {
+        if std::env::var_os("OUT_DIR").is_some() {
+            return Ok(());
+        }
+        let mut cpp_deps = crate::CppDeps::new()?;
+        cpp_deps.items(crate::testing::corpus::src_file::items()?)?;
+        let mut build = cc::Build::new();
+        build.opt_level(3);
+        cpp_deps.compiler(build)?;
+        cpp_deps.target("x86_64-unknown-linux-gnu");
+        cpp_deps.host("x86_64-unknown-linux-gnu");
+        let results = cpp_deps.into_iter().collect::<Vec<_>>();
+        assert_eq!(results.len(), 1);
+        assert!(results[0].is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn cxx_std_year() -> BoxResult<()> {
+        assert_eq!(super::cxx_std_year("-std=c++17"), Some(2017));
+        assert_eq!(super::cxx_std_year("-std=gnu++2a"), Some(2020));
+        assert_eq!(super::cxx_std_year("-std=c++23"), Some(2023));
+        assert_eq!(super::cxx_std_year("/std:c++latest"), Some(2026));
+        assert_eq!(super::cxx_std_year("-std=c17"), None);
+        assert_eq!(super::cxx_std_year("-std=c++4x"), None);
+        Ok(())
+    }
+
+    // The standard selected by the build is kept rather than replaced with `c++20`.
+    #[test]
+    fn cxx_std_kept() -> BoxResult<()> {
+        let paths = crate::testing::corpus::src_file::items()?;
+        let mut validate = crate::testing::corpus::src_file::validate_order(paths)?;
+        let executor = alloc::sync::Arc::new(crate::testing::RecordingExecutor::default());
+        validate.cpp_deps.executor(executor.clone());
+        validate.run()?;
+        let commands = executor.commands();
+        assert_eq!(commands.len(), 10);
+        for command in &commands {
+            let stds = command
+                .args()
+                .filter_map(|arg| arg.to_str()?.strip_prefix("-std="))
+                .collect::<Vec<_>>();
+            assert_eq!(stds.last(), Some(&"gnu++23"));
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn cxx_std_below_20() -> BoxResult<()> {
+        let paths = crate::testing::corpus::src_file::items()?;
+        let mut validate = crate::testing::corpus::src_file::validate_order(paths)?;
+        validate.cpp_deps.executor(crate::testing::RecordingExecutor::default());
+        let mut build = cc::Build::new();
+        build.opt_level(3).flag("-std=c++17");
+        validate.cpp_deps.compiler(build)?;
+        let results = validate.cpp_deps.into_iter().collect::<Vec<_>>();
+        assert_eq!(results.len(), 1);
+        let err = results.into_iter().next().ok_or("no result")?.err().ok_or("no error")?;
+        assert!(err.is_std_unsupported());
+        assert!(
+            err.to_string()
+                .starts_with("error: `-std=c++17` selects a C++ standard older than C++20")
+        );
+        Ok(())
+    }
+
+    // A standard too new to be known is left to the compiler, which may reject it when probed.
+    #[test]
+    fn cxx_std_unknown() -> BoxResult<()> {
+        struct RejectingExecutor;
+        impl crate::CppDepsExecutor for RejectingExecutor {
+            fn execute(&self, command: &crate::CppDepsCommand) -> std::io::Result<crate::CppDepsExecution> {
+                let rejected = command.args().any(|arg| arg == "-std=c++2d");
+                let stderr = b"error: invalid value 'c++2d' in '-std=c++2d'\n".to_vec();
+                Ok(crate::CppDepsExecution::new(Some(i32::from(rejected)), vec![], stderr))
+            }
+        }
+        assert_eq!(super::cxx_std_year("-std=c++2d"), None);
+        assert!(super::check_cxx_std("-std=c++2d").is_ok());
+
+        let paths = crate::testing::corpus::src_file::items()?;
+        let mut validate = crate::testing::corpus::src_file::validate_order(paths)?;
+        validate.cpp_deps.executor(RejectingExecutor);
+        let mut build = cc::Build::new();
+        build.opt_level(3).flag("-std=c++2d");
+        validate.cpp_deps.compiler(build.clone())?;
+        let err = validate.cpp_deps.probe().err().ok_or("expected an error")?;
+        assert!(err.is_std_unsupported());
+        assert!(err.command().is_some());
+        assert!(
+            err.to_string()
+                .starts_with("error: the compiler doesn't accept `-std=c++2d`")
+        );
+
+        // NOTE: the analysis probes the compiler by default, so the standard is rejected before any unit is scanned
+        let out_dir = tempdir::TempDir::new("cpp-deps")?;
+        let mut cpp_deps = crate::CppDeps::new()?;
+        cpp_deps.items(crate::testing::corpus::src_file::items()?)?;
+        crate::testing::configure_probed(&mut cpp_deps, out_dir.path())?;
+        cpp_deps.executor(RejectingExecutor);
+        cpp_deps.compiler(build)?;
+        let err = cpp_deps.analyze().find_map(Result::err).ok_or("expected an error")?;
+        assert!(err.is_std_unsupported());
+        Ok(())
+    }
+
+    #[test]
+    fn layout_dirs() -> BoxResult<()> {
+        let paths = crate::testing::corpus::src_file::items()?;
+        let mut validate = crate::testing::corpus::src_file::validate_order(paths)?;
+        let executor = alloc::sync::Arc::new(crate::testing::RecordingExecutor::default());
+        validate.cpp_deps.executor(executor.clone());
+        let layout = crate::CppDepsLayout::default()
+            .with_scan_dir("scans")
+            .with_obj_dir("objs");
+        validate.cpp_deps.layout(layout);
+        let outputs = validate.cpp_deps.into_iter().collect::<Result<Vec<_>, _>>()?;
+        let obj_paths = outputs
+            .iter()
+            .filter_map(crate::CppDepsOutput::obj_path)
+            .collect::<Vec<_>>();
+        assert_eq!(obj_paths.len(), 5);
+        let obj_dir = validate.out_dir.path().join("objs");
+        assert!(obj_paths.iter().all(|path| path.starts_with(&obj_dir)));
+        let scan_dir = validate.out_dir.path().join("scans");
+        let scans = executor
+            .commands()
+            .iter()
+            .flat_map(|command| {
+                command
+                    .args()
+                    .filter_map(|arg| arg.to_str()?.strip_prefix("-fdeps-file="))
+            })
+            .map(String::from)
+            .collect::<Vec<_>>();
+        assert!(
+            scans
+                .iter()
+                .all(|path| std::path::Path::new(path).starts_with(&scan_dir))
+        );
+        Ok(())
+    }
+
+    // Clang writes a single BMI per compilation, so a rule providing several modules needs a distinct path for each.
+    #[test]
+    fn clang_multiple_provides() -> BoxResult<()> {
+        use p1689::r5;
+        let crate::CppDepsItem::DepText { dep_path, dep_text, .. } = crate::testing::corpus::dep_text::foo_parts()
+        else {
+            return Err("expected dep text".into());
+        };
+        let dep_text = core::str::from_utf8(dep_text.as_ref())?;
+        let module_output = |paths: Option<[&str; 2]>, bmi_dir: Option<&r5::Utf8Path>| -> BoxResult<Vec<String>> {
+            let mut dep_text = dep_text.to_owned();
+            for (name, path) in ["foo:part1", "foo:part2"].into_iter().zip(paths.into_iter().flatten()) {
+                let logical_name = format!(r#""logical-name": "{name}","#);
+                dep_text = dep_text.replace(
+                    &logical_name,
+                    &format!(r#"{logical_name} "compiled-module-path": "{path}","#),
+                );
+            }
+            let state = r5::parsers::State::default();
+            let mut stream = r5::parsers::ParseStream::new(dep_path.as_ref(), dep_text.as_bytes(), state);
+            let dep_file = r5::parsers::dep_file(&mut stream).map_err(|_| "invalid dep text")?;
+            let cmd = super::CompilerFamily::Clang.obj_file_cmd(
+                std::process::Command::new("clang++"),
+                r5::Utf8Path::new("foo/parts.cppm"),
+                r5::Utf8Path::new("out/foo/parts.o"),
+                &dep_file.rules[0],
+                bmi_dir,
+                &mut std::collections::BTreeSet::new(),
+                &[],
+            )?;
+            Ok(cmd
+                .get_args()
+                .filter_map(|arg| arg.to_str())
+                .filter(|arg| arg.starts_with("-fmodule-output"))
+                .map(ToOwned::to_owned)
+                .collect())
+        };
+        assert_eq!(module_output(Some(["out/part1.pcm", "out/part2.pcm"]), None)?, [
+            "-fmodule-output=out/part1.pcm",
+            "-fmodule-output=out/part2.pcm"
+        ]);
+        assert_eq!(module_output(None, Some(r5::Utf8Path::new("out/bmi")))?, [
+            "-fmodule-output=out/bmi/foo-part1.pcm",
+            "-fmodule-output=out/bmi/foo-part2.pcm"
+        ]);
+        for paths in [None, Some(["out/parts.pcm", "out/parts.pcm"])] {
+            let err = module_output(paths, None).err().ok_or("expected an error")?;
+            let err = err.downcast::<crate::InnerError>()?;
+            assert!(matches!(
+                err.kind,
+                crate::InnerErrorKind::CompilerMultipleModuleOutputs { ref provides } if provides[..] == ["foo:part1", "foo:part2"]
+            ));
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn clang_module_output() -> BoxResult<()> {
+        use p1689::r5;
+        let crate::CppDepsItem::DepText { dep_path, dep_text, .. } = crate::testing::corpus::dep_text::foo_part1()
+        else {
+            return Err("expected dep text".into());
+        };
+        let state = r5::parsers::State::default();
+        let mut stream = r5::parsers::ParseStream::new(dep_path.as_ref(), dep_text.as_ref(), state);
+        let dep_file = r5::parsers::dep_file(&mut stream).map_err(|_| "invalid dep text")?;
+        let module_output = |bmi_dir: Option<&r5::Utf8Path>| -> BoxResult<Vec<String>> {
+            let cmd = super::CompilerFamily::Clang.obj_file_cmd(
+                std::process::Command::new("clang++"),
+                r5::Utf8Path::new("foo/part1.cppm"),
+                r5::Utf8Path::new("out/foo/part1.o"),
+                &dep_file.rules[0],
+                bmi_dir,
+                &mut std::collections::BTreeSet::new(),
+                &[],
+            )?;
+            Ok(cmd
+                .get_args()
+                .filter_map(|arg| arg.to_str())
+                .filter(|arg| arg.starts_with("-fmodule-output"))
+                .map(ToOwned::to_owned)
+                .collect())
+        };
+        assert_eq!(module_output(None)?, ["-fmodule-output"]);
+        assert_eq!(module_output(Some(r5::Utf8Path::new("out/bmi")))?, [
+            "-fmodule-output=out/bmi/foo-part1.pcm"
+        ]);
+        Ok(())
+    }
+
+    // The BMIs which gcc writes under the working directory are reported there, not under that of the process.
+    #[test]
+    fn bmi_current_dir() -> BoxResult<()> {
+        let work_dir = tempdir::TempDir::new("cpp-deps")?;
+        let work_dir = p1689::r5::Utf8Path::from_path(work_dir.path()).ok_or("non-UTF-8 temp dir")?;
+        let mut cxx = std::process::Command::new("c++");
+        cxx.current_dir(work_dir);
+        let command = crate::CppDepsCommand::from(&cxx);
+        let paths = crate::testing::corpus::src_file::items()?.map(|item| match item {
+            crate::CppDepsItem::SrcFile { src_file } => crate::CppDepsItem::SrcFile {
+                src_file: src_file.with_command(command.clone()),
+            },
+            item => item,
+        });
+        let mut validate = crate::testing::corpus::src_file::validate_order(paths)?;
+        validate.cpp_deps.executor(crate::testing::RecordingExecutor::default());
+        let outputs = validate.cpp_deps.into_iter().collect::<Result<Vec<_>, _>>()?;
+        let bmi_paths = outputs.iter().flat_map(|output| output.bmi_paths()).collect::<Vec<_>>();
+        assert_eq!(bmi_paths.len(), 4);
+        for (_, path) in bmi_paths {
+            assert!(path.is_absolute());
+            if path.components().any(|component| component.as_str() == "gcm.cache") {
+                assert!(path.starts_with(work_dir));
+            }
+        }
+        Ok(())
+    }
+
+    // Only the scans write to the output directory during a dry run.
+    #[test]
+    fn dry_run_dirs() -> BoxResult<()> {
+        let paths = crate::testing::corpus::src_file::items()?;
+        let mut validate = crate::testing::corpus::src_file::validate_order(paths)?;
+        validate.cpp_deps.executor(crate::testing::RecordingExecutor::default());
+        validate.cpp_deps.dry_run(true);
+        let layout = crate::CppDepsLayout::default()
+            .with_scan_dir("scans")
+            .with_obj_dir("objs")
+            .with_bmi_dir("bmis");
+        validate.cpp_deps.layout(layout);
+        let outputs = validate.cpp_deps.into_iter().collect::<Result<Vec<_>, _>>()?;
+        assert_eq!(outputs.len(), 5);
+        assert!(validate.out_dir.path().join("scans").exists());
+        assert!(!validate.out_dir.path().join("objs").exists());
+        assert!(!validate.out_dir.path().join("bmis").exists());
+        Ok(())
+    }
+
+    // Sources outside of their base are built under a hashed directory rather than failing.
+    #[test]
+    fn external_sources() -> BoxResult<()> {
+        let paths = crate::testing::corpus::src_file::items()?.map(|item| match item {
+            crate::CppDepsItem::SrcFile { src_file } => {
+                let src_base = alloc::borrow::Cow::Owned(p1689::r5::Utf8PathBuf::from("/nonexistent"));
+                let src_file = crate::CppDepsSrc { src_base, ..src_file };
+                crate::CppDepsItem::SrcFile { src_file }
+            },
+            item => item,
+        });
+        let mut validate = crate::testing::corpus::src_file::validate_order(paths)?;
+        validate.cpp_deps.executor(crate::testing::RecordingExecutor::default());
+        let outputs = validate.cpp_deps.into_iter().collect::<Result<Vec<_>, _>>()?;
+        let obj_paths = outputs
+            .iter()
+            .filter_map(crate::CppDepsOutput::obj_path)
+            .collect::<Vec<_>>();
+        assert_eq!(obj_paths.len(), 5);
+        let external = validate.out_dir.path().join("external");
+        assert!(obj_paths.iter().all(|path| path.starts_with(&external)));
+        Ok(())
+    }
+
+    #[test]
+    fn make_deps_prerequisites() {
+        let text = "build/foo.o build/foo.ddi: src/foo.cc \\\n  include/a\\ b.h /usr/include/c++/12/vector \\\n lib$$.h\nfoo.c++m: build/foo.o\n";
+        let paths = super::make_deps_prerequisites(text);
+        assert_eq!(paths, [
+            "src/foo.cc",
+            "include/a b.h",
+            "/usr/include/c++/12/vector",
+            "lib$.h"
+        ]);
+    }
+
     // TODO: test compiling invalid source file
 }
@@ -0,0 +1,274 @@
+use p1689::r5;
+
+const SEVERITIES: [(&str, CppDepsSeverity); 5] = [
+    ("fatal error", CppDepsSeverity::Fatal),
+    ("error", CppDepsSeverity::Error),
+    ("warning", CppDepsSeverity::Warning),
+    ("note", CppDepsSeverity::Note),
+    ("remark", CppDepsSeverity::Remark),
+];
+
+#[derive(Clone, Copy, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
+pub enum CppDepsSeverity {
+    Note,
+    Remark,
+    Warning,
+    Error,
+    Fatal,
+}
+
+/// Where a diagnostic points, as `path[:line[:column]]`.
+///
+/// The path is whatever the compiler printed in place of a file, which may also be the name of a module or of the
+/// compiler itself.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct CppDepsLocation {
+    path: r5::Utf8PathBuf,
+    line: Option<u32>,
+    column: Option<u32>,
+}
+impl CppDepsLocation {
+    pub fn path(&self) -> &r5::Utf8Path {
+        &self.path
+    }
+
+    pub fn line(&self) -> Option<u32> {
+        self.line
+    }
+
+    pub fn column(&self) -> Option<u32> {
+        self.column
+    }
+
+    fn parse(text: &str) -> Self {
+        fn split(text: &str) -> Option<(&str, u32)> {
+            let (rest, number) = text.rsplit_once(':')?;
+            Some((rest, number.parse().ok()?))
+        }
+        let (path, line, column) = match split(text) {
+            Some((rest, column)) => match split(rest) {
+                Some((path, line)) => (path, Some(line), Some(column)),
+                None => (rest, Some(column), None),
+            },
+            None => (text, None, None),
+        };
+        let path = r5::Utf8PathBuf::from(path);
+        Self { path, line, column }
+    }
+}
+
+/// An inclusion or module import through which a diagnostic was reached.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct CppDepsFrame {
+    module: Option<String>,
+    location: CppDepsLocation,
+}
+impl CppDepsFrame {
+    /// The imported module, when the compiler names it. Absent for `#include`s.
+    pub fn module(&self) -> Option<&str> {
+        self.module.as_deref()
+    }
+
+    /// Where the header was included or the module was imported.
+    pub fn location(&self) -> &CppDepsLocation {
+        &self.location
+    }
+
+    // Recognize the `In file included from` and `In module imported from` lines preceding a diagnostic.
+    fn parse(line: &str, continued: bool) -> Option<Self> {
+        let line = line.trim_end().trim_end_matches([':', ',']);
+        let (module, location) = if let Some(location) = line.strip_prefix("In file included from ") {
+            (None, location)
+        } else if let Some(location) = line.trim_start().strip_prefix("from ").filter(|_| continued) {
+            (None, location)
+        } else if let Some(location) = line.strip_prefix("In module imported at ") {
+            (None, location)
+        } else if let Some(rest) = line.strip_prefix("In module ") {
+            // NOTE: clang prints `In module 'foo' imported from`, gcc prints `In module foo, imported at`
+            let (module, location) = rest
+                .split_once(" imported from ")
+                .or_else(|| rest.split_once(", imported at "))?;
+            (Some(module.trim_matches('\'').to_owned()), location)
+        } else {
+            return None;
+        };
+        let location = CppDepsLocation::parse(location);
+        Some(Self { module, location })
+    }
+}
+
+/// A diagnostic printed by GCC or Clang, as `path:line:column: severity: message`.
+///
+/// The notes following a diagnostic are attached to it, as is the chain of inclusions and module imports printed before
+/// it. Other lines, like the source excerpts and the summaries, are ignored.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct CppDepsDiagnostic {
+    severity: CppDepsSeverity,
+    location: Option<CppDepsLocation>,
+    message: String,
+    frames: Vec<CppDepsFrame>,
+    notes: Vec<CppDepsDiagnostic>,
+}
+impl CppDepsDiagnostic {
+    pub fn severity(&self) -> CppDepsSeverity {
+        self.severity
+    }
+
+    /// Absent for diagnostics about the command itself, like an unknown option.
+    pub fn location(&self) -> Option<&CppDepsLocation> {
+        self.location.as_ref()
+    }
+
+    pub fn message(&self) -> &str {
+        &self.message
+    }
+
+    /// The inclusions and module imports through which the diagnostic was reached, innermost first.
+    pub fn frames(&self) -> &[CppDepsFrame] {
+        &self.frames
+    }
+
+    pub fn notes(&self) -> &[CppDepsDiagnostic] {
+        &self.notes
+    }
+
+    /// Parse the diagnostics in the standard error of a GCC or Clang command.
+    pub fn parse(text: &str) -> Vec<CppDepsDiagnostic> {
+        let mut diagnostics = Vec::<CppDepsDiagnostic>::new();
+        let mut frames = vec![];
+        for line in text.lines() {
+            if let Some(frame) = CppDepsFrame::parse(line, !frames.is_empty()) {
+                frames.push(frame);
+                continue;
+            }
+            let Some(mut diagnostic) = Self::parse_line(line) else {
+                continue;
+            };
+            diagnostic.frames = core::mem::take(&mut frames);
+            match diagnostics.last_mut() {
+                Some(last) if diagnostic.severity == CppDepsSeverity::Note => last.notes.push(diagnostic),
+                _ => diagnostics.push(diagnostic),
+            }
+        }
+        diagnostics
+    }
+
+    fn parse_line(line: &str) -> Option<Self> {
+        let (location, severity, message) = SEVERITIES
+            .iter()
+            .filter_map(|(name, severity)| {
+                if let Some(message) = line.strip_prefix(name).and_then(|rest| rest.strip_prefix(": ")) {
+                    return Some((0, None, *severity, message));
+                }
+                let start = line.find(&format!(": {name}: "))?;
+                let location = Some(CppDepsLocation::parse(&line[.. start]));
+                Some((start, location, *severity, &line[start + name.len() + 4 ..]))
+            })
+            .min_by_key(|(start, ..)| *start)
+            .map(|(_, location, severity, message)| (location, severity, message))?;
+        Some(Self {
+            severity,
+            location,
+            message: message.to_owned(),
+            frames: vec![],
+            notes: vec![],
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{CppDepsDiagnostic, CppDepsSeverity};
+    use crate::testing::BoxResult;
+
+    #[test]
+    fn parse_gcc() -> BoxResult<()> {
+        let text = "\
+In file included from include/a.h:1,
+                 from src/main.cpp:2:
+include/b.h:3:5: error: 'x' was not declared in this scope
+    3 |     x;
+      |     ^
+src/main.cpp: In function 'int main()':
+src/main.cpp:4:3: warning: unused variable 'y' [-Wunused-variable]
+src/main.cpp:1:5: note: declared here
+In module imported at src/main.cpp:1:1:
+foo: error: failed to read compiled module: No such file or directory
+c++: error: unrecognized command-line option '-fdeps-format=p1689r5'
+compilation terminated.
+";
+        let diagnostics = CppDepsDiagnostic::parse(text);
+        assert_eq!(diagnostics.len(), 4);
+
+        let undeclared = &diagnostics[0];
+        assert_eq!(undeclared.severity(), CppDepsSeverity::Error);
+        assert_eq!(undeclared.message(), "'x' was not declared in this scope");
+        let location = undeclared.location().ok_or("missing location")?;
+        assert_eq!(
+            (location.path().as_str(), location.line(), location.column()),
+            ("include/b.h", Some(3), Some(5))
+        );
+        let frames = undeclared
+            .frames()
+            .iter()
+            .map(|frame| (frame.location().path().as_str(), frame.location().line()))
+            .collect::<Vec<_>>();
+        assert_eq!(frames, [("include/a.h", Some(1)), ("src/main.cpp", Some(2))]);
+
+        let unused = &diagnostics[1];
+        assert_eq!(unused.severity(), CppDepsSeverity::Warning);
+        assert!(unused.frames().is_empty());
+        assert_eq!(unused.notes().len(), 1);
+        assert_eq!(unused.notes()[0].message(), "declared here");
+
+        let module = &diagnostics[2];
+        assert_eq!(module.location().map(|location| location.path().as_str()), Some("foo"));
+        assert_eq!(module.frames().len(), 1);
+        assert_eq!(module.frames()[0].module(), None);
+        assert_eq!(module.frames()[0].location().column(), Some(1));
+
+        let option = &diagnostics[3];
+        assert_eq!(option.location().map(|location| location.path().as_str()), Some("c++"));
+        assert_eq!(option.location().and_then(|location| location.line()), None);
+        Ok(())
+    }
+
+    #[test]
+    fn parse_clang() -> BoxResult<()> {
+        let text = "\
+In module 'foo' imported from src/main.cpp:1:
+In module 'foo:part1' imported from src/foo.cppm:3:
+src/foo/part1.cppm:2:10: fatal error: 'missing.h' file not found
+    2 | #include \"missing.h\"
+      |          ^~~~~~~~~~~
+src/main.cpp:5:3: error: no matching function for call to 'f'
+src/foo.cppm:4:6: note: candidate function not viable: requires 1 argument, but 0 were provided
+error: unable to open output file 'main.o': 'Permission denied'
+2 errors generated.
+";
+        let diagnostics = CppDepsDiagnostic::parse(text);
+        assert_eq!(diagnostics.len(), 3);
+
+        let fatal = &diagnostics[0];
+        assert_eq!(fatal.severity(), CppDepsSeverity::Fatal);
+        assert_eq!(fatal.message(), "'missing.h' file not found");
+        let modules = fatal.frames().iter().map(|frame| frame.module()).collect::<Vec<_>>();
+        assert_eq!(modules, [Some("foo"), Some("foo:part1")]);
+        let location = fatal.frames()[1].location();
+        assert_eq!(
+            (location.path().as_str(), location.line(), location.column()),
+            ("src/foo.cppm", Some(3), None)
+        );
+
+        assert_eq!(diagnostics[1].notes().len(), 1);
+        assert_eq!(diagnostics[1].notes()[0].severity(), CppDepsSeverity::Note);
+
+        let output = &diagnostics[2];
+        assert_eq!(output.location(), None);
+        assert_eq!(
+            output.message(),
+            "unable to open output file 'main.o': 'Permission denied'"
+        );
+        Ok(())
+    }
+}
@@ -0,0 +1,418 @@
+use core::time::Duration;
+use std::{
+    io::Read,
+    process::{Command, ExitStatus, Stdio},
+    time::Instant,
+};
+
+use crate::{CppDepsCancel, CppDepsCommand, CppDepsExecution};
+
+/// Runs the scan and compile commands on behalf of [`CppDeps`](crate::CppDeps).
+///
+/// The default is [`CppDepsLocalExecutor`]. A custom executor can intercept, cache, log or redirect the executions,
+/// e.g., record them for tests or forward them to a remote execution service. Commands run concurrently from the worker
+/// threads. An executor which runs a command elsewhere is responsible for bringing back the files it writes.
+pub trait CppDepsExecutor: Send + Sync {
+    /// Run the command to completion, capturing its standard output and standard error.
+    fn execute(&self, command: &CppDepsCommand) -> std::io::Result<CppDepsExecution>;
+
+    /// Run the command as a job of the analysis, which can be cancelled, has a timeout and may share a jobserver.
+    ///
+    /// By default the command is only run with [`CppDepsExecutor::execute`], or
+    /// [`CppDepsExecutor::execute_with_jobserver`] when the analysis shares a jobserver, if the analysis isn't cancelled
+    /// yet, so it is neither killed nor timed out. An executor spawning local processes can run them with
+    /// [`CppDepsJob::output`].
+    fn execute_job(&self, command: &CppDepsCommand, job: &CppDepsJob<'_>) -> std::io::Result<CppDepsExecution> {
+        job.check()?;
+        #[cfg(feature = "jobserver")]
+        if let Some(jobserver) = job.jobserver() {
+            return self.execute_with_jobserver(command, jobserver);
+        }
+        self.execute(command)
+    }
+
+    /// Run the command while [`CppDeps`](crate::CppDeps) holds a job slot of the jobserver, giving the command access
+    /// to it, e.g., for `-flto=jobserver`. By default the jobserver is not passed on.
+    #[cfg(feature = "jobserver")]
+    fn execute_with_jobserver(
+        &self,
+        command: &CppDepsCommand,
+        jobserver: &jobserver::Client,
+    ) -> std::io::Result<CppDepsExecution> {
+        let _ = jobserver;
+        self.execute(command)
+    }
+}
+
+impl<E> CppDepsExecutor for alloc::sync::Arc<E>
+where
+    E: CppDepsExecutor + ?Sized,
+{
+    fn execute(&self, command: &CppDepsCommand) -> std::io::Result<CppDepsExecution> {
+        E::execute(self, command)
+    }
+
+    fn execute_job(&self, command: &CppDepsCommand, job: &CppDepsJob<'_>) -> std::io::Result<CppDepsExecution> {
+        E::execute_job(self, command, job)
+    }
+
+    #[cfg(feature = "jobserver")]
+    fn execute_with_jobserver(
+        &self,
+        command: &CppDepsCommand,
+        jobserver: &jobserver::Client,
+    ) -> std::io::Result<CppDepsExecution> {
+        E::execute_with_jobserver(self, command, jobserver)
+    }
+}
+
+/// The default [`CppDepsExecutor`], spawning each command as a local process.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct CppDepsLocalExecutor;
+impl CppDepsExecutor for CppDepsLocalExecutor {
+    fn execute(&self, command: &CppDepsCommand) -> std::io::Result<CppDepsExecution> {
+        command.to_command().output().map(CppDepsExecution::from)
+    }
+
+    fn execute_job(&self, command: &CppDepsCommand, job: &CppDepsJob<'_>) -> std::io::Result<CppDepsExecution> {
+        job.output(command.to_command())
+    }
+
+    #[cfg(feature = "jobserver")]
+    fn execute_with_jobserver(
+        &self,
+        command: &CppDepsCommand,
+        jobserver: &jobserver::Client,
+    ) -> std::io::Result<CppDepsExecution> {
+        let mut cmd = command.to_command();
+        jobserver.configure_make(&mut cmd);
+        cmd.output().map(CppDepsExecution::from)
+    }
+}
+
+/// The controls of a command run by a [`CppDepsExecutor`]: the cancellation of the analysis, the timeout of the command
+/// and the jobserver it shares.
+#[derive(Clone, Copy, Debug)]
+pub struct CppDepsJob<'a> {
+    pub(crate) cancel: &'a CppDepsCancel,
+    pub(crate) timeout: Option<Duration>,
+    #[cfg(feature = "jobserver")]
+    pub(crate) jobserver: Option<&'a jobserver::Client>,
+}
+impl CppDepsJob<'_> {
+    pub fn is_cancelled(&self) -> bool {
+        self.cancel.is_cancelled()
+    }
+
+    /// How long the command may run before it is killed. See [`CppDeps::timeout`](crate::CppDeps::timeout).
+    pub fn timeout(&self) -> Option<Duration> {
+        self.timeout
+    }
+
+    /// The jobserver the command may take tokens from, e.g., for `-flto=jobserver`. A job slot is already held for the
+    /// command itself.
+    #[cfg(feature = "jobserver")]
+    pub fn jobserver(&self) -> Option<&jobserver::Client> {
+        self.jobserver
+    }
+
+    /// Fail with [`std::io::ErrorKind::Interrupted`] if the analysis is cancelled.
+    pub fn check(&self) -> std::io::Result<()> {
+        if self.is_cancelled() {
+            return Err(std::io::Error::new(std::io::ErrorKind::Interrupted, "cancelled"));
+        }
+        Ok(())
+    }
+
+    /// Spawn the command, giving it access to the jobserver, and wait for it, capturing its standard output and standard
+    /// error like [`Command::output`].
+    ///
+    /// The process is killed along with the processes it spawned when the analysis is cancelled, failing with
+    /// [`std::io::ErrorKind::Interrupted`], or when it outlives the timeout, failing with
+    /// [`std::io::ErrorKind::TimedOut`]. Its output is then discarded.
+    ///
+    /// The process stays in the process group of the caller, so that an interrupt from the terminal reaches it as well.
+    /// The processes it spawned are instead found by their parent process ids when it is killed, which is only
+    /// supported on Linux and Windows; elsewhere only the process itself is killed.
+    pub fn output(&self, mut cmd: Command) -> std::io::Result<CppDepsExecution> {
+        self.check()?;
+        #[cfg(feature = "jobserver")]
+        if let Some(jobserver) = self.jobserver {
+            jobserver.configure_make(&mut cmd);
+        }
+        cmd.stdin(Stdio::null()).stdout(Stdio::piped()).stderr(Stdio::piped());
+        let deadline = self.timeout.map(|timeout| Instant::now() + timeout);
+        let mut child = cmd.spawn()?;
+        let pid = child.id();
+        // NOTE: read on threads of their own, which report back so that they needn't be joined without a bound
+        fn read(pipe: Option<impl Read + Send + 'static>) -> flume::Receiver<std::io::Result<Vec<u8>>> {
+            let (tx, rx) = flume::bounded(1);
+            std::thread::spawn(move || {
+                let mut buf = vec![];
+                let result = match pipe {
+                    Some(mut pipe) => pipe.read_to_end(&mut buf).map(|_| buf),
+                    None => Ok(buf),
+                };
+                tx.send(result).ok();
+            });
+            rx
+        }
+        let stdout = read(child.stdout.take());
+        let stderr = read(child.stderr.take());
+        // NOTE: waited for on a thread of its own, so that the exit, the cancellation and the timeout wake us alike
+        let (status_tx, status_rx) = flume::bounded::<std::io::Result<ExitStatus>>(1);
+        std::thread::spawn(move || {
+            status_tx.send(child.wait()).ok();
+        });
+        let selector = flume::Selector::new()
+            .recv(&status_rx, Result::ok)
+            .recv(self.cancel.wake_rx(), |_| None);
+        let status = match deadline {
+            Some(deadline) => selector.wait_deadline(deadline).ok().flatten(),
+            None => selector.wait(),
+        };
+        // NOTE: the process may have exited just as it was cancelled or timed out
+        let status = match status.or_else(|| status_rx.try_recv().ok()) {
+            Some(status) => status?,
+            None => {
+                let (kind, message) = if self.is_cancelled() {
+                    (std::io::ErrorKind::Interrupted, "cancelled")
+                } else {
+                    (std::io::ErrorKind::TimedOut, "timed out")
+                };
+                self::kill_process_tree(pid);
+                status_rx.recv().ok().transpose()?;
+                // NOTE: the pipes close once the killed processes are gone, unless a process which escaped the kill
+                // still holds them, in which case its readers are abandoned rather than waited for
+                let deadline = Instant::now() + KILLED_OUTPUT_TIMEOUT;
+                for reader in [stdout, stderr] {
+                    reader.recv_deadline(deadline).ok();
+                }
+                return Err(std::io::Error::new(kind, message));
+            },
+        };
+        let join = |reader: flume::Receiver<std::io::Result<Vec<u8>>>| {
+            reader
+                .recv()
+                .unwrap_or_else(|_| Err(std::io::Error::other("failed reading the output")))
+        };
+        let (stdout, stderr) = (join(stdout)?, join(stderr)?);
+        Ok(CppDepsExecution::new(status.code(), stdout, stderr))
+    }
+}
+
+// How long the output of a killed process is drained before its readers are abandoned.
+const KILLED_OUTPUT_TIMEOUT: Duration = Duration::from_secs(1);
+
+// Kill the process spawned by `CppDepsJob::output` and the processes it spawned in turn.
+//
+// NOTE: each process is stopped before its children are listed, so that it can't spawn more while the tree is walked
+#[cfg(target_os = "linux")]
+fn kill_process_tree(pid: u32) {
+    let mut pids = vec![pid];
+    let mut next = 0;
+    while let Some(&pid) = pids.get(next) {
+        next += 1;
+        let Ok(raw_pid) = libc::pid_t::try_from(pid) else {
+            continue;
+        };
+        unsafe { libc::kill(raw_pid, libc::SIGSTOP) };
+        let Ok(tasks) = std::fs::read_dir(format!("/proc/{pid}/task")) else {
+            continue;
+        };
+        for task in tasks.flatten() {
+            let children = std::fs::read_to_string(task.path().join("children")).unwrap_or_default();
+            pids.extend(
+                children
+                    .split_whitespace()
+                    .filter_map(|child| child.parse::<u32>().ok()),
+            );
+        }
+    }
+    for pid in pids {
+        if let Ok(pid) = libc::pid_t::try_from(pid) {
+            unsafe { libc::kill(pid, libc::SIGKILL) };
+        }
+    }
+}
+
+// Kill the process spawned by `CppDepsJob::output`. The processes it spawned in turn can't be found portably.
+#[cfg(all(unix, not(target_os = "linux")))]
+fn kill_process_tree(pid: u32) {
+    if let Ok(pid) = libc::pid_t::try_from(pid) {
+        unsafe { libc::kill(pid, libc::SIGKILL) };
+    }
+}
+
+// Kill the process spawned by `CppDepsJob::output` and the processes it spawned in turn.
+#[cfg(not(unix))]
+fn kill_process_tree(pid: u32) {
+    Command::new("taskkill")
+        .args(["/F", "/T", "/PID", &pid.to_string()])
+        .stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .status()
+        .ok();
+}
+
+#[cfg(test)]
+mod test {
+    use core::time::Duration;
+    use std::{process::Command, time::Instant};
+
+    use crate::{testing::BoxResult, CppDepsCancel, CppDepsCommand, CppDepsExecution, CppDepsExecutor, CppDepsJob};
+
+    #[test]
+    fn recording_executor() -> BoxResult<()> {
+        let paths = crate::testing::corpus::src_file::items()?;
+        let mut validate = crate::testing::corpus::src_file::validate_order(paths)?;
+        let executor = alloc::sync::Arc::new(crate::testing::RecordingExecutor::default());
+        validate.cpp_deps.executor(executor.clone());
+        let outputs = validate.cpp_deps.analyze().collect::<Result<Vec<_>, _>>()?;
+        assert!(outputs.iter().all(|output| output.execution().is_some()));
+        // NOTE: a scan and a compilation per source
+        assert_eq!(executor.commands().len(), 10);
+        Ok(())
+    }
+
+    #[test]
+    fn execution_failure() -> BoxResult<()> {
+        struct FailingExecutor;
+        impl CppDepsExecutor for FailingExecutor {
+            fn execute(&self, _command: &CppDepsCommand) -> std::io::Result<CppDepsExecution> {
+                let stderr = b"src/main.cpp:1:8: fatal error: module 'foo' not found\n1 error generated.\n";
+                Ok(CppDepsExecution::new(Some(1), vec![], stderr.to_vec()))
+            }
+        }
+        let paths = crate::testing::corpus::src_file::items()?;
+        let mut validate = crate::testing::corpus::src_file::validate_order(paths)?;
+        validate.cpp_deps.executor(FailingExecutor);
+        let err = validate
+            .cpp_deps
+            .analyze()
+            .find_map(Result::err)
+            .ok_or("expected a failure")?;
+        assert!(err.command().is_some());
+        let execution = err.execution().ok_or("missing execution")?;
+        assert_eq!(execution.code(), Some(1));
+        let diagnostics = execution.diagnostics();
+        assert_eq!(diagnostics.len(), 1);
+        assert_eq!(diagnostics[0].message(), "module 'foo' not found");
+        Ok(())
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn job_output() -> BoxResult<()> {
+        let cancel = CppDepsCancel::default();
+        let job = CppDepsJob {
+            cancel: &cancel,
+            timeout: Some(Duration::from_millis(50)),
+            #[cfg(feature = "jobserver")]
+            jobserver: None,
+        };
+        let mut echo = Command::new("sh");
+        echo.args(["-c", "echo out; echo err >&2; exit 3"]);
+        let execution = job.output(echo)?;
+        assert_eq!(execution.code(), Some(3));
+        assert_eq!(execution.stdout(), b"out\n");
+        assert_eq!(execution.stderr(), b"err\n");
+
+        let start = Instant::now();
+        let mut sleep = Command::new("sleep");
+        sleep.arg("5");
+        let err = job.output(sleep).err().ok_or("expected a timeout")?;
+        assert_eq!(err.kind(), std::io::ErrorKind::TimedOut);
+        assert!(start.elapsed() < Duration::from_secs(5));
+
+        let job = CppDepsJob { timeout: None, ..job };
+        let canceller = std::thread::spawn({
+            let cancel = cancel.clone();
+            move || {
+                std::thread::sleep(Duration::from_millis(20));
+                cancel.cancel();
+            }
+        });
+        let mut sleep = Command::new("sleep");
+        sleep.arg("5");
+        let err = job.output(sleep).err().ok_or("expected a cancellation")?;
+        assert_eq!(err.kind(), std::io::ErrorKind::Interrupted);
+        canceller.join().map_err(|_| "canceller panicked")?;
+        Ok(())
+    }
+
+    // The processes spawned by the command are killed along with it, though they share the group of the caller.
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn job_output_kills_tree() -> BoxResult<()> {
+        let cancel = CppDepsCancel::default();
+        let job = CppDepsJob {
+            cancel: &cancel,
+            timeout: Some(Duration::from_millis(50)),
+            #[cfg(feature = "jobserver")]
+            jobserver: None,
+        };
+        let out_dir = tempdir::TempDir::new("cpp-deps")?;
+        let pid_path = out_dir.path().join("pid");
+        let mut sh = Command::new("sh");
+        sh.arg("-c")
+            .arg(format!("sleep 5 & echo $! > {}; wait", pid_path.display()));
+        let err = job.output(sh).err().ok_or("expected a timeout")?;
+        assert_eq!(err.kind(), std::io::ErrorKind::TimedOut);
+        let pid = std::fs::read_to_string(&pid_path)?;
+        let stat = std::path::PathBuf::from(format!("/proc/{}/stat", pid.trim()));
+        // NOTE: the killed process is gone, or a zombie until it is reaped
+        let start = Instant::now();
+        while std::fs::read_to_string(&stat).is_ok_and(|stat| !stat.contains(") Z ")) {
+            assert!(
+                start.elapsed() < Duration::from_secs(1),
+                "the spawned process outlived the command"
+            );
+            std::thread::sleep(Duration::from_millis(10));
+        }
+
+        let job = CppDepsJob { timeout: None, ..job };
+        let mut cat = Command::new("cat");
+        cat.arg("/proc/self/stat");
+        let execution = job.output(cat)?;
+        let stat = core::str::from_utf8(execution.stdout())?;
+        // NOTE: the state, the parent process id and the process group id follow the parenthesized command name
+        let pgrp = stat.rsplit_once(") ").and_then(|(_, fields)| fields.split(' ').nth(2));
+        assert_eq!(pgrp, Some(unsafe { libc::getpgrp() }.to_string().as_str()));
+        Ok(())
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn execution_timeout() -> BoxResult<()> {
+        struct SleepingExecutor;
+        impl CppDepsExecutor for SleepingExecutor {
+            fn execute(&self, _command: &CppDepsCommand) -> std::io::Result<CppDepsExecution> {
+                unreachable!("jobs run with `execute_job`")
+            }
+
+            fn execute_job(
+                &self,
+                _command: &CppDepsCommand,
+                job: &CppDepsJob<'_>,
+            ) -> std::io::Result<CppDepsExecution> {
+                let mut sleep = Command::new("sleep");
+                sleep.arg("5");
+                job.output(sleep)
+            }
+        }
+        let paths = crate::testing::corpus::src_file::items()?;
+        let mut validate = crate::testing::corpus::src_file::validate_order(paths)?;
+        validate.cpp_deps.executor(SleepingExecutor);
+        validate.cpp_deps.timeout(Some(Duration::from_millis(50)));
+        let err = validate
+            .cpp_deps
+            .analyze()
+            .find_map(Result::err)
+            .ok_or("expected a timeout")?;
+        assert!(err.is_timed_out());
+        assert!(err.command().is_some());
+        Ok(())
+    }
+}
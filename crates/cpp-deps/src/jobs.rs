@@ -0,0 +1,201 @@
+use alloc::sync::Arc;
+use std::sync::{Condvar, Mutex, MutexGuard, OnceLock, PoisonError};
+
+use jobserver::{Acquired, Client, HelperThread};
+
+use crate::{InnerError, InnerErrorKind};
+
+/// The job slots shared by the workers of an analysis.
+///
+/// Like every client of a jobserver, the process owns one implicit slot and acquires a token from the jobserver for
+/// each further job. Tokens are requested through a helper thread, so that a worker waiting for one still takes the
+/// implicit slot as soon as it is released, and no worker blocks on the jobserver once the analysis ends.
+pub(crate) struct Jobserver {
+    client: Client,
+    slots: Arc<Slots>,
+    helper: HelperThread,
+}
+
+struct Slots {
+    state: Mutex<SlotState>,
+    available: Condvar,
+}
+impl Slots {
+    fn lock(&self) -> MutexGuard<'_, SlotState> {
+        self.state.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+struct SlotState {
+    implicit: bool,
+    tokens: Vec<Acquired>,
+    waiting: usize,
+    requested: usize,
+}
+
+/// A job slot, released when dropped.
+pub(crate) struct JobSlot<'a> {
+    slots: &'a Slots,
+    token: Option<Acquired>,
+}
+impl Drop for JobSlot<'_> {
+    fn drop(&mut self) {
+        // NOTE: an acquired token goes back to the jobserver on drop, where it may serve another process
+        if self.token.take().is_none() {
+            self.slots.lock().implicit = true;
+            self.slots.available.notify_one();
+        }
+    }
+}
+
+impl Jobserver {
+    pub(crate) fn new(client: Client) -> Result<Self, InnerError> {
+        let slots = Arc::new(Slots {
+            state: Mutex::new(SlotState {
+                implicit: true,
+                tokens: vec![],
+                waiting: 0,
+                requested: 0,
+            }),
+            available: Condvar::new(),
+        });
+        let helper = client
+            .clone()
+            .into_helper_thread({
+                let slots = slots.clone();
+                move |token| {
+                    let mut state = slots.lock();
+                    state.requested = state.requested.saturating_sub(1);
+                    // NOTE: a token arriving after its worker took the implicit slot is released right away
+                    if let (Ok(token), true) = (token, state.waiting > 0) {
+                        state.tokens.push(token);
+                        drop(state);
+                        slots.available.notify_one();
+                    }
+                }
+            })
+            .map_err(|err| InnerError::new(InnerErrorKind::JobserverHelperThread { err }))?;
+        Ok(Self { client, slots, helper })
+    }
+
+    /// Connect to the jobserver inherited through `CARGO_MAKEFLAGS`, `MAKEFLAGS` or `MFLAGS`, if any.
+    pub(crate) fn inherited() -> Result<Option<Arc<Self>>, InnerError> {
+        // NOTE: the descriptors are only claimed once per process, as `cc` does for its own jobs
+        static CLIENT: OnceLock<Option<Client>> = OnceLock::new();
+        let client = CLIENT.get_or_init(|| unsafe { Client::from_env() }).clone();
+        client.map(|client| Self::new(client).map(Arc::new)).transpose()
+    }
+
+    pub(crate) fn client(&self) -> &Client {
+        &self.client
+    }
+
+    /// Wait for a free job slot.
+    pub(crate) fn acquire(&self) -> JobSlot<'_> {
+        let mut state = self.slots.lock();
+        loop {
+            if core::mem::take(&mut state.implicit) {
+                return JobSlot {
+                    slots: &self.slots,
+                    token: None,
+                };
+            }
+            if let Some(token) = state.tokens.pop() {
+                return JobSlot {
+                    slots: &self.slots,
+                    token: Some(token),
+                };
+            }
+            if state.requested <= state.waiting {
+                state.requested += 1;
+                self.helper.request_token();
+            }
+            state.waiting += 1;
+            state = self.slots.available.wait(state).unwrap_or_else(PoisonError::into_inner);
+            state.waiting -= 1;
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use alloc::sync::Arc;
+    use core::{
+        sync::atomic::{AtomicUsize, Ordering},
+        time::Duration,
+    };
+    use std::sync::Mutex;
+
+    use crate::{testing::BoxResult, CppDepsCommand, CppDepsExecution, CppDepsExecutor};
+
+    // Answers like the `RecordingExecutor`, while measuring how many commands run at once.
+    #[derive(Default)]
+    struct ConcurrencyExecutor {
+        inner: crate::testing::RecordingExecutor,
+        running: AtomicUsize,
+        peak: AtomicUsize,
+        makeflags: Mutex<Vec<Option<std::ffi::OsString>>>,
+    }
+    impl CppDepsExecutor for ConcurrencyExecutor {
+        fn execute(&self, command: &CppDepsCommand) -> std::io::Result<CppDepsExecution> {
+            let running = self.running.fetch_add(1, Ordering::SeqCst) + 1;
+            self.peak.fetch_max(running, Ordering::SeqCst);
+            std::thread::sleep(Duration::from_millis(10));
+            let execution = self.inner.execute(command);
+            self.running.fetch_sub(1, Ordering::SeqCst);
+            execution
+        }
+
+        fn execute_with_jobserver(
+            &self,
+            command: &CppDepsCommand,
+            jobserver: &jobserver::Client,
+        ) -> std::io::Result<CppDepsExecution> {
+            let mut cmd = command.to_command();
+            jobserver.configure_make(&mut cmd);
+            let makeflags = cmd
+                .get_envs()
+                .find_map(|(key, val)| (key == "MAKEFLAGS").then(|| val.map(ToOwned::to_owned)))
+                .flatten();
+            self.makeflags.lock().unwrap().push(makeflags);
+            self.execute(command)
+        }
+    }
+
+    #[test]
+    fn jobserver_limits_jobs() -> BoxResult<()> {
+        let paths = crate::testing::corpus::src_file::items()?;
+        let mut validate = crate::testing::corpus::src_file::validate_order(paths)?;
+        let executor = Arc::new(ConcurrencyExecutor::default());
+        validate.cpp_deps.executor(executor.clone());
+        validate.cpp_deps.parallelism(4)?;
+        // NOTE: one token in the jobserver and the implicit slot
+        validate.cpp_deps.jobserver(Some(jobserver::Client::new(1)?))?;
+        validate.run()?;
+
+        assert_eq!(executor.inner.commands().len(), 10);
+        assert!(executor.peak.load(Ordering::SeqCst) <= 2);
+        let makeflags = executor.makeflags.lock().unwrap();
+        assert_eq!(makeflags.len(), 10);
+        assert!(makeflags.iter().all(|flags| {
+            flags
+                .as_ref()
+                .is_some_and(|flags| flags.to_string_lossy().contains("--jobserver-auth="))
+        }));
+        Ok(())
+    }
+
+    #[test]
+    fn jobserver_implicit_slot() -> BoxResult<()> {
+        let jobserver = super::Jobserver::new(jobserver::Client::new(0)?)?;
+        let slot = jobserver.acquire();
+        std::thread::scope(|scope| {
+            let waiter = scope.spawn(|| drop(jobserver.acquire()));
+            std::thread::sleep(Duration::from_millis(10));
+            assert!(!waiter.is_finished());
+            drop(slot);
+            waiter.join().map_err(|_| "waiter panicked")
+        })?;
+        Ok(())
+    }
+}
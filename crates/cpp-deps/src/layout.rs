@@ -0,0 +1,126 @@
+use p1689::r5;
+
+// NOTE: the directory of the sources which aren't under their `src_base`
+const EXTERNAL_DIR: &str = "external";
+
+/// Where the scans, objects and BMIs of the units are written. See [`CppDeps::layout`](crate::CppDeps::layout).
+///
+/// By default everything is written to the output directory, at the path of the source relative to its `src_base`, and
+/// each BMI next to its object file. The directories are relative to the output directory, unless absolute. A source
+/// outside of its `src_base`, e.g., a generated source, is placed under `external/<hash>/` instead, where the hash is
+/// that of the directory the source is in.
+#[derive(Clone, Debug, Default)]
+pub struct CppDepsLayout {
+    scan_dir: Option<r5::Utf8PathBuf>,
+    obj_dir: Option<r5::Utf8PathBuf>,
+    bmi_dir: Option<r5::Utf8PathBuf>,
+}
+impl CppDepsLayout {
+    /// Write the P1689 files, and the dependency files of the scans, to `dir`.
+    pub fn with_scan_dir(mut self, dir: impl Into<r5::Utf8PathBuf>) -> Self {
+        self.scan_dir = Some(dir.into());
+        self
+    }
+
+    /// Write the object files to `dir`.
+    pub fn with_obj_dir(mut self, dir: impl Into<r5::Utf8PathBuf>) -> Self {
+        self.obj_dir = Some(dir.into());
+        self
+    }
+
+    /// Write every BMI to `dir`, named after the module rather than the source, e.g., `foo-part.pcm` for `foo:part`.
+    ///
+    /// The directory is then the only one searched for the BMIs of imported modules. This applies to clang; gcc keeps
+    /// its BMIs in `gcm.cache` under the working directory of each compile command, where its default module mapper
+    /// looks for them.
+    pub fn with_bmi_dir(mut self, dir: impl Into<r5::Utf8PathBuf>) -> Self {
+        self.bmi_dir = Some(dir.into());
+        self
+    }
+
+    pub(crate) fn scan_path(&self, out_dir: &r5::Utf8Path, base: &r5::Utf8Path, src: &r5::Utf8Path) -> r5::Utf8PathBuf {
+        self::artifact_path(&Self::dir(out_dir, self.scan_dir.as_deref()), base, src)
+    }
+
+    pub(crate) fn obj_path(&self, out_dir: &r5::Utf8Path, base: &r5::Utf8Path, src: &r5::Utf8Path) -> r5::Utf8PathBuf {
+        self::artifact_path(&Self::dir(out_dir, self.obj_dir.as_deref()), base, src)
+    }
+
+    pub(crate) fn bmi_dir(&self, out_dir: &r5::Utf8Path) -> Option<r5::Utf8PathBuf> {
+        self.bmi_dir.as_deref().map(|dir| out_dir.join(dir))
+    }
+
+    fn dir(out_dir: &r5::Utf8Path, dir: Option<&r5::Utf8Path>) -> r5::Utf8PathBuf {
+        dir.map_or_else(|| out_dir.to_path_buf(), |dir| out_dir.join(dir))
+    }
+}
+
+/// The file name of the BMI of a module, without extension, as clang and gcc look it up by default.
+pub(crate) fn mangle(name: &str) -> String {
+    name.replace(':', "-")
+}
+
+// The path of a source's artifact under `dir`, before its extension is replaced.
+fn artifact_path(dir: &r5::Utf8Path, base: &r5::Utf8Path, src: &r5::Utf8Path) -> r5::Utf8PathBuf {
+    // NOTE: a relative path climbing out of `src_base` would otherwise climb out of `dir` as well
+    if let Ok(relative) = src.strip_prefix(base) {
+        if relative
+            .as_std_path()
+            .components()
+            .all(|component| matches!(component, std::path::Component::Normal(_)))
+        {
+            return dir.join(relative);
+        }
+    }
+    // NOTE: a stable hash, so that the paths don't change with the Rust release building the crate
+    let hash = crate::state::stable_hash(src.parent().map_or("", r5::Utf8Path::as_str).as_bytes());
+    dir.join(EXTERNAL_DIR)
+        .join(format!("{hash:016x}"))
+        .join(src.file_name().unwrap_or("unit"))
+}
+
+#[cfg(test)]
+mod test {
+    use p1689::r5::{Utf8Path, Utf8PathBuf};
+
+    use crate::testing::BoxResult;
+
+    #[test]
+    fn artifact_paths() -> BoxResult<()> {
+        let out_dir = Utf8Path::new("/out");
+        let base = Utf8Path::new("/src");
+        let layout = super::CppDepsLayout::default()
+            .with_scan_dir("scans")
+            .with_obj_dir("/objs");
+        assert_eq!(
+            layout.scan_path(out_dir, base, Utf8Path::new("/src/foo/bar.cppm")),
+            "/out/scans/foo/bar.cppm"
+        );
+        assert_eq!(
+            layout.obj_path(out_dir, base, Utf8Path::new("/src/foo/bar.cppm")),
+            "/objs/foo/bar.cppm"
+        );
+        assert_eq!(layout.bmi_dir(out_dir), None);
+
+        // Sources outside of the base share a directory per directory they are in.
+        let external = |src: &str| layout.obj_path(out_dir, base, Utf8Path::new(src));
+        let gen = external("/gen/foo.cppm");
+        assert_eq!(gen, "/objs/external/019b569c958e83fc/foo.cppm");
+        assert_eq!(gen.file_name(), Some("foo.cppm"));
+        assert_eq!(gen.parent(), external("/gen/bar.cppm").parent());
+        assert_ne!(gen.parent(), external("/other/foo.cppm").parent());
+        assert!(external("/src/../gen/foo.cppm").starts_with("/objs/external"));
+        Ok(())
+    }
+
+    #[test]
+    fn bmi_dir() -> BoxResult<()> {
+        let layout = super::CppDepsLayout::default().with_bmi_dir("bmi");
+        assert_eq!(
+            layout.bmi_dir(Utf8Path::new("/out")),
+            Some(Utf8PathBuf::from("/out/bmi"))
+        );
+        assert_eq!(super::mangle("foo.bar:part1"), "foo.bar-part1");
+        Ok(())
+    }
+}
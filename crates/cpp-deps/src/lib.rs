@@ -3,13 +3,12 @@
 // - support adding custom module mappings
 // - handle error propagation
 // - add test for clang with both .cpp and .cppm (to ensure `is-interface` is used correctly)
-// - don't clobber std setting (but ensure c++20 is set)
-// - replace `pending_count` with a set of names
-//   - we can use this information to determine which compilation tasks failed and remove them from the set on failure, so we don't prevent shutdown and deadlock
 
 // NOTE:
 // - all errors except for send and thread errors are forwarded through the iterator
 // - send and thread errors are returned by the iterator at the end if an error occurred
+// - with `keep_going`, scan and compile failures are yielded as they occur and the iterator continues; units depending
+//   on a failed unit are skipped, and the final item is an error carrying the `CppDepsSummary`
 
 // NOTE:
 // - gcc does not populate the source-path field, thus we must track paths separately
@@ -17,30 +16,79 @@
 extern crate alloc;
 
 mod analyzer;
+mod cancel;
+#[cfg(feature = "cc")]
+mod collate;
+#[cfg(feature = "compile_commands")]
+mod compile_commands;
 #[cfg(feature = "cc")]
 mod compiler;
+mod diagnostic;
+#[cfg(feature = "cc")]
+mod executor;
+#[cfg(feature = "jobserver")]
+mod jobs;
+#[cfg(feature = "cc")]
+mod layout;
+#[cfg(feature = "cc")]
+mod make;
+#[cfg(feature = "cc")]
+mod ninja;
+mod observer;
+mod output;
+#[cfg(feature = "plan")]
+mod plan;
+#[cfg(feature = "cc")]
+mod probe;
 mod queue;
+mod schedule;
 #[cfg(feature = "sink")]
 mod sink;
+#[cfg(feature = "cc")]
+mod state;
 #[cfg(test)]
 mod testing;
+#[cfg(feature = "trace")]
+mod trace;
 mod vendor;
 mod worker;
 
-#[cfg(feature = "cc")]
 use alloc::sync::Arc;
 use core::{marker::PhantomData, num::NonZeroUsize};
 
 use p1689::r5::{self, yoke::DepInfoYoke};
 use queue::TaskQueue;
 
-pub use crate::analyzer::CppDepsAnalyzer;
-#[cfg(feature = "cc")]
-use crate::compiler::Compiler;
+use crate::analyzer::AnalyzerOptions;
+#[cfg(feature = "plan")]
+pub use crate::plan::{CppDepsPlan, CppDepsStep};
 #[cfg(feature = "sink")]
 pub use crate::sink::CppDepsSink;
+#[cfg(feature = "trace")]
+pub use crate::trace::{CppDepsTrace, CppDepsTraceReport, CppDepsUnitTiming};
+pub use crate::{
+    analyzer::{CppDepsAnalyzer, CppDepsBlocked, CppDepsCycle, CppDepsDuplicate, CppDepsMissing, CppDepsSummary},
+    cancel::CppDepsCancel,
+    diagnostic::{CppDepsDiagnostic, CppDepsFrame, CppDepsLocation, CppDepsSeverity},
+    observer::{CppDepsEvent, CppDepsObserver},
+    output::{CppDepsCommand, CppDepsExecution, CppDepsOutput},
+};
+#[cfg(feature = "cc")]
+use crate::{
+    compiler::{Compiler, CompilerConfig},
+    state::BuildState,
+};
+#[cfg(feature = "cc")]
+pub use crate::{
+    executor::{CppDepsExecutor, CppDepsJob, CppDepsLocalExecutor},
+    layout::CppDepsLayout,
+    make::CppDepsMake,
+    ninja::CppDepsNinja,
+    probe::CppDepsProbe,
+};
 
 #[cfg(feature = "memchr")]
+#[derive(Clone)]
 struct Finders {
     #[cfg(target_feature = "avx2")]
     dotted: memchr::arch::x86_64::avx2::memchr::One,
@@ -87,9 +135,18 @@ impl core::fmt::Debug for InnerError {
 #[derive(Debug)]
 enum InnerErrorKind {
     AnalyzerAlreadyReceivedExpectsCount,
+    AnalyzerFinishedWithFailures {
+        summary: Box<CppDepsSummary>,
+    },
     #[cfg(feature = "cc")]
     AnalyzerFailedSendingCompileItem,
     BuilderFailedSendingCppDepsItem,
+    Cancelled,
+    #[cfg(feature = "cc")]
+    CcTryCompile {
+        #[allow(unused)]
+        err: cc::Error,
+    },
     #[cfg(feature = "cc")]
     CcTryGetCompiler {
         #[allow(unused)]
@@ -101,17 +158,62 @@ enum InnerErrorKind {
         err: std::io::Error,
     },
     #[cfg(feature = "cc")]
-    CommandCompilerNonZeroExit,
+    CommandCompilerNonZeroExit {
+        failed: Box<(CppDepsCommand, CppDepsExecution)>,
+    },
+    #[cfg(feature = "cc")]
+    CommandTimedOut {
+        timed_out: Box<(CppDepsCommand, Option<core::time::Duration>)>,
+    },
+    #[cfg(feature = "compile_commands")]
+    CompileCommandsInvalidEntry,
+    #[cfg(feature = "compile_commands")]
+    CompileCommandsJson {
+        #[allow(unused)]
+        err: serde_json::Error,
+    },
     #[cfg(feature = "cc")]
     CompilerFamilyTryFromUnknownFamily,
+    #[cfg(feature = "cc")]
+    CompilerFeatureUnsupported {
+        hint: &'static str,
+        version: Option<Box<str>>,
+        failed: Option<Box<(CppDepsCommand, CppDepsExecution)>>,
+    },
+    #[cfg(feature = "cc")]
+    CompilerMultipleModuleOutputs {
+        provides: Box<[String]>,
+    },
+    #[cfg(feature = "cc")]
+    CompilerStdBelowCxx20 {
+        std: Box<str>,
+    },
+    #[cfg(feature = "cc")]
+    CompilerStdUnsupported {
+        std: Box<str>,
+        failed: Box<(CppDepsCommand, CppDepsExecution)>,
+    },
     DepFileParse,
+    DuplicateModuleProvider {
+        duplicate: Box<CppDepsDuplicate>,
+    },
+    #[cfg(feature = "compile_commands")]
+    EnvCurrentDir {
+        #[allow(unused)]
+        err: std::io::Error,
+    },
     #[cfg(feature = "cc")]
     EnvVar {
         #[allow(unused)]
         err: std::env::VarError,
     },
     #[cfg(feature = "cc")]
-    FileCreate {
+    FileRead {
+        #[allow(unused)]
+        err: std::io::Error,
+    },
+    #[cfg(feature = "cc")]
+    FileWrite {
         #[allow(unused)]
         err: std::io::Error,
     },
@@ -119,6 +221,16 @@ enum InnerErrorKind {
         #[allow(unused)]
         err: std::io::Error,
     },
+    #[cfg(feature = "jobserver")]
+    JobserverHelperThread {
+        #[allow(unused)]
+        err: std::io::Error,
+    },
+    #[cfg(feature = "jobserver")]
+    JobserverNew {
+        #[allow(unused)]
+        err: std::io::Error,
+    },
     #[cfg(feature = "cc")]
     FsCreateDirAll {
         #[allow(unused)]
@@ -130,18 +242,24 @@ enum InnerErrorKind {
         #[allow(unused)]
         err: std::io::Error,
     },
+    #[cfg(feature = "cc")]
+    NinjaManifestParse,
+    #[cfg(feature = "cc")]
+    NinjaUnsupportedItem,
     NonZeroUsizeTryFromUsize {
         #[allow(unused)]
         err: core::num::TryFromIntError,
     },
-    #[cfg(feature = "cc")]
-    PathStripPrefix {
-        #[allow(unused)]
-        err: std::path::StripPrefixError,
+    OrderingSolutionBlocked {
+        blocked: Box<CppDepsBlocked>,
     },
-    OrderingSolutionBlocked,
     #[cfg(all(feature = "cc", feature = "sink"))]
     SinkFailedSendingCppDepsItem,
+    #[cfg(feature = "cc")]
+    StateWrite {
+        #[allow(unused)]
+        err: std::io::Error,
+    },
     QueueFailedSendingCompileItem,
     WorkerFailedSendingAnalyzerItem,
     WorkerFailedSendingExpects,
@@ -152,8 +270,140 @@ impl core::fmt::Display for InnerError {
     }
 }
 impl std::error::Error for InnerError {}
+// NOTE: the kinds carrying commands, summaries or diagnostics get a message of their own, leaving the details to the
+// `Display` of `Error`; the others are described well enough by their name
+impl core::fmt::Display for InnerErrorKind {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        #[cfg(feature = "cc")]
+        fn command_line(command: &CppDepsCommand) -> String {
+            core::iter::once(command.program())
+                .chain(command.args())
+                .map(|arg| arg.to_string_lossy())
+                .collect::<Vec<_>>()
+                .join(" ")
+        }
+        match self {
+            InnerErrorKind::AnalyzerFinishedWithFailures { summary } => write!(
+                f,
+                "{} units failed, {} could not be scanned and {} were skipped",
+                summary.failed().len(),
+                summary.failed_scans().len(),
+                summary.skipped().len()
+            ),
+            InnerErrorKind::Cancelled => write!(f, "the analysis was cancelled"),
+            #[cfg(feature = "cc")]
+            InnerErrorKind::CommandCompilerNonZeroExit { failed } => match failed.1.code() {
+                Some(code) => write!(f, "`{}` exited with code {code}", command_line(&failed.0)),
+                None => write!(f, "`{}` was terminated by a signal", command_line(&failed.0)),
+            },
+            #[cfg(feature = "cc")]
+            InnerErrorKind::CommandTimedOut { timed_out } => match timed_out.1 {
+                Some(timeout) => write!(f, "`{}` timed out after {timeout:?}", command_line(&timed_out.0)),
+                None => write!(f, "`{}` timed out", command_line(&timed_out.0)),
+            },
+            #[cfg(feature = "cc")]
+            InnerErrorKind::CompilerFeatureUnsupported { hint, version, .. } => match version {
+                Some(version) => write!(f, "{hint} (found version {version})"),
+                None => write!(f, "{hint}"),
+            },
+            #[cfg(feature = "cc")]
+            InnerErrorKind::CompilerMultipleModuleOutputs { provides } => write!(
+                f,
+                "the BMIs of the modules `{}` provided by one unit would be written to the same path",
+                provides.join("`, `")
+            ),
+            #[cfg(feature = "cc")]
+            InnerErrorKind::CompilerStdBelowCxx20 { std } => {
+                write!(
+                    f,
+                    "`{std}` selects a C++ standard older than C++20, which modules require"
+                )
+            },
+            #[cfg(feature = "cc")]
+            InnerErrorKind::CompilerStdUnsupported { std, .. } => write!(f, "the compiler doesn't accept `{std}`"),
+            InnerErrorKind::DuplicateModuleProvider { .. } => write!(f, "a module has more than one provider"),
+            InnerErrorKind::OrderingSolutionBlocked { .. } => write!(f, "the remaining units can't be ordered"),
+            kind => core::fmt::Debug::fmt(kind, f),
+        }
+    }
+}
 
 pub struct Error(InnerError);
+impl Error {
+    /// The missing providers and import cycles which prevented ordering the remaining units.
+    pub fn blocked(&self) -> Option<&CppDepsBlocked> {
+        match &self.0.kind {
+            InnerErrorKind::OrderingSolutionBlocked { blocked } => Some(blocked),
+            _ => None,
+        }
+    }
+
+    /// The conflicting providers of a module when duplicate providers are not allowed.
+    pub fn duplicate(&self) -> Option<&CppDepsDuplicate> {
+        match &self.0.kind {
+            InnerErrorKind::DuplicateModuleProvider { duplicate } => Some(duplicate),
+            _ => None,
+        }
+    }
+
+    /// The command which exited unsuccessfully or was killed after its timeout.
+    #[cfg(feature = "cc")]
+    pub fn command(&self) -> Option<&CppDepsCommand> {
+        match &self.0.kind {
+            InnerErrorKind::CommandCompilerNonZeroExit { failed } => Some(&failed.0),
+            InnerErrorKind::CompilerFeatureUnsupported {
+                failed: Some(failed), ..
+            } => Some(&failed.0),
+            InnerErrorKind::CompilerStdUnsupported { failed, .. } => Some(&failed.0),
+            InnerErrorKind::CommandTimedOut { timed_out } => Some(&timed_out.0),
+            _ => None,
+        }
+    }
+
+    /// Whether the analysis was cancelled through a [`CppDepsCancel`].
+    pub fn is_cancelled(&self) -> bool {
+        matches!(self.0.kind, InnerErrorKind::Cancelled)
+    }
+
+    /// Whether a command was killed after running longer than the timeout. See [`CppDeps::timeout`].
+    #[cfg(feature = "cc")]
+    pub fn is_timed_out(&self) -> bool {
+        matches!(self.0.kind, InnerErrorKind::CommandTimedOut { .. })
+    }
+
+    /// Whether the selected C++ standard can't be used, either because it predates C++20, which modules require, or
+    /// because the compiler rejects it.
+    #[cfg(feature = "cc")]
+    pub fn is_std_unsupported(&self) -> bool {
+        matches!(
+            self.0.kind,
+            InnerErrorKind::CompilerStdBelowCxx20 { .. } | InnerErrorKind::CompilerStdUnsupported { .. }
+        )
+    }
+
+    /// The exit code and captured output of the command which exited unsuccessfully.
+    ///
+    /// The diagnostics are available through [`CppDepsExecution::diagnostics`].
+    #[cfg(feature = "cc")]
+    pub fn execution(&self) -> Option<&CppDepsExecution> {
+        match &self.0.kind {
+            InnerErrorKind::CommandCompilerNonZeroExit { failed } => Some(&failed.1),
+            InnerErrorKind::CompilerFeatureUnsupported {
+                failed: Some(failed), ..
+            } => Some(&failed.1),
+            InnerErrorKind::CompilerStdUnsupported { failed, .. } => Some(&failed.1),
+            _ => None,
+        }
+    }
+
+    /// The summary of a `keep_going` analysis which finished with failures.
+    pub fn summary(&self) -> Option<&CppDepsSummary> {
+        match &self.0.kind {
+            InnerErrorKind::AnalyzerFinishedWithFailures { summary } => Some(summary),
+            _ => None,
+        }
+    }
+}
 impl core::fmt::Debug for Error {
     fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
         core::fmt::Debug::fmt(&self.0, f)
@@ -161,7 +411,17 @@ impl core::fmt::Debug for Error {
 }
 impl core::fmt::Display for Error {
     fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
-        writeln!(f, "error: {:?}", self.0.kind)?;
+        writeln!(f, "error: {}", self.0.kind)?;
+        if let Some(blocked) = self.blocked() {
+            write!(f, "{blocked}")?;
+        }
+        if let Some(duplicate) = self.duplicate() {
+            write!(f, "{duplicate}")?;
+        }
+        #[cfg(feature = "cc")]
+        if let Some(execution) = self.execution() {
+            write!(f, "{}", String::from_utf8_lossy(execution.stderr()))?;
+        }
         if matches!(self.0.backtrace.status(), std::backtrace::BacktraceStatus::Captured) {
             writeln!(f, "backtrace:\n{}", self.0.backtrace)?;
         }
@@ -179,10 +439,29 @@ impl From<InnerError> for Error {
 pub struct CppDepsSrc<P> {
     pub src_base: P,
     pub src_path: P,
+    command: Option<Box<CppDepsCommand>>,
 }
 impl<P> CppDepsSrc<P> {
     pub fn new(src_base: P, src_path: P) -> Self {
-        Self { src_base, src_path }
+        Self {
+            src_base,
+            src_path,
+            command: None,
+        }
+    }
+
+    /// Scan and compile the source with its own command, e.g., from a compilation database.
+    ///
+    /// The command is used instead of the configured compiler. Its arguments must not name the source, the outputs or
+    /// any module flags, since those are added for each step.
+    pub fn with_command(mut self, command: CppDepsCommand) -> Self {
+        self.command = Some(Box::new(command));
+        self
+    }
+
+    /// The compiler invocation for this source, if it has its own. See [`CppDepsSrc::with_command`].
+    pub fn command(&self) -> Option<&CppDepsCommand> {
+        self.command.as_deref()
     }
 }
 
@@ -211,8 +490,17 @@ pub enum CppDepsItem<P, B> {
 
 pub struct CppDeps<P = r5::Utf8PathBuf, B = Vec<u8>> {
     #[cfg(feature = "cc")]
-    compiler: Arc<Compiler>,
+    compiler: CompilerConfig,
+    #[cfg(feature = "cc")]
+    dry_run: bool,
+    #[cfg(feature = "cc")]
+    incremental: bool,
+    #[cfg(feature = "compile_commands")]
+    write_compile_commands: bool,
+    observer: Option<Arc<dyn CppDepsObserver>>,
+    cancel: CppDepsCancel,
     parallelism: NonZeroUsize,
+    options: AnalyzerOptions,
     cppdeps_tx: flume::Sender<CppDepsItem<P, B>>,
     cppdeps_rx: flume::Receiver<CppDepsItem<P, B>>,
     p: PhantomData<P>,
@@ -226,19 +514,30 @@ where
 {
     pub fn new() -> Result<CppDeps<P, B>, Error> {
         let (cppdeps_tx, cppdeps_rx) = flume::unbounded();
+        let cancel = CppDepsCancel::default();
         #[cfg(feature = "cc")]
-        let compiler = {
-            let build = cc::Build::default();
-            let compiler = Compiler::new(build)?;
-            Arc::from(compiler)
-        };
+        let compiler = CompilerConfig::new(
+            Arc::new(CppDepsLocalExecutor),
+            cancel.clone(),
+            #[cfg(feature = "jobserver")]
+            crate::jobs::Jobserver::inherited()?,
+        );
         let parallelism = std::thread::available_parallelism()
             .or(NonZeroUsize::try_from(1)
                 .map_err(|err| InnerError::new(InnerErrorKind::NonZeroUsizeTryFromUsize { err })))?;
         Ok(CppDeps {
             #[cfg(feature = "cc")]
             compiler,
+            #[cfg(feature = "cc")]
+            dry_run: false,
+            #[cfg(feature = "cc")]
+            incremental: false,
+            #[cfg(feature = "compile_commands")]
+            write_compile_commands: false,
+            observer: None,
+            cancel,
             parallelism,
+            options: AnalyzerOptions::default(),
             cppdeps_tx,
             cppdeps_rx,
             p: PhantomData,
@@ -252,25 +551,199 @@ where
     P: AsRef<r5::Utf8Path> + Send + Sync + 'static,
     B: AsRef<[u8]> + Send + Sync + 'static,
 {
+    #[cfg(feature = "cc")]
+    fn analyze(self) -> CppDepsAnalyzer<P, B> {
+        match self.compiler.resolve() {
+            Ok(compiler) => self.start(Arc::new(compiler)),
+            // NOTE: the analyzer yields the error, so that configuring the compiler can't fail before it starts
+            Err(err) => CppDepsAnalyzer::new(
+                TaskQueue::failed(err),
+                self.options,
+                self.observer,
+                self.cancel,
+                self.compiler.env_vars(),
+                #[cfg(feature = "compile_commands")]
+                None,
+            ),
+        }
+    }
+
+    #[cfg(not(feature = "cc"))]
     fn analyze(self) -> CppDepsAnalyzer<P, B> {
+        self.start()
+    }
+
+    fn start(self, #[cfg(feature = "cc")] compiler: Arc<Compiler>) -> CppDepsAnalyzer<P, B> {
         let parallelism = self.parallelism.get();
+        #[cfg(feature = "cc")]
+        let state = self.incremental.then(|| Arc::new(BuildState::load(compiler.out_dir())));
+        #[cfg(feature = "compile_commands")]
+        let compile_commands = self.write_compile_commands.then(|| {
+            let path = compiler
+                .out_dir()
+                .join(crate::compile_commands::COMPILE_COMMANDS_FILE_NAME);
+            crate::compile_commands::CompileCommands::new(path)
+        });
         let queue = TaskQueue::new(
             self.cppdeps_rx,
+            self.observer.clone(),
+            self.cancel.clone(),
+            #[cfg(feature = "cc")]
+            compiler,
             #[cfg(feature = "cc")]
-            self.compiler,
+            state,
+            #[cfg(feature = "cc")]
+            self.dry_run,
             parallelism,
         );
-        CppDepsAnalyzer::new(queue)
+        #[cfg(feature = "cc")]
+        let env_vars = self.compiler.env_vars();
+        #[cfg(not(feature = "cc"))]
+        let env_vars = vec![];
+        CppDepsAnalyzer::new(
+            queue,
+            self.options,
+            self.observer,
+            self.cancel,
+            env_vars,
+            #[cfg(feature = "compile_commands")]
+            compile_commands,
+        )
+    }
+
+    /// Allow more than one unit to provide the same module.
+    ///
+    /// By default this is an error naming both providers, since dependents could otherwise be compiled against the
+    /// wrong BMI. Enable it when the duplicates are intentional, e.g., the same module built for separate
+    /// configurations; dependents then use whichever provider resolves first.
+    pub fn allow_duplicate_providers(&mut self, allow: bool) {
+        self.options.allow_duplicate_providers = allow;
+    }
+
+    /// Run the analysis to completion and archive the compiled objects into a static library.
+    ///
+    /// The library is written to the output directory as by [`cc::Build::compile`](crate::vendor::cc::Build::compile),
+    /// including the `cargo:rustc-link-lib` and `cargo:rustc-link-search` lines, so a build script can go from module
+    /// sources to a linkable library in one call. Returns the outputs in dependency order.
+    #[cfg(feature = "cc")]
+    pub fn compile(self, lib_name: &str) -> Result<Vec<CppDepsOutput>, Error> {
+        let compiler = Arc::new(self.compiler.resolve()?);
+        let outputs = self.start(compiler.clone()).collect::<Result<Vec<_>, _>>()?;
+        compiler.archive(lib_name, outputs.iter().filter_map(CppDepsOutput::obj_path))?;
+        Ok(outputs)
+    }
+
+    /// Add a [`CppDepsItem::SrcFile`] for each C++ source under `src_base` in a `compile_commands.json` compilation
+    /// database, as written by CMake or Bear.
+    ///
+    /// Each source is scanned and compiled with the program, arguments and directory of its entry instead of the
+    /// configured compiler, so the module graph is the one the real build sees. The source, outputs, dependency files
+    /// and module flags of an entry are replaced by those of each step, objects are still written to the output
+    /// directory, and the compiler family is still that of the configured compiler. Entries for other languages or for
+    /// files outside of `src_base` are skipped, as are further entries for the same file.
+    #[cfg(feature = "compile_commands")]
+    pub fn compile_commands(
+        &mut self,
+        path: impl AsRef<r5::Utf8Path>,
+        src_base: impl AsRef<r5::Utf8Path>,
+    ) -> Result<(), Error>
+    where
+        P: From<r5::Utf8PathBuf>,
+    {
+        let src_files = crate::compile_commands::load(path.as_ref(), src_base.as_ref())?;
+        self.items(src_files.into_iter().map(|src_file| {
+            let src_file = CppDepsSrc {
+                src_base: P::from(src_file.src_base),
+                src_path: P::from(src_file.src_path),
+                command: src_file.command,
+            };
+            CppDepsItem::SrcFile { src_file }
+        }))
     }
 
-    // FIXME: check for `std >= 20`
+    /// A handle to cancel the analysis from another thread. See [`CppDepsCancel`].
+    pub fn cancel_handle(&self) -> CppDepsCancel {
+        self.cancel.clone()
+    }
+
+    /// Scan and compile with the compiler and flags of the build.
+    ///
+    /// The compiler is detected when the analysis starts, so the output directory, target and host can be configured
+    /// before or after. See [`CppDeps::out_dir`], [`CppDeps::target`] and [`CppDeps::host`].
+    ///
+    /// The C++ standard selected by the build, through [`cc::Build::std`](crate::vendor::cc::Build::std), a `-std=`
+    /// flag or `CXXFLAGS`, is kept, and defaults to `c++20`. The analysis fails if the standard is older than C++20 or,
+    /// unless [`CppDeps::probe_compiler`] is disabled, the compiler doesn't accept it.
     #[cfg(feature = "cc")]
     pub fn compiler(&mut self, build: crate::vendor::cc::Build) -> Result<(), Error> {
-        let compiler = self::Compiler::new(build)?;
-        self.compiler = Arc::from(compiler);
+        self.compiler.set_build(build);
+        Ok(())
+    }
+
+    /// Build the compile commands without running them.
+    ///
+    /// Sources are still scanned, unless their scan is up to date, and the analyzer still yields every unit in
+    /// dependency order with the object path and the command it would be compiled with. The build state is left alone
+    /// for compilations, so a later build compiles every unit. See [`CppDeps::plan`] for a serializable build plan.
+    #[cfg(feature = "cc")]
+    pub fn dry_run(&mut self, dry_run: bool) {
+        self.dry_run = dry_run;
+    }
+
+    /// Run the scan and compile commands with the executor instead of spawning them as local processes.
+    ///
+    /// The executor, like the timeout and the jobserver, is kept when the compiler is reconfigured. See [`CppDepsExecutor`].
+    #[cfg(feature = "cc")]
+    pub fn executor(&mut self, executor: impl CppDepsExecutor + 'static) {
+        self.compiler.set_executor(Arc::new(executor));
+    }
+
+    /// Hold a job slot of the jobserver while running each scan and compilation, and give the commands access to it.
+    ///
+    /// By default, the jobserver inherited through `CARGO_MAKEFLAGS` or `MAKEFLAGS` is used, if any, so that the
+    /// analysis doesn't oversubscribe the machine under `cargo build -j` or `make -j`. The parallelism still bounds the
+    /// number of jobs. `None` runs the jobs without a jobserver. See [`CppDeps::serve_jobserver`].
+    #[cfg(feature = "jobserver")]
+    pub fn jobserver(&mut self, client: Option<jobserver::Client>) -> Result<(), Error> {
+        let jobserver = client.map(crate::jobs::Jobserver::new).transpose()?.map(Arc::new);
+        self.compiler.set_jobserver(jobserver);
         Ok(())
     }
 
+    /// Act as the jobserver of the child compilers when none was inherited, e.g., for `-flto=jobserver`.
+    ///
+    /// The jobserver has a slot for each job of the current parallelism, so set the parallelism first.
+    #[cfg(feature = "jobserver")]
+    pub fn serve_jobserver(&mut self) -> Result<(), Error> {
+        if self.compiler.jobserver().is_some() {
+            return Ok(());
+        }
+        // NOTE: the process holds an implicit slot in addition to the tokens of the jobserver
+        let client = jobserver::Client::new(self.parallelism.get() - 1)
+            .map_err(|err| InnerError::new(InnerErrorKind::JobserverNew { err }))?;
+        self.jobserver(Some(client))
+    }
+
+    /// The target triple of the machine running the compiler, e.g., `x86_64-unknown-linux-gnu`.
+    ///
+    /// Defaults to the `HOST` environment variable, as set by Cargo for build scripts, unless set on the `cc::Build`.
+    #[cfg(feature = "cc")]
+    pub fn host(&mut self, host: impl Into<String>) {
+        self.compiler.set_host(host.into());
+    }
+
+    /// Skip scanning and compiling units which are unchanged since the previous build. Disabled by default.
+    ///
+    /// The state of each build is recorded in `cpp-deps.state` in the output directory: per unit, the hashes of the
+    /// source and the headers it includes, the scan and compile commands, the P1689 file and the outputs. A unit is
+    /// rescanned when any of its inputs or the scan command changed, and recompiled when it was rescanned, its command
+    /// changed, a BMI it was compiled against changed, or an output is missing. Up-to-date units are still yielded by
+    /// the analyzer in dependency order.
+    #[cfg(feature = "cc")]
+    pub fn incremental(&mut self, incremental: bool) {
+        self.incremental = incremental;
+    }
+
     pub fn items<Is>(&mut self, items: Is) -> Result<(), Error>
     where
         Is: IntoIterator<Item = CppDepsItem<P, B>>,
@@ -283,6 +756,112 @@ where
         Ok(())
     }
 
+    /// Write the scans, objects and BMIs to the directories of the layout rather than all to the output directory.
+    ///
+    /// See [`CppDepsLayout`], which also covers where the artifacts of sources outside of their `src_base` go.
+    #[cfg(feature = "cc")]
+    pub fn layout(&mut self, layout: CppDepsLayout) {
+        self.compiler.set_layout(layout);
+    }
+
+    /// Continue past failed units instead of stopping at the first failure.
+    ///
+    /// Units importing a failed unit (transitively) are skipped while unrelated units keep building.
+    pub fn keep_going(&mut self, keep_going: bool) {
+        self.options.keep_going = keep_going;
+    }
+
+    /// Print `cargo:rerun-if-changed` lines for every input once the analysis finishes, whether it succeeds or fails.
+    ///
+    /// Inputs are the source files, the headers discovered while scanning them and the dependency files passed in, so
+    /// a build script using cpp-deps only reruns when one of them changes. The environment variables the analysis
+    /// consults, e.g., `CXX` and `CXXFLAGS`, are printed as `cargo:rerun-if-env-changed` lines. See
+    /// [`CppDepsAnalyzer::emit_rerun_if_changed`] to print them manually.
+    pub fn rerun_if_changed(&mut self, emit: bool) {
+        self.options.emit_rerun_if_changed = emit;
+    }
+
+    /// Probe the compiler for its version and the module features it supports, without analyzing the units.
+    ///
+    /// The probes run even if [`CppDeps::probe_compiler`] is disabled. Probing fails when the compiler rejects the
+    /// selected C++ standard. See [`CppDepsProbe`] and [`Error::is_std_unsupported`].
+    #[cfg(feature = "cc")]
+    pub fn probe(&self) -> Result<CppDepsProbe, Error> {
+        let compiler = self.compiler.detect()?;
+        Ok(crate::probe::probe(&compiler)?)
+    }
+
+    /// Probe the compiler when the analysis starts, like [`CppDeps::probe`].
+    ///
+    /// A scan or compilation needing a missing feature, e.g., scanning to P1689 with gcc older than 14, then fails with
+    /// a hint at the compiler version required rather than with the diagnostics of an unknown flag, and a compiler
+    /// rejecting the selected C++ standard fails before any unit is scanned. Enabled by default; disabling it skips the
+    /// handful of commands the probes run and the scratch sources they write to the `cpp-deps-probe` directory of the
+    /// output directory, even for a dry run.
+    #[cfg(feature = "cc")]
+    pub fn probe_compiler(&mut self, probe: bool) {
+        self.compiler.set_probe(probe);
+    }
+
+    /// Generate a Ninja build file for the source files instead of compiling them in-process.
+    ///
+    /// The `build.ninja` is written to the output directory. It scans each source with the same command used
+    /// in-process, runs `collator` with the path of a collation manifest to turn the scan results into a `dyndep` file
+    /// and a module map per unit (see [`CppDepsNinja::collate`]), and compiles each unit with the module flags read from
+    /// its module map. Only [`CppDepsItem::SrcFile`] items are supported.
+    ///
+    /// Only the items sent before the call are included; it doesn't wait for clones of a `CppDepsSink` to be
+    /// dropped.
+    #[cfg(feature = "cc")]
+    pub fn ninja(self, collator: &std::process::Command) -> Result<CppDepsNinja, Error> {
+        let CppDeps {
+            compiler,
+            cppdeps_tx,
+            cppdeps_rx,
+            ..
+        } = self;
+        drop(cppdeps_tx);
+        let mut src_files = vec![];
+        // NOTE: drained rather than iterated, since a live `CppDepsSink` clone would keep the channel open forever
+        for item in cppdeps_rx.drain() {
+            match item {
+                CppDepsItem::SrcFile { src_file } => src_files.push(src_file),
+                _ => return Err(InnerError::new(InnerErrorKind::NinjaUnsupportedItem).into()),
+            }
+        }
+        let compiler = compiler.resolve()?;
+        let ninja = crate::ninja::generate(&compiler, &src_files, collator)?;
+        Ok(ninja)
+    }
+
+    /// Analyze the units and plan their compilation without running the compiler, as with [`CppDeps::dry_run`].
+    ///
+    /// Sources are scanned, unless their scan is up to date, while dependency files and dependency info are used as
+    /// given. The plan lists a step per unit in dependency order, which can be inspected or handed off as JSON with
+    /// [`CppDepsPlan::to_json`].
+    #[cfg(feature = "plan")]
+    pub fn plan(mut self) -> Result<CppDepsPlan, Error> {
+        self.dry_run = true;
+        let outputs = self.into_iter().collect::<Result<Vec<_>, _>>()?;
+        Ok(CppDepsPlan::from(outputs))
+    }
+
+    /// Report the progress of the analysis to the observer. See [`CppDepsEvent`] for what is reported.
+    pub fn observer(&mut self, observer: impl CppDepsObserver + 'static) {
+        self.observer = Some(Arc::new(observer));
+    }
+
+    /// The directory the P1689 files, objects, BMIs and build state are written to.
+    ///
+    /// Defaults to the `OUT_DIR` environment variable, as set by Cargo for build scripts. Set it to use cpp-deps
+    /// elsewhere, or to run several analyses in separate directories from the same process.
+    #[cfg(feature = "cc")]
+    pub fn out_dir(&mut self, out_dir: impl Into<r5::Utf8PathBuf>) {
+        self.compiler.set_out_dir(out_dir.into());
+    }
+
+    /// Run up to `jobs` scans and compilations at once. Scans run first, then the compilations of the units which the
+    /// most units transitively import.
     pub fn parallelism(&mut self, jobs: usize) -> Result<(), Error> {
         let jobs = NonZeroUsize::try_from(jobs)
             .map_err(|err| InnerError::new(InnerErrorKind::NonZeroUsizeTryFromUsize { err }))?;
@@ -290,11 +869,36 @@ where
         Ok(())
     }
 
+    /// Kill the scans and compilations which run longer than `timeout`, failing the unit with an error for which
+    /// [`Error::is_timed_out`] holds. Commands run without a timeout by default.
+    #[cfg(feature = "cc")]
+    pub fn timeout(&mut self, timeout: Option<core::time::Duration>) {
+        self.compiler.set_timeout(timeout);
+    }
+
     #[cfg(feature = "sink")]
     pub fn sink(&self) -> CppDepsSink<P, B> {
         let sink = self.cppdeps_tx.clone().into_sink();
         CppDepsSink { sink }
     }
+
+    /// The target triple to compile for, e.g., `x86_64-unknown-linux-gnu`.
+    ///
+    /// Defaults to the `TARGET` environment variable, as set by Cargo for build scripts, unless set on the `cc::Build`.
+    #[cfg(feature = "cc")]
+    pub fn target(&mut self, target: impl Into<String>) {
+        self.compiler.set_target(target.into());
+    }
+
+    /// Write a `compile_commands.json` compilation database to the output directory after each successful run.
+    ///
+    /// It holds the exact command each unit was compiled with, including the flags locating the BMIs of its imports,
+    /// so tools like clangd can resolve `import` statements. Units which were up to date are included as well. The
+    /// database is left alone when it's unchanged.
+    #[cfg(feature = "compile_commands")]
+    pub fn write_compile_commands(&mut self, write: bool) {
+        self.write_compile_commands = write;
+    }
 }
 
 impl<P, B> IntoIterator for CppDeps<P, B>
@@ -303,7 +907,7 @@ where
     B: AsRef<[u8]> + Send + Sync + 'static,
 {
     type IntoIter = CppDepsAnalyzer<P, B>;
-    type Item = Result<DepInfoYoke, Error>;
+    type Item = Result<CppDepsOutput, Error>;
 
     fn into_iter(self) -> Self::IntoIter {
         self.analyze()
@@ -0,0 +1,221 @@
+use p1689::r5;
+
+use crate::{
+    collate::{write_if_changed, CollateUnit},
+    compiler::CompilerFamily,
+    ninja::shell_quote,
+    Error,
+    InnerError,
+    InnerErrorKind,
+};
+
+const MODULE_FLAGS_VAR: &str = "CPP_DEPS_MODULE_FLAGS";
+const OBJECTS_VAR: &str = "CPP_DEPS_OBJECTS";
+const MODULE_MAP_EXT: &str = "modmap";
+
+/// A GNU Make fragment generated by [`CppDepsMake::generate`].
+///
+/// The fragment is meant to be `include`d by a Makefile which scans the sources and compiles them with a pattern rule.
+/// For every object it adds prerequisites on the P1689 file it was generated from and on the objects providing the
+/// modules it imports (transitively), and sets the target-specific `CPP_DEPS_MODULE_FLAGS` to the module flags for the
+/// compiler. `CPP_DEPS_OBJECTS` lists every object. The fragment is typically regenerated by a rule depending on the
+/// P1689 files, running `cpp-deps-cli make <family> <fragment> <dep-file>...`; see `examples/gnu-make`.
+#[derive(Clone, Debug)]
+pub struct CppDepsMake {
+    path: r5::Utf8PathBuf,
+    obj_paths: Vec<r5::Utf8PathBuf>,
+}
+impl CppDepsMake {
+    /// The path of the generated fragment.
+    pub fn path(&self) -> &r5::Utf8Path {
+        &self.path
+    }
+
+    /// The object files listed in the fragment, in the order the P1689 files were given.
+    pub fn obj_paths(&self) -> &[r5::Utf8PathBuf] {
+        &self.obj_paths
+    }
+
+    /// Write the fragment for the scanned P1689 files to `path`.
+    ///
+    /// `family` is either `"clang"` or `"gcc"`. Each object is expected next to its P1689 file, with the same stem. For
+    /// gcc, a module mapper file is written next to each object as well. Files are left alone when their contents are
+    /// unchanged. Missing providers, import cycles and duplicate providers are reported like they are by the
+    /// [`CppDepsAnalyzer`](crate::CppDepsAnalyzer).
+    pub fn generate<P>(family: &str, path: impl AsRef<r5::Utf8Path>, dep_paths: &[P]) -> Result<Self, Error>
+    where
+        P: AsRef<r5::Utf8Path>,
+    {
+        let family = family.parse::<CompilerFamily>()?;
+        let make = self::generate(family, path.as_ref(), dep_paths)?;
+        Ok(make)
+    }
+}
+
+fn generate<P>(family: CompilerFamily, path: &r5::Utf8Path, dep_paths: &[P]) -> Result<CppDepsMake, InnerError>
+where
+    P: AsRef<r5::Utf8Path>,
+{
+    let mut units = vec![];
+    let mut dep_path_of = vec![];
+    for dep_path in dep_paths {
+        let dep_path = dep_path.as_ref();
+        let obj_path = dep_path.with_extension(family.obj_file_ext());
+        units.push(CollateUnit::load(dep_path, obj_path)?);
+        dep_path_of.push(dep_path);
+    }
+    let collated = crate::collate::collate(family, None, &units)?;
+
+    let mut make = String::from("# Generated by cpp-deps. Do not edit.\n");
+    for (((unit, dep_path), provides), imports) in units
+        .iter()
+        .zip(dep_path_of)
+        .zip(&collated.provides)
+        .zip(&collated.imports)
+    {
+        let module_map = unit.obj_path.with_extension(MODULE_MAP_EXT);
+        let (flags, module_map_text) = family.module_flags_make(
+            unit.is_interface(),
+            provides,
+            imports.iter().map(|(name, (_, path))| (name.as_str(), path.as_path())),
+            &module_map,
+        );
+        let obj_path = escape_path(&unit.obj_path);
+
+        let mut prerequisites = vec![escape_path(dep_path)];
+        if let Some(text) = module_map_text {
+            write_if_changed(&module_map, &text)?;
+            prerequisites.push(escape_path(&module_map));
+        }
+        let mut providers = imports.values().map(|(provider, _)| *provider).collect::<Vec<_>>();
+        providers.sort_unstable();
+        providers.dedup();
+        prerequisites.extend(
+            providers
+                .into_iter()
+                .map(|provider| escape_path(&units[provider].obj_path)),
+        );
+
+        let flags = flags.iter().map(|flag| shell_quote(flag)).collect::<Vec<_>>().join(" ");
+        make.push_str(&format!("\n{OBJECTS_VAR} += {obj_path}\n"));
+        make.push_str(&format!("{obj_path} : {}\n", prerequisites.join(" ")));
+        make.push_str(&format!(
+            "{obj_path} : {MODULE_FLAGS_VAR} := {}\n",
+            escape_value(&flags)
+        ));
+    }
+
+    if let Some(dir) = path.parent().filter(|dir| !dir.as_str().is_empty()) {
+        std::fs::create_dir_all(dir).map_err(|err| InnerError::new(InnerErrorKind::FsCreateDirAll { err }))?;
+    }
+    write_if_changed(path, &make)?;
+    let obj_paths = units.into_iter().map(|unit| unit.obj_path).collect();
+    Ok(CppDepsMake {
+        path: path.to_path_buf(),
+        obj_paths,
+    })
+}
+
+fn escape_path(path: &r5::Utf8Path) -> String {
+    let mut escaped = String::with_capacity(path.as_str().len());
+    for c in path.as_str().chars() {
+        match c {
+            '$' => escaped.push_str("$$"),
+            ' ' | '#' | ':' | '%' => {
+                escaped.push('\\');
+                escaped.push(c);
+            },
+            _ => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+fn escape_value(value: &str) -> String {
+    value.replace('$', "$$").replace('#', "\\#")
+}
+
+#[cfg(test)]
+mod test {
+    use p1689::r5;
+
+    use crate::{testing::BoxResult, CppDepsItem};
+
+    fn dep_paths(out_dir: &r5::Utf8Path) -> BoxResult<Vec<r5::Utf8PathBuf>> {
+        let mut dep_paths = vec![];
+        for item in crate::testing::corpus::dep_text::items() {
+            let CppDepsItem::DepText { dep_path, dep_text, .. } = item else {
+                return Err("expected dep text".into());
+            };
+            let dep_path = out_dir.join(dep_path.as_ref());
+            std::fs::create_dir_all(dep_path.parent().ok_or("missing parent")?)?;
+            std::fs::write(&dep_path, dep_text)?;
+            dep_paths.push(dep_path);
+        }
+        Ok(dep_paths)
+    }
+
+    #[test]
+    fn generate_clang() -> BoxResult<()> {
+        let tmp_dir = tempdir::TempDir::new("cpp-deps")?;
+        let out_dir = r5::Utf8Path::from_path(tmp_dir.path()).ok_or("non-UTF-8 temp dir")?;
+        let dep_paths = self::dep_paths(out_dir)?;
+        let make = super::CppDepsMake::generate("clang", out_dir.join("modules.mk"), &dep_paths)?;
+        assert_eq!(make.obj_paths().len(), dep_paths.len());
+
+        let text = std::fs::read_to_string(make.path())?;
+        let [main, foo, bar, part1, part2] = ["main.o", "foo.o", "bar.o", "foo/part1.o", "foo/part2.o"]
+            .map(|path| super::escape_path(&out_dir.join(path)));
+        let main_ddi = super::escape_path(&out_dir.join("main.ddi"));
+        let main_prerequisites = text
+            .lines()
+            .find_map(|line| line.strip_prefix(&format!("{main} : {main_ddi} ")))
+            .ok_or("missing prerequisites for main")?;
+        let mut prerequisites = main_prerequisites.split(' ').collect::<Vec<_>>();
+        prerequisites.sort_unstable();
+        let mut objs = [&bar, &foo, &part1, &part2].map(String::as_str);
+        objs.sort_unstable();
+        assert_eq!(prerequisites, objs);
+        assert!(text.contains(&format!("\nCPP_DEPS_OBJECTS += {main}\n")));
+
+        let main_flags = text
+            .lines()
+            .find(|line| line.starts_with(&format!("{main} : CPP_DEPS_MODULE_FLAGS := ")))
+            .ok_or("missing flags for main")?;
+        assert!(main_flags.contains(&format!("-fmodule-file=foo:part1={}", out_dir.join("foo/part1.pcm"))));
+        assert!(!main_flags.contains("-fmodule-output"));
+        assert!(main_flags.ends_with(" -x c++"));
+        let bar_flags = format!("{bar} : CPP_DEPS_MODULE_FLAGS := -fmodule-output -x c++-module\n");
+        assert!(text.contains(&bar_flags));
+        assert!(!out_dir.join("main.modmap").exists());
+        Ok(())
+    }
+
+    #[test]
+    fn generate_gcc() -> BoxResult<()> {
+        let tmp_dir = tempdir::TempDir::new("cpp-deps")?;
+        let out_dir = r5::Utf8Path::from_path(tmp_dir.path()).ok_or("non-UTF-8 temp dir")?;
+        let dep_paths = self::dep_paths(out_dir)?;
+        let make = super::CppDepsMake::generate("gcc", out_dir.join("modules.mk"), &dep_paths)?;
+
+        let text = std::fs::read_to_string(make.path())?;
+        let main = super::escape_path(&out_dir.join("main.o"));
+        let module_map = out_dir.join("main.modmap");
+        let flags = format!(
+            "-fmodules-ts -fmodule-mapper={} -x c++",
+            super::escape_value(module_map.as_str())
+        );
+        assert!(text.contains(&format!("\n{main} : CPP_DEPS_MODULE_FLAGS := {flags}\n")));
+        let module_map = std::fs::read_to_string(module_map)?;
+        assert!(module_map.starts_with("$root .\n"));
+        assert!(module_map.contains("foo:part1 gcm.cache/foo-part1.gcm\n"));
+        Ok(())
+    }
+
+    #[test]
+    fn generate_unknown_family() -> BoxResult<()> {
+        let paths: [&r5::Utf8Path; 0] = [];
+        assert!(super::CppDepsMake::generate("msvc", "modules.mk", &paths).is_err());
+        Ok(())
+    }
+}
@@ -0,0 +1,473 @@
+use std::process::Command;
+
+use p1689::r5;
+
+use crate::{
+    collate::{write_if_changed, CollateUnit},
+    compiler::{Compiler, CompilerFamily},
+    output::CppDepsCommand,
+    state::{unescape, write_line},
+    CppDepsSrc,
+    Error,
+    InnerError,
+    InnerErrorKind,
+};
+
+const NINJA_FILE_NAME: &str = "build.ninja";
+const DYNDEP_FILE_NAME: &str = "cpp-deps.dd";
+const MANIFEST_FILE_NAME: &str = "cpp-deps.collate";
+const MANIFEST_FILE_HEADER: &str = "cpp-deps-collate 1";
+const MODULE_MAP_EXT: &str = "modmap";
+
+/// A Ninja build file generated by [`CppDeps::ninja`](crate::CppDeps::ninja).
+///
+/// Building it scans every source, collates the scan results into a `dyndep` file and a module map per unit, and then
+/// compiles the units in dependency order, leaving incrementality and parallelism to Ninja.
+#[derive(Clone, Debug)]
+pub struct CppDepsNinja {
+    path: r5::Utf8PathBuf,
+    obj_paths: Vec<r5::Utf8PathBuf>,
+}
+impl CppDepsNinja {
+    /// The path of the generated `build.ninja`.
+    pub fn path(&self) -> &r5::Utf8Path {
+        &self.path
+    }
+
+    /// The object files built by the default target, in the order the sources were given.
+    pub fn obj_paths(&self) -> &[r5::Utf8PathBuf] {
+        &self.obj_paths
+    }
+
+    /// Run the collation step of a generated build file.
+    ///
+    /// The collator command passed to [`CppDeps::ninja`](crate::CppDeps::ninja) must end up here with the path of the
+    /// collation manifest, e.g., through `cpp-deps-cli collate <manifest>`. Missing providers, import cycles and
+    /// duplicate providers are reported like they are by the [`CppDepsAnalyzer`](crate::CppDepsAnalyzer).
+    pub fn collate(manifest: impl AsRef<r5::Utf8Path>) -> Result<(), Error> {
+        self::collate(manifest.as_ref()).map_err(Error::from)
+    }
+}
+
+struct ManifestUnit {
+    dep_path: r5::Utf8PathBuf,
+    obj_path: r5::Utf8PathBuf,
+    module_map: r5::Utf8PathBuf,
+}
+
+struct Manifest {
+    family: CompilerFamily,
+    dyndep: r5::Utf8PathBuf,
+    bmi_dir: Option<r5::Utf8PathBuf>,
+    units: Vec<ManifestUnit>,
+}
+impl Manifest {
+    fn write(&self) -> String {
+        let mut text = String::from(MANIFEST_FILE_HEADER);
+        text.push('\n');
+        write_line(&mut text, "family", [self.family.as_str()]);
+        write_line(&mut text, "dyndep", [self.dyndep.as_str()]);
+        if let Some(bmi_dir) = &self.bmi_dir {
+            write_line(&mut text, "bmi_dir", [bmi_dir.as_str()]);
+        }
+        for unit in &self.units {
+            let fields = [unit.dep_path.as_str(), unit.obj_path.as_str(), unit.module_map.as_str()];
+            write_line(&mut text, "unit", fields);
+        }
+        text
+    }
+
+    fn parse(text: &str) -> Option<Self> {
+        let mut lines = text.lines();
+        if lines.next() != Some(MANIFEST_FILE_HEADER) {
+            return None;
+        }
+        let mut family = None;
+        let mut dyndep = None;
+        let mut bmi_dir = None;
+        let mut units = vec![];
+        for line in lines {
+            let mut fields = line.split(' ');
+            let tag = fields.next()?;
+            let fields = fields.map(unescape).collect::<Option<Vec<_>>>()?;
+            match (tag, <[String; 1]>::try_from(fields)) {
+                ("family", Ok([name])) => family = Some(name.parse().ok()?),
+                ("dyndep", Ok([path])) => dyndep = Some(path.into()),
+                ("bmi_dir", Ok([path])) => bmi_dir = Some(path.into()),
+                ("unit", Err(fields)) => {
+                    let [dep_path, obj_path, module_map] = <[String; 3]>::try_from(fields).ok()?;
+                    units.push(ManifestUnit {
+                        dep_path: dep_path.into(),
+                        obj_path: obj_path.into(),
+                        module_map: module_map.into(),
+                    });
+                },
+                _ => return None,
+            }
+        }
+        Some(Self {
+            family: family?,
+            dyndep: dyndep?,
+            bmi_dir,
+            units,
+        })
+    }
+}
+
+/// Write the Ninja build file and the collation manifest for the sources to the output directory.
+pub(crate) fn generate<P>(
+    compiler: &Compiler,
+    src_files: &[CppDepsSrc<P>],
+    collator: &Command,
+) -> Result<CppDepsNinja, InnerError>
+where
+    P: AsRef<r5::Utf8Path>,
+{
+    // NOTE: unless probing is disabled; the build would otherwise only fail once ninja runs the first scan
+    compiler.probe().require_scan()?;
+    compiler.probe().require_module_output()?;
+    let out_dir = compiler.out_dir();
+    let family = compiler.family();
+    let path = out_dir.join(NINJA_FILE_NAME);
+    let manifest_path = out_dir.join(MANIFEST_FILE_NAME);
+    let mut manifest = Manifest {
+        family,
+        dyndep: out_dir.join(DYNDEP_FILE_NAME),
+        bmi_dir: compiler.bmi_dir(),
+        units: vec![],
+    };
+
+    let mut ninja = String::from("# Generated by cpp-deps. Do not edit.\n\nninja_required_version = 1.10\n");
+    ninja.push_str("\nrule scan\n  command = $cmd\n  description = SCAN $in\n  deps = gcc\n");
+    ninja.push_str("\nrule collate\n  command = $cmd\n  description = COLLATE $out\n  restat = 1\n");
+    ninja.push_str("\nrule compile\n  command = $cmd\n  description = CXX $in\n");
+
+    let mut compiles = String::new();
+    for src_file in src_files {
+        let src_base = src_file.src_base.as_ref();
+        let src_path = src_file.src_path.as_ref();
+
+        let (dep_path, cmd) = compiler.dep_file_cmd_for(src_base, src_path, src_file.command())?;
+        let stdout = family.dep_file_to_stdout().then_some(dep_path.as_path());
+        let depfile = dep_path.with_extension("d");
+        ninja.push_str(&format!(
+            "\nbuild {}: scan {}\n",
+            escape_path(&dep_path),
+            escape_path(src_path)
+        ));
+        ninja.push_str(&format!(
+            "  cmd = {}\n",
+            escape_value(&shell_command(&CppDepsCommand::from(&cmd), stdout))
+        ));
+        ninja.push_str(&format!("  depfile = {}\n", escape_value(depfile.as_str())));
+
+        let module_map = dep_path.with_extension(MODULE_MAP_EXT);
+        let (obj_path, cmd) = compiler.obj_file_cmd_for(src_base, src_path, src_file.command(), &module_map)?;
+        compiles.push_str(&format!(
+            "\nbuild {}: compile {} | {} || {}\n",
+            escape_path(&obj_path),
+            escape_path(src_path),
+            escape_path(&module_map),
+            escape_path(&manifest.dyndep),
+        ));
+        compiles.push_str(&format!(
+            "  cmd = {}\n",
+            escape_value(&shell_command(&CppDepsCommand::from(&cmd), None))
+        ));
+        compiles.push_str(&format!("  dyndep = {}\n", escape_value(manifest.dyndep.as_str())));
+
+        manifest.units.push(ManifestUnit {
+            dep_path,
+            obj_path,
+            module_map,
+        });
+    }
+
+    let mut collate = CppDepsCommand::from(collator);
+    collate.args.push(manifest_path.as_str().into());
+    ninja.push_str(&format!(
+        "\nbuild {}{}: collate{} | {}\n",
+        escape_path(&manifest.dyndep),
+        escape_paths(" |", manifest.units.iter().map(|unit| &unit.module_map)),
+        escape_paths("", manifest.units.iter().map(|unit| &unit.dep_path)),
+        escape_path(&manifest_path),
+    ));
+    ninja.push_str(&format!("  cmd = {}\n", escape_value(&shell_command(&collate, None))));
+    ninja.push_str(&compiles);
+
+    let obj_paths = manifest
+        .units
+        .iter()
+        .map(|unit| unit.obj_path.clone())
+        .collect::<Vec<_>>();
+    ninja.push_str(&format!("\nbuild all: phony{}\n", escape_paths("", &obj_paths)));
+    ninja.push_str("\ndefault all\n");
+
+    std::fs::create_dir_all(out_dir).map_err(|err| InnerError::new(InnerErrorKind::FsCreateDirAll { err }))?;
+    write_if_changed(&manifest_path, &manifest.write())?;
+    write_if_changed(&path, &ninja)?;
+    Ok(CppDepsNinja { path, obj_paths })
+}
+
+fn collate(manifest_path: &r5::Utf8Path) -> Result<(), InnerError> {
+    let text =
+        std::fs::read_to_string(manifest_path).map_err(|err| InnerError::new(InnerErrorKind::FileRead { err }))?;
+    let manifest = Manifest::parse(&text).ok_or_else(|| InnerError::new(InnerErrorKind::NinjaManifestParse))?;
+
+    let units = manifest
+        .units
+        .iter()
+        .map(|unit| CollateUnit::load(&unit.dep_path, unit.obj_path.clone()))
+        .collect::<Result<Vec<_>, _>>()?;
+    let collated = crate::collate::collate(manifest.family, manifest.bmi_dir.as_deref(), &units)?;
+
+    let mut dyndep = String::from("ninja_dyndep_version = 1\n");
+    for (((unit, manifest_unit), provides), imports) in units
+        .iter()
+        .zip(&manifest.units)
+        .zip(&collated.provides)
+        .zip(&collated.imports)
+    {
+        let module_map = manifest.family.module_map(
+            unit.is_interface(),
+            provides.iter().map(|(name, path)| (name.as_str(), path.as_path())),
+            imports.iter().map(|(name, (_, path))| (name.as_str(), path.as_path())),
+        );
+        write_if_changed(&manifest_unit.module_map, &module_map)?;
+        dyndep.push_str(&format!(
+            "\nbuild {}{}: dyndep{}\n",
+            escape_path(&unit.obj_path),
+            escape_paths(" |", provides.iter().map(|(_, path)| path)),
+            escape_paths(" |", imports.values().map(|(_, path)| path)),
+        ));
+    }
+    write_if_changed(&manifest.dyndep, &dyndep)
+}
+
+fn escape_path(path: &r5::Utf8Path) -> String {
+    let mut escaped = String::with_capacity(path.as_str().len());
+    for c in path.as_str().chars() {
+        if matches!(c, '$' | ' ' | ':') {
+            escaped.push('$');
+        }
+        escaped.push(c);
+    }
+    escaped
+}
+
+// Escape the paths for a build line, each preceded by a space, and prefixed with `sep` unless there are none.
+fn escape_paths<'a>(sep: &str, paths: impl IntoIterator<Item = &'a r5::Utf8PathBuf>) -> String {
+    let mut escaped = String::new();
+    for path in paths {
+        escaped.push(' ');
+        escaped.push_str(&escape_path(path));
+    }
+    if escaped.is_empty() {
+        escaped
+    } else {
+        format!("{sep}{escaped}")
+    }
+}
+
+fn escape_value(value: &str) -> String {
+    value.replace('$', "$$")
+}
+
+pub(crate) fn shell_quote(arg: &str) -> String {
+    let is_plain = |c: char| c.is_ascii_alphanumeric() || "%+,-./:=@_^".contains(c);
+    if !arg.is_empty() && arg.chars().all(is_plain) {
+        arg.to_string()
+    } else {
+        format!("'{}'", arg.replace('\'', r"'\''"))
+    }
+}
+
+// FIXME: Ninja runs commands through `/bin/sh` only on Unix; render `cmd /c` commands for Windows.
+fn shell_command(command: &CppDepsCommand, stdout: Option<&r5::Utf8Path>) -> String {
+    let mut line = String::new();
+    if let Some(dir) = command.current_dir() {
+        line.push_str(&format!("cd {} && ", shell_quote(&dir.to_string_lossy())));
+    }
+    // NOTE: `cc` may copy the whole environment into its commands, while Ninja already runs them in the environment
+    // it inherits, so only what differs from the current one is set
+    let envs = command
+        .envs()
+        .filter(|(key, val)| std::env::var_os(key).as_deref() != *val)
+        .collect::<Vec<_>>();
+    if !envs.is_empty() {
+        line.push_str("env");
+        for (key, val) in envs {
+            let key = key.to_string_lossy();
+            match val {
+                Some(val) => line.push_str(&format!(
+                    " {}",
+                    shell_quote(&format!("{key}={}", val.to_string_lossy()))
+                )),
+                None => line.push_str(&format!(" -u {}", shell_quote(&key))),
+            }
+        }
+        line.push(' ');
+    }
+    line.push_str(&shell_quote(&command.program().to_string_lossy()));
+    for arg in command.args() {
+        line.push(' ');
+        line.push_str(&shell_quote(&arg.to_string_lossy()));
+    }
+    if let Some(path) = stdout {
+        line.push_str(&format!(" > {}", shell_quote(path.as_str())));
+    }
+    line
+}
+
+#[cfg(test)]
+mod test {
+    use std::borrow::Cow;
+
+    use p1689::r5;
+
+    use crate::{compiler::CompilerFamily, testing::BoxResult, CppDepsItem};
+
+    fn manifest<Is>(out_dir: &r5::Utf8Path, items: Is) -> BoxResult<r5::Utf8PathBuf>
+    where
+        Is: IntoIterator<Item = CppDepsItem<Cow<'static, r5::Utf8Path>, Cow<'static, [u8]>>>,
+    {
+        let mut units = vec![];
+        for item in items {
+            let CppDepsItem::DepText { dep_path, dep_text, .. } = item else {
+                return Err("expected dep text".into());
+            };
+            let dep_path = out_dir.join(dep_path.as_ref());
+            std::fs::create_dir_all(dep_path.parent().ok_or("missing parent")?)?;
+            std::fs::write(&dep_path, dep_text)?;
+            units.push(super::ManifestUnit {
+                obj_path: dep_path.with_extension("o"),
+                module_map: dep_path.with_extension(super::MODULE_MAP_EXT),
+                dep_path,
+            });
+        }
+        let manifest = super::Manifest {
+            family: CompilerFamily::Clang,
+            dyndep: out_dir.join(super::DYNDEP_FILE_NAME),
+            bmi_dir: None,
+            units,
+        };
+        let manifest_path = out_dir.join(super::MANIFEST_FILE_NAME);
+        std::fs::write(&manifest_path, manifest.write())?;
+        Ok(manifest_path)
+    }
+
+    #[test]
+    fn collate() -> BoxResult<()> {
+        let tmp_dir = tempdir::TempDir::new("cpp-deps")?;
+        let out_dir = r5::Utf8Path::from_path(tmp_dir.path()).ok_or("non-UTF-8 temp dir")?;
+        let manifest_path = self::manifest(out_dir, crate::testing::corpus::dep_text::items())?;
+        super::CppDepsNinja::collate(manifest_path)?;
+
+        let dyndep = std::fs::read_to_string(out_dir.join(super::DYNDEP_FILE_NAME))?;
+        let bmis = ["bar.pcm", "foo.pcm", "foo/part1.pcm", "foo/part2.pcm"].map(|path| out_dir.join(path));
+        let main = format!(
+            "build {}: dyndep | {}\n",
+            super::escape_path(&out_dir.join("main.o")),
+            bmis.iter()
+                .map(|path| super::escape_path(path))
+                .collect::<Vec<_>>()
+                .join(" "),
+        );
+        assert!(dyndep.contains(&main));
+        let bar = format!(
+            "build {} | {}: dyndep\n",
+            super::escape_path(&out_dir.join("bar.o")),
+            super::escape_path(&bmis[0]),
+        );
+        assert!(dyndep.contains(&bar));
+
+        let module_map = std::fs::read_to_string(out_dir.join("main.modmap"))?;
+        assert!(module_map.contains(&format!("\"-fmodule-file=foo:part1={}\"\n", bmis[2])));
+        assert!(module_map.ends_with("\"-x\"\n\"c++\"\n"));
+        Ok(())
+    }
+
+    #[test]
+    fn collate_bmi_dir() -> BoxResult<()> {
+        let tmp_dir = tempdir::TempDir::new("cpp-deps")?;
+        let out_dir = r5::Utf8Path::from_path(tmp_dir.path()).ok_or("non-UTF-8 temp dir")?;
+        let manifest_path = self::manifest(out_dir, crate::testing::corpus::dep_text::items())?;
+        let bmi_dir = out_dir.join("bmi");
+        let mut manifest =
+            super::Manifest::parse(&std::fs::read_to_string(&manifest_path)?).ok_or("invalid manifest")?;
+        manifest.bmi_dir = Some(bmi_dir.clone());
+        std::fs::write(&manifest_path, manifest.write())?;
+        super::CppDepsNinja::collate(manifest_path)?;
+
+        let module_map = std::fs::read_to_string(out_dir.join("foo/part1.modmap"))?;
+        assert!(module_map.starts_with(&format!("\"-fmodule-output={}\"\n", bmi_dir.join("foo-part1.pcm"))));
+        let module_map = std::fs::read_to_string(out_dir.join("main.modmap"))?;
+        assert!(module_map.contains(&format!(
+            "\"-fmodule-file=foo:part1={}\"\n",
+            bmi_dir.join("foo-part1.pcm")
+        )));
+        assert!(!module_map.contains("-fmodule-output="));
+        Ok(())
+    }
+
+    #[test]
+    fn generate() -> BoxResult<()> {
+        let paths = crate::testing::corpus::src_file::items()?;
+        let validate = crate::testing::corpus::src_file::validate_order(paths)?;
+        let out_dir = r5::Utf8Path::from_path(validate.out_dir.path()).ok_or("non-UTF-8 temp dir")?;
+        let mut collator = std::process::Command::new("cpp-deps-cli");
+        collator.arg("collate");
+        let ninja = validate.cpp_deps.ninja(&collator)?;
+        assert_eq!(ninja.path(), out_dir.join(super::NINJA_FILE_NAME));
+        assert_eq!(ninja.obj_paths().len(), 5);
+
+        let text = std::fs::read_to_string(ninja.path())?;
+        let manifest_path = out_dir.join(super::MANIFEST_FILE_NAME);
+        let dyndep = super::escape_path(&out_dir.join(super::DYNDEP_FILE_NAME));
+        assert!(text.contains(&format!("  cmd = cpp-deps-cli collate {manifest_path}\n")));
+        for obj_path in ninja.obj_paths() {
+            let module_map = super::escape_path(&obj_path.with_extension(super::MODULE_MAP_EXT));
+            let obj_path = super::escape_path(obj_path);
+            assert!(text.contains(&format!("\nbuild {obj_path}: compile ")));
+            assert!(text.contains(&format!(" | {module_map} || {dyndep}\n")));
+        }
+        assert!(text.ends_with("\ndefault all\n"));
+
+        let manifest = std::fs::read_to_string(manifest_path)?;
+        let manifest = super::Manifest::parse(&manifest).ok_or("invalid manifest")?;
+        assert_eq!(manifest.units.len(), 5);
+        Ok(())
+    }
+
+    // A sink which is still alive doesn't keep the generation waiting for more items.
+    #[cfg(feature = "sink")]
+    #[test]
+    fn generate_with_sink() -> BoxResult<()> {
+        let paths = crate::testing::corpus::src_file::items()?;
+        let mut validate = crate::testing::corpus::src_file::validate_order(paths)?;
+        validate.cpp_deps.executor(crate::testing::RecordingExecutor::default());
+        let _sink = validate.cpp_deps.sink();
+        let ninja = validate.cpp_deps.ninja(&std::process::Command::new("cpp-deps-cli"))?;
+        assert_eq!(ninja.obj_paths().len(), 5);
+        Ok(())
+    }
+
+    #[test]
+    fn collate_cycle() -> BoxResult<()> {
+        let tmp_dir = tempdir::TempDir::new("cpp-deps")?;
+        let out_dir = r5::Utf8Path::from_path(tmp_dir.path()).ok_or("non-UTF-8 temp dir")?;
+        let items = [
+            crate::testing::corpus::dep_text::foo_bar_cycle(),
+            crate::testing::corpus::dep_text::bar_foo_cycle(),
+        ];
+        let manifest_path = self::manifest(out_dir, items)?;
+        let err = super::CppDepsNinja::collate(manifest_path)
+            .err()
+            .ok_or("expected error")?;
+        let blocked = err.blocked().ok_or("missing diagnostics")?;
+        assert!(blocked.missing().is_empty());
+        assert_eq!(blocked.cycles().len(), 1);
+        assert_eq!(blocked.cycles()[0].names(), ["bar", "foo", "bar"]);
+        Ok(())
+    }
+}
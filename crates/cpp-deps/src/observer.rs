@@ -0,0 +1,177 @@
+use core::time::Duration;
+
+use p1689::r5;
+
+/// Receives the [`CppDepsEvent`]s of an analysis, e.g., to render progress or to log timings.
+///
+/// Events are sent from the worker threads and from the thread driving the [`CppDepsAnalyzer`](crate::CppDepsAnalyzer)
+/// as they happen, so an observer should return quickly. Closures taking a `CppDepsEvent` are observers, which makes it
+/// easy to forward the events to a channel.
+pub trait CppDepsObserver: Send + Sync {
+    fn event(&self, event: CppDepsEvent);
+}
+
+impl<F> CppDepsObserver for F
+where
+    F: Fn(CppDepsEvent) + Send + Sync,
+{
+    fn event(&self, event: CppDepsEvent) {
+        self(event)
+    }
+}
+
+/// What happened during an analysis, as reported to a [`CppDepsObserver`].
+///
+/// A unit is identified by its source path, or by its primary output when it has no source. Scan and compile events
+/// are sent from the worker thread running the command.
+#[derive(Clone, Debug, Eq, PartialEq)]
+#[non_exhaustive]
+pub enum CppDepsEvent {
+    /// A worker took up an item, identified by its source or dependency file path.
+    #[non_exhaustive]
+    ItemReceived { path: Option<r5::Utf8PathBuf> },
+    #[non_exhaustive]
+    ScanStarted { src_path: r5::Utf8PathBuf },
+    /// The scan ran, or was skipped as up to date, and the `duration` includes reading the build state.
+    #[non_exhaustive]
+    ScanFinished {
+        src_path: r5::Utf8PathBuf,
+        duration: Duration,
+        success: bool,
+    },
+    /// A unit waits on `requires`, the names which are not resolved yet.
+    #[non_exhaustive]
+    Blocked {
+        unit: Option<r5::Utf8PathBuf>,
+        provides: Vec<String>,
+        requires: Vec<String>,
+    },
+    /// Every name a blocked unit was waiting on is now resolved.
+    #[non_exhaustive]
+    Unblocked {
+        unit: Option<r5::Utf8PathBuf>,
+        provides: Vec<String>,
+    },
+    #[non_exhaustive]
+    CompileStarted {
+        src_path: r5::Utf8PathBuf,
+        provides: Vec<String>,
+        requires: Vec<String>,
+    },
+    /// The compilation ran, was skipped as up to date or, for a dry run, was only planned.
+    #[non_exhaustive]
+    CompileFinished {
+        src_path: r5::Utf8PathBuf,
+        provides: Vec<String>,
+        requires: Vec<String>,
+        duration: Duration,
+        success: bool,
+    },
+    /// The analysis ended, with the number of units which succeeded, failed to scan or compile, or were skipped.
+    #[non_exhaustive]
+    Finished {
+        succeeded: usize,
+        failed: usize,
+        skipped: usize,
+    },
+}
+
+#[cfg(test)]
+mod test {
+    use alloc::sync::Arc;
+    use std::sync::Mutex;
+
+    use p1689::r5;
+
+    use super::CppDepsEvent;
+    use crate::{testing::BoxResult, CppDeps};
+
+    fn observe<P, B>(cpp_deps: &mut CppDeps<P, B>) -> Arc<Mutex<Vec<CppDepsEvent>>>
+    where
+        P: AsRef<r5::Utf8Path> + Send + Sync + 'static,
+        B: AsRef<[u8]> + Send + Sync + 'static,
+    {
+        let events = Arc::new(Mutex::new(vec![]));
+        cpp_deps.observer({
+            let events = events.clone();
+            move |event| events.lock().unwrap().push(event)
+        });
+        events
+    }
+
+    #[test]
+    fn observe_analyze() -> BoxResult<()> {
+        let paths = crate::testing::corpus::dep_text::items().rev();
+        let mut validate = crate::testing::corpus::dep_text::validate_order(paths)?;
+        let events = observe(&mut validate.cpp_deps);
+        validate.run()?;
+
+        let events = events.lock().unwrap();
+        let received = events
+            .iter()
+            .filter(|event| matches!(event, CppDepsEvent::ItemReceived { .. }))
+            .count();
+        assert_eq!(received, 5);
+        let blocked = events
+            .iter()
+            .filter_map(|event| match event {
+                CppDepsEvent::Blocked { unit, requires, .. } => Some((unit.clone(), requires.clone())),
+                _ => None,
+            })
+            .collect::<Vec<_>>();
+        assert!(!blocked.is_empty());
+        assert!(blocked.iter().all(|(_, requires)| !requires.is_empty()));
+        let unblocked = events
+            .iter()
+            .filter_map(|event| match event {
+                CppDepsEvent::Unblocked { unit, .. } => Some(unit.clone()),
+                _ => None,
+            })
+            .collect::<Vec<_>>();
+        let mut blocked = blocked.into_iter().map(|(unit, _)| unit).collect::<Vec<_>>();
+        blocked.sort();
+        let mut unblocked = unblocked;
+        unblocked.sort();
+        assert_eq!(blocked, unblocked);
+        assert_eq!(
+            events.last(),
+            Some(&CppDepsEvent::Finished {
+                succeeded: 5,
+                failed: 0,
+                skipped: 0
+            })
+        );
+        Ok(())
+    }
+
+    #[cfg(feature = "cc")]
+    #[test]
+    fn observe_compile() -> BoxResult<()> {
+        let paths = crate::testing::corpus::src_file::items()?;
+        let mut validate = crate::testing::corpus::src_file::validate_order(paths)?;
+        validate.cpp_deps.executor(crate::testing::RecordingExecutor::default());
+        let events = observe(&mut validate.cpp_deps);
+        validate.run()?;
+
+        let events = events.lock().unwrap();
+        let count = |matches: fn(&CppDepsEvent) -> bool| events.iter().filter(|event| matches(event)).count();
+        assert_eq!(count(|event| matches!(event, CppDepsEvent::ScanStarted { .. })), 5);
+        assert_eq!(
+            count(|event| matches!(event, CppDepsEvent::ScanFinished { success: true, .. })),
+            5
+        );
+        assert_eq!(count(|event| matches!(event, CppDepsEvent::CompileStarted { .. })), 5);
+        assert_eq!(
+            count(|event| matches!(event, CppDepsEvent::CompileFinished { success: true, .. })),
+            5
+        );
+        let bar = events.iter().find_map(|event| match event {
+            CppDepsEvent::CompileFinished { src_path, provides, .. } if src_path.ends_with("bar.cppm") => {
+                Some(provides.clone())
+            },
+            _ => None,
+        });
+        assert_eq!(bar, Some(vec![String::from("bar")]));
+        Ok(())
+    }
+}
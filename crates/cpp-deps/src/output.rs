@@ -0,0 +1,182 @@
+use std::{
+    ffi::{OsStr, OsString},
+    path::{Path, PathBuf},
+    process::Command,
+};
+
+use p1689::r5::{self, yoke::DepInfoYoke};
+
+use crate::CppDepsDiagnostic;
+
+/// A unit yielded by the [`CppDepsAnalyzer`](crate::CppDepsAnalyzer), in dependency order.
+///
+/// The paths and command are only present when cpp-deps compiled the unit itself.
+#[derive(Clone)]
+pub struct CppDepsOutput {
+    pub(crate) dep_info: DepInfoYoke,
+    pub(crate) src_path: Option<r5::Utf8PathBuf>,
+    pub(crate) obj_path: Option<r5::Utf8PathBuf>,
+    pub(crate) bmi_paths: Vec<(String, r5::Utf8PathBuf)>,
+    pub(crate) command: Option<CppDepsCommand>,
+    pub(crate) execution: Option<CppDepsExecution>,
+    pub(crate) scan_execution: Option<CppDepsExecution>,
+}
+impl CppDepsOutput {
+    pub fn dep_info(&self) -> &DepInfoYoke {
+        &self.dep_info
+    }
+
+    pub fn into_dep_info(self) -> DepInfoYoke {
+        self.dep_info
+    }
+
+    pub fn src_path(&self) -> Option<&r5::Utf8Path> {
+        self.src_path.as_deref()
+    }
+
+    pub fn obj_path(&self) -> Option<&r5::Utf8Path> {
+        self.obj_path.as_deref()
+    }
+
+    /// The BMI written for each provided module, by logical name.
+    pub fn bmi_paths(&self) -> &[(String, r5::Utf8PathBuf)] {
+        &self.bmi_paths
+    }
+
+    /// The command which compiled the unit.
+    pub fn command(&self) -> Option<&CppDepsCommand> {
+        self.command.as_ref()
+    }
+
+    /// The exit code and captured output of the compilation. Absent when the compilation was up to date or planned.
+    pub fn execution(&self) -> Option<&CppDepsExecution> {
+        self.execution.as_ref()
+    }
+
+    /// The exit code and captured output of the scan, e.g., its warnings. Absent when the scan was up to date or the
+    /// unit wasn't scanned by cpp-deps.
+    ///
+    /// When the scanner prints the P1689 file, its standard output is moved to the file rather than kept here.
+    pub fn scan_execution(&self) -> Option<&CppDepsExecution> {
+        self.scan_execution.as_ref()
+    }
+}
+impl core::fmt::Debug for CppDepsOutput {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.debug_struct("CppDepsOutput")
+            .field("dep_info", self.dep_info.get())
+            .field("src_path", &self.src_path)
+            .field("obj_path", &self.obj_path)
+            .field("bmi_paths", &self.bmi_paths)
+            .field("command", &self.command)
+            .field("execution", &self.execution)
+            .field("scan_execution", &self.scan_execution)
+            .finish()
+    }
+}
+
+/// The outcome of a command: its exit code and its captured standard output and standard error.
+///
+/// Commands are run by the `CppDepsExecutor` when the `cc` feature is enabled.
+#[derive(Clone, Default, Eq, PartialEq)]
+pub struct CppDepsExecution {
+    pub(crate) code: Option<i32>,
+    pub(crate) stdout: Vec<u8>,
+    pub(crate) stderr: Vec<u8>,
+}
+impl CppDepsExecution {
+    /// `code` is the exit code, or `None` when the command was terminated by a signal.
+    pub fn new(code: Option<i32>, stdout: Vec<u8>, stderr: Vec<u8>) -> Self {
+        Self { code, stdout, stderr }
+    }
+
+    pub fn code(&self) -> Option<i32> {
+        self.code
+    }
+
+    pub fn success(&self) -> bool {
+        self.code == Some(0)
+    }
+
+    pub fn stdout(&self) -> &[u8] {
+        &self.stdout
+    }
+
+    pub fn stderr(&self) -> &[u8] {
+        &self.stderr
+    }
+
+    /// Parse the diagnostics the compiler wrote to the standard error.
+    pub fn diagnostics(&self) -> Vec<CppDepsDiagnostic> {
+        CppDepsDiagnostic::parse(&String::from_utf8_lossy(&self.stderr))
+    }
+}
+impl core::fmt::Debug for CppDepsExecution {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.debug_struct("CppDepsExecution")
+            .field("code", &self.code)
+            .field("stdout", &String::from_utf8_lossy(&self.stdout))
+            .field("stderr", &String::from_utf8_lossy(&self.stderr))
+            .finish()
+    }
+}
+impl From<std::process::Output> for CppDepsExecution {
+    fn from(output: std::process::Output) -> Self {
+        Self::new(output.status.code(), output.stdout, output.stderr)
+    }
+}
+
+/// A fully described command, as it was (or would be) executed.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct CppDepsCommand {
+    pub(crate) program: OsString,
+    pub(crate) args: Vec<OsString>,
+    pub(crate) envs: Vec<(OsString, Option<OsString>)>,
+    pub(crate) current_dir: Option<PathBuf>,
+}
+impl CppDepsCommand {
+    pub fn program(&self) -> &OsStr {
+        &self.program
+    }
+
+    pub fn args(&self) -> impl Iterator<Item = &OsStr> {
+        self.args.iter().map(OsString::as_os_str)
+    }
+
+    /// Environment changes relative to the parent process. A `None` value removes the variable.
+    pub fn envs(&self) -> impl Iterator<Item = (&OsStr, Option<&OsStr>)> {
+        self.envs.iter().map(|(key, val)| (key.as_os_str(), val.as_deref()))
+    }
+
+    pub fn current_dir(&self) -> Option<&Path> {
+        self.current_dir.as_deref()
+    }
+
+    pub fn to_command(&self) -> Command {
+        let mut cmd = Command::new(&self.program);
+        cmd.args(&self.args);
+        for (key, val) in &self.envs {
+            match val {
+                Some(val) => cmd.env(key, val),
+                None => cmd.env_remove(key),
+            };
+        }
+        if let Some(dir) = &self.current_dir {
+            cmd.current_dir(dir);
+        }
+        cmd
+    }
+}
+impl From<&Command> for CppDepsCommand {
+    fn from(cmd: &Command) -> Self {
+        Self {
+            program: cmd.get_program().to_owned(),
+            args: cmd.get_args().map(ToOwned::to_owned).collect(),
+            envs: cmd
+                .get_envs()
+                .map(|(key, val)| (key.to_owned(), val.map(ToOwned::to_owned)))
+                .collect(),
+            current_dir: cmd.get_current_dir().map(ToOwned::to_owned),
+        }
+    }
+}
@@ -0,0 +1,216 @@
+use std::collections::{BTreeSet, HashMap};
+
+use p1689::r5::{self, yoke::DepInfoYokeExt};
+use serde_json::Value;
+
+use crate::{CppDepsCommand, CppDepsOutput};
+
+const PLAN_VERSION: u64 = 1;
+
+/// A build plan produced by [`CppDeps::plan`](crate::CppDeps::plan) without running the compiler.
+///
+/// The steps are in dependency order: each step only depends on earlier steps.
+#[derive(Clone, Debug)]
+pub struct CppDepsPlan {
+    steps: Vec<CppDepsStep>,
+}
+impl CppDepsPlan {
+    pub fn steps(&self) -> &[CppDepsStep] {
+        &self.steps
+    }
+
+    /// Serialize the plan as JSON.
+    ///
+    /// The plan is an object with a `version` and its `steps`. Each step has its `id` (its index), the `src` path, the
+    /// `inputs` and `outputs` paths, the `provides` and `requires` module names, the `command` as an object with the
+    /// `program`, `args`, `env` and `cwd`, and the `deps` listing the ids of the steps it depends on. The `src`,
+    /// `command` and `cwd` are `null` when absent, as is the value of a variable removed from the environment.
+    pub fn to_json(&self) -> String {
+        let steps = self
+            .steps
+            .iter()
+            .enumerate()
+            .map(|(id, step)| step.to_json(id))
+            .collect::<Vec<_>>();
+        let plan = serde_json::json!({ "version": PLAN_VERSION, "steps": steps });
+        format!("{plan:#}")
+    }
+}
+impl From<Vec<CppDepsOutput>> for CppDepsPlan {
+    fn from(outputs: Vec<CppDepsOutput>) -> Self {
+        let mut providers = HashMap::new();
+        // The BMIs read by the dependents of each step: those it was compiled against and those it produced.
+        let mut upstream = Vec::<BTreeSet<r5::Utf8PathBuf>>::with_capacity(outputs.len());
+        let mut steps = Vec::with_capacity(outputs.len());
+        for (id, output) in outputs.into_iter().enumerate() {
+            let provides = output
+                .dep_info
+                .provides()
+                .map(|name| name.yoke.get().to_string())
+                .collect::<Vec<_>>();
+            let requires = output
+                .dep_info
+                .requires()
+                .map(|name| name.yoke.get().to_string())
+                .collect::<Vec<_>>();
+            let deps = requires
+                .iter()
+                .filter_map(|name| providers.get(name).copied())
+                .collect::<BTreeSet<usize>>();
+
+            // Like the compile commands, a step reads the BMIs produced anywhere upstream.
+            let bmis = deps
+                .iter()
+                .flat_map(|&dep| upstream[dep].iter().cloned())
+                .collect::<BTreeSet<_>>();
+            let inputs = output.src_path.iter().cloned().chain(bmis.iter().cloned()).collect();
+            let outputs = output
+                .obj_path
+                .iter()
+                .cloned()
+                .chain(output.bmi_paths.iter().map(|(_, path)| path.clone()))
+                .collect();
+
+            for name in &provides {
+                providers.entry(name.clone()).or_insert(id);
+            }
+            upstream.push(
+                bmis.into_iter()
+                    .chain(output.bmi_paths.iter().map(|(_, path)| path.clone()))
+                    .collect(),
+            );
+            steps.push(CppDepsStep {
+                src_path: output.src_path,
+                inputs,
+                outputs,
+                provides,
+                requires,
+                command: output.command,
+                deps: deps.into_iter().collect(),
+            });
+        }
+        Self { steps }
+    }
+}
+
+/// A unit of a [`CppDepsPlan`].
+#[derive(Clone, Debug)]
+pub struct CppDepsStep {
+    src_path: Option<r5::Utf8PathBuf>,
+    inputs: Vec<r5::Utf8PathBuf>,
+    outputs: Vec<r5::Utf8PathBuf>,
+    provides: Vec<String>,
+    requires: Vec<String>,
+    command: Option<CppDepsCommand>,
+    deps: Vec<usize>,
+}
+impl CppDepsStep {
+    pub fn src_path(&self) -> Option<&r5::Utf8Path> {
+        self.src_path.as_deref()
+    }
+
+    /// The source followed by the BMIs the unit is compiled against.
+    pub fn inputs(&self) -> &[r5::Utf8PathBuf] {
+        &self.inputs
+    }
+
+    /// The object file followed by the BMIs of the provided modules.
+    pub fn outputs(&self) -> &[r5::Utf8PathBuf] {
+        &self.outputs
+    }
+
+    pub fn provides(&self) -> &[String] {
+        &self.provides
+    }
+
+    pub fn requires(&self) -> &[String] {
+        &self.requires
+    }
+
+    /// The command which would compile the unit. Absent for units which were only analyzed.
+    pub fn command(&self) -> Option<&CppDepsCommand> {
+        self.command.as_ref()
+    }
+
+    /// The indices of the steps providing the modules this step requires.
+    pub fn deps(&self) -> &[usize] {
+        &self.deps
+    }
+
+    fn to_json(&self, id: usize) -> Value {
+        let paths = |paths: &[r5::Utf8PathBuf]| paths.iter().map(|path| Value::from(path.as_str())).collect::<Vec<_>>();
+        let command = self.command.as_ref().map(|command| {
+            let env = command
+                .envs()
+                .map(|(key, val)| {
+                    let val = val.map(|val| val.to_string_lossy().into_owned());
+                    (key.to_string_lossy().into_owned(), Value::from(val))
+                })
+                .collect::<serde_json::Map<_, _>>();
+            serde_json::json!({
+                "program": command.program().to_string_lossy(),
+                "args": command.args().map(|arg| arg.to_string_lossy()).collect::<Vec<_>>(),
+                "env": env,
+                "cwd": command.current_dir().map(|dir| dir.to_string_lossy()),
+            })
+        });
+        serde_json::json!({
+            "id": id,
+            "src": self.src_path.as_ref().map(|path| path.as_str()),
+            "inputs": paths(&self.inputs),
+            "outputs": paths(&self.outputs),
+            "provides": self.provides,
+            "requires": self.requires,
+            "command": command,
+            "deps": self.deps,
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::testing::BoxResult;
+
+    #[test]
+    fn plan() -> BoxResult<()> {
+        let items = crate::testing::corpus::dep_text::items();
+        let validate = crate::testing::corpus::dep_text::validate_order(items)?;
+        let plan = validate.cpp_deps.plan()?;
+        let steps = plan.steps();
+        assert_eq!(steps.len(), 5);
+        for (id, step) in steps.iter().enumerate() {
+            assert!(step.command().is_none());
+            assert!(step.deps().iter().all(|&dep| dep < id));
+            for name in step.requires() {
+                assert!(step.deps().iter().any(|&dep| steps[dep].provides().contains(name)));
+            }
+        }
+
+        let json = serde_json::from_str::<serde_json::Value>(&plan.to_json())?;
+        assert_eq!(json["version"], 1);
+        let main = json["steps"]
+            .as_array()
+            .and_then(|steps| steps.last())
+            .ok_or("missing steps")?;
+        assert_eq!(main["id"], 4);
+        assert_eq!(main["provides"], serde_json::json!([]));
+        assert_eq!(main["deps"].as_array().map(Vec::len), Some(steps[4].deps().len()));
+        assert!(main["command"].is_null());
+        Ok(())
+    }
+
+    #[test]
+    fn plan_dry_run() -> BoxResult<()> {
+        let paths = crate::testing::corpus::src_file::items()?;
+        let validate = crate::testing::corpus::src_file::validate_order(paths)?;
+        let plan = validate.cpp_deps.plan()?;
+        assert_eq!(plan.steps().len(), 5);
+        for step in plan.steps() {
+            let command = step.command().ok_or("missing command")?;
+            let obj_path = step.outputs().first().ok_or("missing object")?;
+            assert!(command.args().any(|arg| arg == obj_path.as_str()));
+            assert!(!obj_path.exists());
+        }
+        Ok(())
+    }
+}
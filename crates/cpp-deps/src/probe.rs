@@ -0,0 +1,324 @@
+use p1689::r5;
+
+use crate::{
+    compiler::{Compiler, CompilerFamily},
+    CppDepsCommand,
+    CppDepsExecution,
+    InnerError,
+    InnerErrorKind,
+};
+
+// NOTE: the scratch directory, under the output directory, of the sources probed and of what their scan writes
+pub(crate) const PROBE_DIR: &str = "cpp-deps-probe";
+
+const PROBE_SRC: &str = "int main() { return 0; }\n";
+
+/// The version and features of the compiler. See [`CppDeps::probe`](crate::CppDeps::probe) and
+/// [`CppDeps::probe_compiler`](crate::CppDeps::probe_compiler).
+///
+/// Scanning to P1689 is required for the sources, as is `-fmodule-output` for compiling with clang. When they are
+/// missing, unless probing the compiler was disabled, the scan or compilation needing them fails with a hint
+/// at the compiler version required, rather than with the diagnostics of an unknown flag. The other features are
+/// optional.
+#[derive(Clone, Debug, Default)]
+pub struct CppDepsProbe {
+    version: Option<String>,
+    module_output: bool,
+    reduced_bmi: bool,
+    header_units: bool,
+    scan_missing: Option<Missing>,
+    module_output_missing: Option<Missing>,
+}
+impl CppDepsProbe {
+    /// The version printed by `--version`, e.g., `18.1.3`, if any could be found.
+    pub fn version(&self) -> Option<&str> {
+        self.version.as_deref()
+    }
+
+    /// Whether sources are scanned to P1689, with `clang-scan-deps -format=p1689` or gcc's `-fdeps-format=p1689r5`.
+    pub fn p1689(&self) -> bool {
+        self.scan_missing.is_none()
+    }
+
+    /// Whether clang writes the BMI of a module while compiling its object with `-fmodule-output`. Always `false` with
+    /// gcc, which writes its BMIs through its module mapper instead.
+    pub fn module_output(&self) -> bool {
+        self.module_output
+    }
+
+    /// Whether clang writes reduced BMIs, without the definitions importers don't need, with
+    /// `-fexperimental-modules-reduced-bmi`. Always `false` with gcc.
+    pub fn reduced_bmi(&self) -> bool {
+        self.reduced_bmi
+    }
+
+    /// Whether headers can be compiled as header units with `-fmodule-header`.
+    pub fn header_units(&self) -> bool {
+        self.header_units
+    }
+
+    pub(crate) fn require_scan(&self) -> Result<(), InnerError> {
+        self.require(self.scan_missing.as_ref())
+    }
+
+    pub(crate) fn require_module_output(&self) -> Result<(), InnerError> {
+        self.require(self.module_output_missing.as_ref())
+    }
+
+    fn require(&self, missing: Option<&Missing>) -> Result<(), InnerError> {
+        let Some(missing) = missing else {
+            return Ok(());
+        };
+        Err(InnerError::new(InnerErrorKind::CompilerFeatureUnsupported {
+            hint: missing.hint,
+            version: self.version.as_deref().map(Box::from),
+            failed: missing.rejected.clone(),
+        }))
+    }
+}
+
+// A required feature the compiler rejected, with what to do about it.
+#[derive(Clone, Debug)]
+struct Missing {
+    hint: &'static str,
+    rejected: Rejected,
+}
+
+/// Query the version of the compiler, check that it scans to P1689, and find which other features it supports.
+///
+/// The probes run through the executor, like the scans and compilations they stand for. Only cancellation, a timeout,
+/// failing to write the probed sources or the compiler rejecting the selected C++ standard fail the probing itself.
+pub(crate) fn probe(compiler: &Compiler) -> Result<CppDepsProbe, InnerError> {
+    let probe_dir = compiler.out_dir().join(PROBE_DIR);
+    std::fs::create_dir_all(&probe_dir).map_err(|err| InnerError::new(InnerErrorKind::FsCreateDirAll { err }))?;
+    let src = probe_dir.join("probe.cpp");
+    std::fs::write(&src, PROBE_SRC).map_err(|err| InnerError::new(InnerErrorKind::FileWrite { err }))?;
+    let header = src.with_extension("hpp");
+    std::fs::write(&header, "").map_err(|err| InnerError::new(InnerErrorKind::FileWrite { err }))?;
+
+    let mut cmd = compiler.cxx(None);
+    cmd.arg("--version");
+    let version = match compiler.execute(&CppDepsCommand::from(&cmd)) {
+        Ok(execution) => self::parse_version(execution.stdout()),
+        // NOTE: the version only makes the hints more helpful
+        Err(err) if self::is_unsupported(&err) => None,
+        Err(err) => return Err(err),
+    };
+
+    let supports = |flags: &[&str], src: &r5::Utf8Path| {
+        let mut cmd = compiler.cxx(None);
+        cmd.args(flags).args(["-fsyntax-only", src.as_str()]);
+        self::run(compiler, CppDepsCommand::from(&cmd))
+    };
+    // NOTE: checked first, since every other probe selects the standard as well
+    if let Some(std) = compiler.cxx_std() {
+        if let Err(Some(failed)) = supports(&[], &src)? {
+            let std = Box::from(std);
+            return Err(InnerError::new(InnerErrorKind::CompilerStdUnsupported { std, failed }));
+        }
+    }
+
+    let family = compiler.family();
+    let cmd = compiler.dep_file_cmd(&src, &src.with_extension("ddi"), None)?;
+    let scan_missing = self::run(compiler, CppDepsCommand::from(&cmd))?.err().map(|rejected| {
+        let hint = match family {
+            CompilerFamily::Clang => {
+                "scanning to P1689 requires clang 16 or newer, and `clang-scan-deps` on the `PATH`"
+            },
+            CompilerFamily::Gcc => "scanning to P1689 requires gcc 14 or newer",
+        };
+        Missing { hint, rejected }
+    });
+    let (module_output_missing, reduced_bmi, header_units) = match family {
+        CompilerFamily::Clang => (
+            supports(&["-fmodule-output"], &src)?.err().map(|rejected| Missing {
+                hint: "`-fmodule-output` requires clang 16 or newer",
+                rejected,
+            }),
+            supports(&["-fexperimental-modules-reduced-bmi"], &src)?.is_ok(),
+            supports(&["-fmodule-header", "-xc++-header"], &header)?.is_ok(),
+        ),
+        CompilerFamily::Gcc => (
+            None,
+            false,
+            supports(&["-fmodules-ts", "-fmodule-header", "-xc++-header"], &header)?.is_ok(),
+        ),
+    };
+    Ok(CppDepsProbe {
+        version,
+        module_output: matches!(family, CompilerFamily::Clang) && module_output_missing.is_none(),
+        reduced_bmi,
+        header_units,
+        scan_missing,
+        module_output_missing,
+    })
+}
+
+// The command rejected by the compiler and its execution, if it could be run at all.
+type Rejected = Option<Box<(CppDepsCommand, CppDepsExecution)>>;
+
+// Run a probe, distinguishing the compiler rejecting it from the analysis failing, e.g., on cancellation or timeout.
+fn run(compiler: &Compiler, command: CppDepsCommand) -> Result<Result<(), Rejected>, InnerError> {
+    match compiler.execute(&command) {
+        Ok(_) => Ok(Ok(())),
+        Err(InnerError {
+            kind: InnerErrorKind::CommandCompilerNonZeroExit { failed },
+            ..
+        }) => Ok(Err(Some(failed))),
+        Err(err) if self::is_unsupported(&err) => Ok(Err(None)),
+        Err(err) => Err(err),
+    }
+}
+
+// Whether the probe failed because the compiler rejected it, or couldn't be run at all, rather than being cancelled or
+// timing out.
+fn is_unsupported(err: &InnerError) -> bool {
+    matches!(
+        err.kind,
+        InnerErrorKind::CommandCompilerNonZeroExit { .. } | InnerErrorKind::CommandStatus { .. }
+    )
+}
+
+// The last version-like word on the first line, e.g., `gcc (GCC) 14.1.1 20240522` or `clang version 18.1.3 (...)`.
+fn parse_version(stdout: &[u8]) -> Option<String> {
+    let text = String::from_utf8_lossy(stdout);
+    text.lines()
+        .next()?
+        .split_whitespace()
+        .filter(|word| {
+            word.starts_with(|c: char| c.is_ascii_digit())
+                && word.contains('.')
+                && word.chars().all(|c| c.is_ascii_digit() || c == '.')
+        })
+        .last()
+        .map(String::from)
+}
+
+#[cfg(test)]
+mod test {
+    use crate::testing::BoxResult;
+
+    #[test]
+    fn parse_version() -> BoxResult<()> {
+        assert_eq!(
+            super::parse_version(b"c++ (Debian 12.2.0-14) 12.2.0\nCopyright (C) 2022\n").as_deref(),
+            Some("12.2.0")
+        );
+        assert_eq!(
+            super::parse_version(b"gcc (GCC) 14.1.1 20240522\n").as_deref(),
+            Some("14.1.1")
+        );
+        assert_eq!(
+            super::parse_version(b"Ubuntu clang version 18.1.3 (1ubuntu1)\nTarget: x86_64-pc-linux-gnu\n").as_deref(),
+            Some("18.1.3")
+        );
+        assert_eq!(super::parse_version(b""), None);
+        Ok(())
+    }
+
+    // The probes go through the executor and only write to their scratch directory.
+    #[test]
+    fn probe_features() -> BoxResult<()> {
+        let paths = crate::testing::corpus::src_file::items()?;
+        let mut validate = crate::testing::corpus::src_file::validate_order(paths)?;
+        let executor = alloc::sync::Arc::new(crate::testing::RecordingExecutor::default());
+        validate.cpp_deps.executor(executor.clone());
+        let probe = validate.cpp_deps.probe()?;
+        assert_eq!(probe.version(), None);
+        assert!(probe.header_units());
+        let commands = executor.commands();
+        assert!(!commands.is_empty());
+        let probe_dir = validate.out_dir.path().join(super::PROBE_DIR);
+        for command in &commands {
+            for arg in command.args().filter_map(|arg| arg.to_str()) {
+                let path = std::path::Path::new(arg.rsplit('=').next().unwrap_or(arg));
+                if path.is_absolute() && path.starts_with(validate.out_dir.path()) {
+                    assert!(path.starts_with(&probe_dir));
+                }
+            }
+        }
+        let entries = std::fs::read_dir(validate.out_dir.path())?
+            .map(|entry| entry.map(|entry| entry.file_name()))
+            .collect::<Result<Vec<_>, _>>()?;
+        assert_eq!(entries, [super::PROBE_DIR]);
+        Ok(())
+    }
+
+    // The compiler is probed when the analysis starts unless disabled.
+    #[test]
+    fn probe_default() -> BoxResult<()> {
+        let out_dir = tempdir::TempDir::new("cpp-deps")?;
+        let mut cpp_deps = crate::CppDeps::new()?;
+        cpp_deps.items(crate::testing::corpus::src_file::items()?)?;
+        crate::testing::configure_probed(&mut cpp_deps, out_dir.path())?;
+        let executor = alloc::sync::Arc::new(crate::testing::RecordingExecutor::default());
+        cpp_deps.executor(executor.clone());
+        cpp_deps.dry_run(true);
+        for output in cpp_deps.analyze() {
+            output?;
+        }
+        assert!(out_dir.path().join(super::PROBE_DIR).exists());
+        // NOTE: the probes, then a scan per source
+        assert!(executor.commands().len() > 5);
+        Ok(())
+    }
+
+    #[test]
+    fn probe_disabled() -> BoxResult<()> {
+        let paths = crate::testing::corpus::src_file::items()?;
+        let mut validate = crate::testing::corpus::src_file::validate_order(paths)?;
+        let executor = alloc::sync::Arc::new(crate::testing::RecordingExecutor::default());
+        validate.cpp_deps.executor(executor.clone());
+        validate.cpp_deps.probe_compiler(false);
+        validate.cpp_deps.dry_run(true);
+        let out_dir = validate.out_dir.path().to_owned();
+        validate.run()?;
+        assert!(!out_dir.join(super::PROBE_DIR).exists());
+        // NOTE: a scan per source, and nothing else
+        assert_eq!(executor.commands().len(), 5);
+        Ok(())
+    }
+
+    // A compiler which can't scan to P1689 fails the first scan with a hint rather than the diagnostics of the scan.
+    #[test]
+    fn probe_scan_unsupported() -> BoxResult<()> {
+        struct OldExecutor;
+        impl crate::CppDepsExecutor for OldExecutor {
+            fn execute(&self, command: &crate::CppDepsCommand) -> std::io::Result<crate::CppDepsExecution> {
+                let args = command.args().filter_map(|arg| arg.to_str()).collect::<Vec<_>>();
+                if args.contains(&"--version") {
+                    return Ok(crate::CppDepsExecution::new(
+                        Some(0),
+                        b"c++ (GCC) 12.2.0\n".to_vec(),
+                        vec![],
+                    ));
+                }
+                if !args.iter().any(|arg| arg.starts_with("-fdeps-")) {
+                    return Ok(crate::CppDepsExecution::new(Some(0), vec![], vec![]));
+                }
+                let stderr = b"c++: error: unrecognized command-line option '-fdeps-format=p1689r5'\n";
+                Ok(crate::CppDepsExecution::new(Some(1), vec![], stderr.to_vec()))
+            }
+        }
+        let paths = crate::testing::corpus::src_file::items()?;
+        let mut validate = crate::testing::corpus::src_file::validate_order(paths)?;
+        validate.cpp_deps.executor(OldExecutor);
+        let probe = validate.cpp_deps.probe()?;
+        assert_eq!(probe.version(), Some("12.2.0"));
+        assert!(!probe.p1689());
+
+        validate.cpp_deps.probe_compiler(true);
+
+        let err = validate
+            .cpp_deps
+            .analyze()
+            .find_map(Result::err)
+            .ok_or("expected a failure")?;
+        let text = err.to_string();
+        assert!(text.contains("gcc 14 or newer"));
+        assert!(text.contains("12.2.0"));
+        assert!(text.contains("unrecognized command-line option"));
+        assert!(err.command().is_some());
+        Ok(())
+    }
+}
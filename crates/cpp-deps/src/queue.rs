@@ -1,29 +1,33 @@
-#[cfg(feature = "cc")]
 use alloc::sync::Arc;
 use std::thread::JoinHandle;
 
 use flume::{Receiver, Sender};
 use p1689::r5::Utf8Path;
 
-#[cfg(feature = "cc")]
-use crate::compiler::Compiler;
 use crate::{
     analyzer::{AnalyzerItem, WorkerItem},
+    schedule::{self, Priority},
     worker::Worker,
+    CppDepsCancel,
     CppDepsItem,
+    CppDepsObserver,
     InnerError,
     InnerErrorKind,
 };
+#[cfg(feature = "cc")]
+use crate::{compiler::Compiler, state::BuildState};
 
+// NOTE: the senders belong to the workers only, so that both channels disconnect once they are done
 pub(crate) struct TaskQueue<P, B> {
-    failure_tx: Sender<InnerError>,
-    pub(crate) compile_tx: Sender<WorkerItem<P, B>>,
+    pub(crate) compile_tx: schedule::Sender<WorkerItem<P, B>>,
     pub(crate) failure_rx: Receiver<InnerError>,
     pub(crate) analyze_rx: Receiver<AnalyzerItem<P>>,
     threads: Vec<JoinHandle<()>>,
 }
 impl<P, B> Drop for TaskQueue<P, B> {
     fn drop(&mut self) {
+        // NOTE: a cancelled analysis ends without shutting down, and the idle workers wait until every sender is gone
+        self.shutdown();
         while let Some(thread) = self.threads.pop() {
             thread.join().unwrap();
         }
@@ -36,79 +40,137 @@ where
 {
     pub(crate) fn new(
         cppdeps_rx: Receiver<CppDepsItem<P, B>>,
+        observer: Option<Arc<dyn CppDepsObserver>>,
+        cancel: CppDepsCancel,
         #[cfg(feature = "cc")] compiler: Arc<Compiler>,
+        #[cfg(feature = "cc")] state: Option<Arc<BuildState>>,
+        #[cfg(feature = "cc")] dry_run: bool,
         parallelism: usize,
     ) -> Self {
         let (failure_tx, failure_rx) = flume::bounded(0);
         let (analyze_tx, analyze_rx) = flume::unbounded();
-        let (compile_tx, compile_rx) = flume::unbounded();
+        let (compile_tx, compile_rx) = schedule::unbounded();
         let threads = Vec::with_capacity(parallelism + 1);
         let mut this = Self {
-            failure_tx,
             compile_tx,
             failure_rx,
             analyze_rx,
             threads,
         };
         this.spawn_compile_workers(
+            &failure_tx,
             &analyze_tx,
             &compile_rx,
+            &observer,
+            &cancel,
             #[cfg(feature = "cc")]
             &compiler,
+            #[cfg(feature = "cc")]
+            &state,
+            #[cfg(feature = "cc")]
+            dry_run,
             parallelism,
         );
-        this.spawn_analyze_cppdeps(cppdeps_rx);
+        this.spawn_analyze_cppdeps(cppdeps_rx, cancel);
         this
     }
 
-    fn spawn_analyze_cppdeps(&mut self, cppdeps_rx: flume::Receiver<CppDepsItem<P, B>>) {
+    fn spawn_analyze_cppdeps(&mut self, cppdeps_rx: flume::Receiver<CppDepsItem<P, B>>, cancel: CppDepsCancel) {
         let compile_tx = self.compile_tx.clone();
         let thunk = move || -> Result<(), InnerError> {
             let mut item_count = 0;
-            while let Ok(item) = cppdeps_rx.recv() {
+            // NOTE: the cancellation disconnects its receiver, which ends the loop like the end of the items
+            while let Some(item) = flume::Selector::new()
+                .recv(&cppdeps_rx, Result::ok)
+                .recv(cancel.wake_rx(), |_| None)
+                .wait()
+            {
                 item_count += 1;
                 let item = WorkerItem::Analyze(item);
                 compile_tx
-                    .send(item)
+                    .send(item, Priority::Scan)
                     .map_err(|_| InnerError::new(InnerErrorKind::QueueFailedSendingCompileItem))?;
             }
             let item = WorkerItem::Expects(item_count);
             compile_tx
-                .send(item)
+                .send(item, Priority::Scan)
                 .map_err(|_| InnerError::new(InnerErrorKind::QueueFailedSendingCompileItem))?;
             Ok(())
         };
-        self.threads.push(std::thread::spawn(move || {
-            thunk().ok();
-        }))
+        let thread = std::thread::Builder::new()
+            .name(String::from("cpp-deps-items"))
+            .spawn(move || {
+                thunk().ok();
+            });
+        self.threads.push(thread.expect("failed to spawn thread"))
     }
 
+    #[allow(clippy::too_many_arguments)]
     fn spawn_compile_workers(
         &mut self,
+        failure_tx: &Sender<InnerError>,
         analyze_tx: &Sender<AnalyzerItem<P>>,
-        compile_rx: &Receiver<WorkerItem<P, B>>,
+        compile_rx: &schedule::Receiver<WorkerItem<P, B>>,
+        observer: &Option<Arc<dyn CppDepsObserver>>,
+        cancel: &CppDepsCancel,
         #[cfg(feature = "cc")] compiler: &Arc<Compiler>,
+        #[cfg(feature = "cc")] state: &Option<Arc<BuildState>>,
+        #[cfg(feature = "cc")] dry_run: bool,
         parallelism: usize,
     ) {
-        for _ in 0 .. parallelism {
-            let failure_tx = self.failure_tx.clone();
+        for i in 0 .. parallelism {
+            let failure_tx = failure_tx.clone();
             let analyze_tx = analyze_tx.clone();
             let compile_rx = compile_rx.clone();
+            let observer = observer.clone();
+            let cancel = cancel.clone();
             #[cfg(feature = "cc")]
             let compiler = compiler.clone();
+            #[cfg(feature = "cc")]
+            let state = state.clone();
             let worker = Worker::new(
                 failure_tx,
                 analyze_tx,
                 compile_rx,
+                observer,
+                cancel,
                 #[cfg(feature = "cc")]
                 compiler,
+                #[cfg(feature = "cc")]
+                state,
+                #[cfg(feature = "cc")]
+                dry_run,
             );
-            self.threads.push(std::thread::spawn(worker.run()));
+            // NOTE: named so that traces and panics can tell the workers apart
+            let thread = std::thread::Builder::new()
+                .name(format!("cpp-deps-worker-{i}"))
+                .spawn(worker.run());
+            self.threads.push(thread.expect("failed to spawn thread"));
         }
     }
 }
 impl<P, B> TaskQueue<P, B> {
+    /// A queue without workers which only reports the error, e.g., when the compiler can't be configured.
+    #[cfg(feature = "cc")]
+    pub(crate) fn failed(err: InnerError) -> Self {
+        let (failure_tx, failure_rx) = flume::unbounded();
+        failure_tx.send(err).ok();
+        let analyze_rx = flume::unbounded().1;
+        Self::from_receivers(failure_rx, analyze_rx)
+    }
+
+    /// A queue without workers which reports whatever is sent to the receivers.
+    #[cfg(any(test, feature = "cc"))]
+    pub(crate) fn from_receivers(failure_rx: Receiver<InnerError>, analyze_rx: Receiver<AnalyzerItem<P>>) -> Self {
+        Self {
+            compile_tx: schedule::unbounded().0,
+            failure_rx,
+            analyze_rx,
+            threads: vec![],
+        }
+    }
+
     pub(crate) fn shutdown(&mut self) {
-        self.compile_tx = flume::bounded(0).0;
+        self.compile_tx = schedule::unbounded().0;
     }
 }
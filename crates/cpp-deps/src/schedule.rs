@@ -0,0 +1,192 @@
+use alloc::{collections::BinaryHeap, sync::Arc};
+use core::cmp::{Ordering, Reverse};
+use std::sync::{Condvar, Mutex, MutexGuard, PoisonError};
+
+/// How urgently a worker item runs. Higher priorities are received first, equal priorities in the order they were sent.
+#[derive(Clone, Copy, Debug, Eq, Ord, PartialEq, PartialOrd)]
+pub(crate) enum Priority {
+    /// Units which more units transitively wait on are compiled first, since they hold up more of the graph.
+    #[cfg(feature = "cc")]
+    Compile { dependents: usize },
+    /// Scans come first, since they discover the graph which ranks the compilations.
+    Scan,
+}
+
+struct Entry<T> {
+    priority: Priority,
+    sequence: Reverse<u64>,
+    item: T,
+}
+impl<T> PartialEq for Entry<T> {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+impl<T> Eq for Entry<T> {}
+impl<T> PartialOrd for Entry<T> {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+impl<T> Ord for Entry<T> {
+    fn cmp(&self, other: &Self) -> Ordering {
+        (self.priority, self.sequence).cmp(&(other.priority, other.sequence))
+    }
+}
+
+struct Shared<T> {
+    state: Mutex<State<T>>,
+    available: Condvar,
+}
+impl<T> Shared<T> {
+    fn lock(&self) -> MutexGuard<'_, State<T>> {
+        self.state.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+struct State<T> {
+    heap: BinaryHeap<Entry<T>>,
+    sequence: u64,
+    senders: usize,
+    receivers: usize,
+}
+
+/// Create an unbounded channel delivering the items by [`Priority`].
+///
+/// Like a `flume` channel, sending fails once every receiver is dropped, and receiving drains the remaining items once
+/// every sender is dropped.
+pub(crate) fn unbounded<T>() -> (Sender<T>, Receiver<T>) {
+    let shared = Arc::new(Shared {
+        state: Mutex::new(State {
+            heap: BinaryHeap::new(),
+            sequence: 0,
+            senders: 1,
+            receivers: 1,
+        }),
+        available: Condvar::new(),
+    });
+    (Sender { shared: shared.clone() }, Receiver { shared })
+}
+
+pub(crate) struct Sender<T> {
+    shared: Arc<Shared<T>>,
+}
+impl<T> Sender<T> {
+    /// Queue the item, returning it if every receiver was dropped.
+    pub(crate) fn send(&self, item: T, priority: Priority) -> Result<(), T> {
+        let mut state = self.shared.lock();
+        if state.receivers == 0 {
+            return Err(item);
+        }
+        let sequence = Reverse(state.sequence);
+        state.sequence += 1;
+        state.heap.push(Entry {
+            priority,
+            sequence,
+            item,
+        });
+        drop(state);
+        self.shared.available.notify_one();
+        Ok(())
+    }
+}
+impl<T> Clone for Sender<T> {
+    fn clone(&self) -> Self {
+        self.shared.lock().senders += 1;
+        let shared = self.shared.clone();
+        Self { shared }
+    }
+}
+impl<T> Drop for Sender<T> {
+    fn drop(&mut self) {
+        let mut state = self.shared.lock();
+        state.senders -= 1;
+        if state.senders == 0 {
+            drop(state);
+            self.shared.available.notify_all();
+        }
+    }
+}
+
+pub(crate) struct Receiver<T> {
+    shared: Arc<Shared<T>>,
+}
+impl<T> Receiver<T> {
+    /// Wait for the item of highest priority, or `None` once the channel is empty and every sender was dropped.
+    pub(crate) fn recv(&self) -> Option<T> {
+        let mut state = self.shared.lock();
+        loop {
+            if let Some(entry) = state.heap.pop() {
+                return Some(entry.item);
+            }
+            if state.senders == 0 {
+                return None;
+            }
+            state = self
+                .shared
+                .available
+                .wait(state)
+                .unwrap_or_else(PoisonError::into_inner);
+        }
+    }
+}
+impl<T> Clone for Receiver<T> {
+    fn clone(&self) -> Self {
+        self.shared.lock().receivers += 1;
+        let shared = self.shared.clone();
+        Self { shared }
+    }
+}
+impl<T> Drop for Receiver<T> {
+    fn drop(&mut self) {
+        let mut state = self.shared.lock();
+        state.receivers -= 1;
+        // NOTE: release the queued items, as nothing can receive them anymore
+        if state.receivers == 0 {
+            state.heap.clear();
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::Priority;
+    use crate::testing::BoxResult;
+
+    #[cfg(feature = "cc")]
+    #[test]
+    fn priority_order() -> BoxResult<()> {
+        let (tx, rx) = super::unbounded();
+        tx.send("leaf", Priority::Compile { dependents: 0 })
+            .map_err(|_| "send")?;
+        tx.send("root", Priority::Compile { dependents: 3 })
+            .map_err(|_| "send")?;
+        tx.send("scan-1", Priority::Scan).map_err(|_| "send")?;
+        tx.send("other", Priority::Compile { dependents: 0 })
+            .map_err(|_| "send")?;
+        tx.send("scan-2", Priority::Scan).map_err(|_| "send")?;
+        drop(tx);
+        let items = core::iter::from_fn(|| rx.recv()).collect::<Vec<_>>();
+        assert_eq!(items, ["scan-1", "scan-2", "root", "leaf", "other"]);
+        Ok(())
+    }
+
+    #[test]
+    fn disconnect() -> BoxResult<()> {
+        let (tx, rx) = super::unbounded::<usize>();
+        let worker = {
+            let rx = rx.clone();
+            std::thread::spawn(move || core::iter::from_fn(|| rx.recv()).sum::<usize>())
+        };
+        for n in 1 ..= 4 {
+            tx.clone().send(n, Priority::Scan).map_err(|_| "send")?;
+        }
+        drop(tx);
+        assert_eq!(worker.join().map_err(|_| "worker panicked")?, 10);
+
+        let (tx, rx) = super::unbounded();
+        drop(rx);
+        assert_eq!(tx.send(1, Priority::Scan), Err(1));
+        Ok(())
+    }
+}
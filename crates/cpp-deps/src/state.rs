@@ -0,0 +1,410 @@
+use alloc::sync::Arc;
+use std::{
+    collections::{BTreeSet, HashMap, HashSet},
+    fs::File,
+    io::Write,
+    sync::{Mutex, PoisonError},
+};
+
+use p1689::r5;
+
+use crate::{output::CppDepsCommand, InnerError, InnerErrorKind};
+
+const STATE_FILE_NAME: &str = "cpp-deps.state";
+const STATE_FILE_HEADER: &str = "cpp-deps-state 2";
+
+/// Persistent record of the previous builds in the output directory.
+///
+/// The file is a list of unit records keyed by source path. A record is appended whenever a unit is scanned or
+/// compiled, so an interrupted build keeps what it finished, and the file is compacted to the latest record per unit
+/// when it is loaded.
+pub(crate) struct BuildState {
+    path: r5::Utf8PathBuf,
+    inner: Mutex<BuildStateInner>,
+}
+
+#[derive(Default)]
+struct BuildStateInner {
+    units: HashMap<r5::Utf8PathBuf, UnitState>,
+    // Units whose scan inputs were verified (or rescanned) during this run.
+    current: HashSet<r5::Utf8PathBuf>,
+    file: Option<File>,
+}
+
+#[derive(Clone, Default, PartialEq)]
+struct UnitState {
+    scan_command: Vec<String>,
+    dep_file: (r5::Utf8PathBuf, u64),
+    inputs: Vec<(r5::Utf8PathBuf, u64)>,
+    compile: Option<CompileState>,
+}
+
+#[derive(Clone, Default, PartialEq)]
+struct CompileState {
+    command: Vec<String>,
+    bmi_inputs: Vec<(r5::Utf8PathBuf, u64)>,
+    outputs: Vec<r5::Utf8PathBuf>,
+}
+
+impl BuildState {
+    /// Load the state from the output directory. A missing or unreadable state file starts from scratch.
+    pub(crate) fn load(out_dir: &r5::Utf8Path) -> Self {
+        let path = out_dir.join(STATE_FILE_NAME);
+        let units = std::fs::read_to_string(&path)
+            .map(|text| parse_state(&text))
+            .unwrap_or_default();
+        let inner = Mutex::new(BuildStateInner {
+            units,
+            ..Default::default()
+        });
+        Self { path, inner }
+    }
+
+    /// Return the headers of the previous scan if the source, its headers, the dependency file and the scan command
+    /// are all unchanged.
+    pub(crate) fn scanned(
+        &self,
+        src: &r5::Utf8Path,
+        command: &CppDepsCommand,
+        dep_path: &r5::Utf8Path,
+    ) -> Option<Vec<r5::Utf8PathBuf>> {
+        let unit = self.lock().units.get(src).cloned()?;
+        let fresh = unit.scan_command == command_line(command)
+            && unit.dep_file.0 == dep_path
+            && is_unchanged(&unit.dep_file)
+            && unit.inputs.iter().all(is_unchanged);
+        if !fresh {
+            return None;
+        }
+        self.lock().current.insert(src.to_owned());
+        let headers = unit.inputs.into_iter().map(|(path, _)| path).filter(|path| path != src);
+        Some(headers.collect())
+    }
+
+    /// Record a fresh scan of the source. This invalidates the previous compilation of the unit.
+    pub(crate) fn record_scan(
+        &self,
+        src: &r5::Utf8Path,
+        command: &CppDepsCommand,
+        dep_path: &r5::Utf8Path,
+        headers: &[r5::Utf8PathBuf],
+    ) -> Result<(), InnerError> {
+        let inputs = core::iter::once(src).chain(headers.iter().map(AsRef::as_ref));
+        // NOTE: a unit with unreadable inputs isn't recorded so that the next run scans it again
+        let (Some(dep_file), Some(inputs)) = (hash_entry(dep_path), inputs.map(hash_entry).collect::<Option<_>>())
+        else {
+            return Ok(());
+        };
+        let unit = UnitState {
+            scan_command: command_line(command),
+            dep_file,
+            inputs,
+            compile: None,
+        };
+        let mut inner = self.lock();
+        inner.current.insert(src.to_owned());
+        self.append(&mut inner, src, unit)
+    }
+
+    /// Whether the unit was already compiled with the same command against the same BMIs, and its outputs still exist.
+    pub(crate) fn compiled(
+        &self,
+        src: &r5::Utf8Path,
+        command: &CppDepsCommand,
+        bmi_inputs: &BTreeSet<Arc<r5::Utf8PathBuf>>,
+        outputs: &[r5::Utf8PathBuf],
+    ) -> bool {
+        let compile = {
+            let inner = self.lock();
+            if !inner.current.contains(src) {
+                return false;
+            }
+            match inner.units.get(src).and_then(|unit| unit.compile.clone()) {
+                Some(compile) => compile,
+                None => return false,
+            }
+        };
+        compile.command == command_line(command)
+            && compile
+                .bmi_inputs
+                .iter()
+                .map(|(path, _)| path)
+                .eq(bmi_inputs.iter().map(|path| &**path))
+            && compile.bmi_inputs.iter().all(is_unchanged)
+            && compile.outputs == outputs
+            && outputs.iter().all(|path| path.exists())
+    }
+
+    /// Record a compilation of a unit scanned during this run.
+    pub(crate) fn record_compile(
+        &self,
+        src: &r5::Utf8Path,
+        command: &CppDepsCommand,
+        bmi_inputs: &BTreeSet<Arc<r5::Utf8PathBuf>>,
+        outputs: Vec<r5::Utf8PathBuf>,
+    ) -> Result<(), InnerError> {
+        let Some(bmi_inputs) = bmi_inputs.iter().map(|path| hash_entry(path)).collect() else {
+            return Ok(());
+        };
+        let compile = CompileState {
+            command: command_line(command),
+            bmi_inputs,
+            outputs,
+        };
+        let mut inner = self.lock();
+        let Some(mut unit) = inner.units.get(src).cloned() else {
+            return Ok(());
+        };
+        unit.compile = Some(compile);
+        self.append(&mut inner, src, unit)
+    }
+
+    fn append(&self, inner: &mut BuildStateInner, src: &r5::Utf8Path, unit: UnitState) -> Result<(), InnerError> {
+        let file = match &mut inner.file {
+            Some(file) => file,
+            None => {
+                // Compact the state on the first write so the file doesn't grow without bound across builds.
+                let mut text = String::from(STATE_FILE_HEADER);
+                text.push('\n');
+                for (src, unit) in &inner.units {
+                    write_unit(&mut text, src, unit);
+                }
+                std::fs::write(&self.path, text).map_err(|err| InnerError::new(InnerErrorKind::StateWrite { err }))?;
+                let file = File::options()
+                    .append(true)
+                    .open(&self.path)
+                    .map_err(|err| InnerError::new(InnerErrorKind::StateWrite { err }))?;
+                inner.file.insert(file)
+            },
+        };
+        let mut text = String::new();
+        write_unit(&mut text, src, &unit);
+        file.write_all(text.as_bytes())
+            .map_err(|err| InnerError::new(InnerErrorKind::StateWrite { err }))?;
+        inner.units.insert(src.to_owned(), unit);
+        Ok(())
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, BuildStateInner> {
+        self.inner.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+fn command_line(command: &CppDepsCommand) -> Vec<String> {
+    let mut line = vec![command.program().to_string_lossy().into_owned()];
+    line.extend(command.args().map(|arg| arg.to_string_lossy().into_owned()));
+    for (key, val) in command.envs() {
+        let key = key.to_string_lossy();
+        match val {
+            Some(val) => line.push(format!("env:{key}={}", val.to_string_lossy())),
+            None => line.push(format!("env-remove:{key}")),
+        }
+    }
+    if let Some(dir) = command.current_dir() {
+        line.push(format!("cwd:{}", dir.to_string_lossy()));
+    }
+    line
+}
+
+fn hash_file(path: &r5::Utf8Path) -> Option<u64> {
+    let bytes = std::fs::read(path).ok()?;
+    Some(stable_hash(&bytes))
+}
+
+/// The 64-bit FNV-1a hash of the bytes.
+///
+/// Unlike `std`'s `DefaultHasher`, the result is the same across Rust releases, so it can be persisted or used in paths.
+pub(crate) fn stable_hash(bytes: &[u8]) -> u64 {
+    const OFFSET_BASIS: u64 = 0xcbf2_9ce4_8422_2325;
+    const PRIME: u64 = 0x0000_0100_0000_01b3;
+    bytes
+        .iter()
+        .fold(OFFSET_BASIS, |hash, &byte| (hash ^ u64::from(byte)).wrapping_mul(PRIME))
+}
+
+fn hash_entry(path: &r5::Utf8Path) -> Option<(r5::Utf8PathBuf, u64)> {
+    hash_file(path).map(|hash| (path.to_owned(), hash))
+}
+
+fn is_unchanged((path, hash): &(r5::Utf8PathBuf, u64)) -> bool {
+    hash_file(path) == Some(*hash)
+}
+
+// NOTE: fields are separated by spaces, so escape the characters which would split a field or a record
+fn escape(field: &str) -> String {
+    let mut escaped = String::with_capacity(field.len());
+    for c in field.chars() {
+        match c {
+            '%' => escaped.push_str("%25"),
+            ' ' => escaped.push_str("%20"),
+            '\t' => escaped.push_str("%09"),
+            '\n' => escaped.push_str("%0A"),
+            '\r' => escaped.push_str("%0D"),
+            _ => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+pub(crate) fn unescape(field: &str) -> Option<String> {
+    let mut unescaped = String::with_capacity(field.len());
+    let mut rest = field;
+    while let Some(i) = rest.find('%') {
+        unescaped.push_str(&rest[.. i]);
+        let code = rest.get(i + 1 .. i + 3)?;
+        unescaped.push(char::from(u8::from_str_radix(code, 16).ok()?));
+        rest = &rest[i + 3 ..];
+    }
+    unescaped.push_str(rest);
+    Some(unescaped)
+}
+
+pub(crate) fn write_line<'a>(text: &mut String, tag: &str, fields: impl IntoIterator<Item = &'a str>) {
+    text.push_str(tag);
+    for field in fields {
+        text.push(' ');
+        text.push_str(&escape(field));
+    }
+    text.push('\n');
+}
+
+fn write_hashed(text: &mut String, tag: &str, (path, hash): &(r5::Utf8PathBuf, u64)) {
+    write_line(text, tag, [path.as_str(), &format!("{hash:016x}")]);
+}
+
+fn write_unit(text: &mut String, src: &r5::Utf8Path, unit: &UnitState) {
+    write_line(text, "unit", [src.as_str()]);
+    write_line(text, "scan", unit.scan_command.iter().map(String::as_str));
+    write_hashed(text, "dep", &unit.dep_file);
+    for input in &unit.inputs {
+        write_hashed(text, "input", input);
+    }
+    if let Some(compile) = &unit.compile {
+        write_line(text, "compile", compile.command.iter().map(String::as_str));
+        for bmi_input in &compile.bmi_inputs {
+            write_hashed(text, "bmi", bmi_input);
+        }
+        for output in &compile.outputs {
+            write_line(text, "output", [output.as_str()]);
+        }
+    }
+    write_line(text, "end", []);
+}
+
+// Parse the records of a state file. Malformed records (e.g., one cut short by an interrupted build) are dropped, and
+// later records for a unit replace earlier ones.
+fn parse_state(text: &str) -> HashMap<r5::Utf8PathBuf, UnitState> {
+    let mut units = HashMap::new();
+    let mut lines = text.lines();
+    if lines.next() != Some(STATE_FILE_HEADER) {
+        return units;
+    }
+    let mut record = None::<(r5::Utf8PathBuf, UnitState)>;
+    for line in lines {
+        let mut fields = line.split(' ');
+        let tag = fields.next().unwrap_or_default();
+        let Some(fields) = fields.map(unescape).collect::<Option<Vec<_>>>() else {
+            record = None;
+            continue;
+        };
+        match (tag, record.as_mut()) {
+            ("unit", _) => {
+                record = match <[String; 1]>::try_from(fields) {
+                    Ok([src]) => Some((src.into(), UnitState::default())),
+                    Err(_) => None,
+                };
+            },
+            ("scan", Some((_, unit))) => unit.scan_command = fields,
+            ("dep", Some((_, unit))) => match parse_hashed(fields) {
+                Some(dep_file) => unit.dep_file = dep_file,
+                None => record = None,
+            },
+            ("input", Some((_, unit))) => match parse_hashed(fields) {
+                Some(input) => unit.inputs.push(input),
+                None => record = None,
+            },
+            ("compile", Some((_, unit))) => {
+                let command = fields;
+                unit.compile = Some(CompileState {
+                    command,
+                    ..Default::default()
+                });
+            },
+            (
+                "bmi",
+                Some((
+                    _,
+                    UnitState {
+                        compile: Some(compile), ..
+                    },
+                )),
+            ) => match parse_hashed(fields) {
+                Some(bmi_input) => compile.bmi_inputs.push(bmi_input),
+                None => record = None,
+            },
+            (
+                "output",
+                Some((
+                    _,
+                    UnitState {
+                        compile: Some(compile), ..
+                    },
+                )),
+            ) => match <[String; 1]>::try_from(fields) {
+                Ok([output]) => compile.outputs.push(output.into()),
+                Err(_) => record = None,
+            },
+            ("end", Some(_)) => {
+                if let Some((src, unit)) = record.take() {
+                    units.insert(src, unit);
+                }
+            },
+            _ => record = None,
+        }
+    }
+    units
+}
+
+fn parse_hashed(fields: Vec<String>) -> Option<(r5::Utf8PathBuf, u64)> {
+    let [path, hash] = <[String; 2]>::try_from(fields).ok()?;
+    let hash = u64::from_str_radix(&hash, 16).ok()?;
+    Some((path.into(), hash))
+}
+
+#[cfg(test)]
+mod test {
+    use p1689::r5;
+
+    use super::{CompileState, UnitState};
+
+    #[test]
+    fn state_roundtrip() {
+        let unit = UnitState {
+            scan_command: vec!["c++".into(), "-I".into(), "include dir/100%".into()],
+            dep_file: ("out/foo.ddi".into(), 0x1234),
+            inputs: vec![("src/foo.cppm".into(), 0x5678), ("src/foo bar.h".into(), 0x9abc)],
+            compile: Some(CompileState {
+                command: vec!["c++".into(), "-c".into(), "src/foo.cppm".into()],
+                bmi_inputs: vec![("out/bar.pcm".into(), 0xdef0)],
+                outputs: vec!["out/foo.o".into(), "out/foo.pcm".into()],
+            }),
+        };
+        let mut text = String::from(super::STATE_FILE_HEADER);
+        text.push('\n');
+        super::write_unit(&mut text, r5::Utf8Path::new("src/foo.cppm"), &unit);
+        // A record cut short by an interrupted build is dropped.
+        super::write_unit(&mut text, r5::Utf8Path::new("src/bar.cppm"), &unit);
+        text.truncate(text.len() - "end\n".len());
+        let units = super::parse_state(&text);
+        assert_eq!(units.len(), 1);
+        assert!(units.get(r5::Utf8Path::new("src/foo.cppm")) == Some(&unit));
+    }
+
+    // The hashes are persisted, so they must match the published FNV-1a test vectors.
+    #[test]
+    fn stable_hash() {
+        assert_eq!(super::stable_hash(b""), 0xcbf2_9ce4_8422_2325);
+        assert_eq!(super::stable_hash(b"a"), 0xaf63_dc4c_8601_ec8c);
+        assert_eq!(super::stable_hash(b"foobar"), 0x8594_4171_f739_67e8);
+    }
+}
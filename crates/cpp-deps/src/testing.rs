@@ -1,3 +1,5 @@
+#[cfg(feature = "cc")]
+use std::sync::Mutex;
 use std::{
     collections::BTreeSet,
     path::{Path, PathBuf},
@@ -7,6 +9,8 @@ use p1689::r5::Utf8Path;
 use tempdir::TempDir;
 
 use crate::{CppDeps, CppDepsItem};
+#[cfg(feature = "cc")]
+use crate::{CppDepsCommand, CppDepsExecution, CppDepsExecutor};
 
 pub mod corpus;
 
@@ -34,15 +38,10 @@ where
     {
         let out_dir = tempdir::TempDir::new("cpp-deps")?;
         let src_root = out_dir.path().join(src_proj.as_ref());
-        self::build_script_env(out_dir.path())?;
         let mut cpp_deps = CppDeps::new()?;
         cpp_deps.items(items)?;
         #[cfg(feature = "cc")]
-        {
-            let mut build = cc::Build::new();
-            build.std("gnu++23");
-            cpp_deps.compiler(build)?;
-        }
+        self::configure(&mut cpp_deps, out_dir.path())?;
         Ok(ValidateOrder {
             out_dir,
             src_root,
@@ -56,16 +55,91 @@ where
             .analyze()
             .validate_order(&self.src_root, self.expected_outputs)
     }
+
+    #[cfg(feature = "async")]
+    pub fn run_stream(self) -> BoxResult<()> {
+        use futures_util::StreamExt;
+        let outputs = futures_executor::block_on(StreamExt::collect::<Vec<_>>(self.cpp_deps.into_iter()));
+        crate::analyzer::validate_outputs(outputs, &self.src_root, self.expected_outputs)
+    }
 }
 
-pub fn build_script_env(out_dir: &Path) -> BoxResult<()> {
-    let out_dir = out_dir
-        .as_os_str()
-        .to_str()
-        .ok_or_else(|| -> BoxError { "StringConversionFailure".into() })?;
-    std::env::set_var("OPT_LEVEL", "3");
-    std::env::set_var("TARGET", "x86_64-unknown-linux-gnu");
-    std::env::set_var("HOST", "x86_64-unknown-linux-gnu");
-    std::env::set_var("OUT_DIR", out_dir);
+// Answers scans with the P1689 text of the corpus and compilations with success, without running anything.
+#[cfg(feature = "cc")]
+#[derive(Default)]
+pub struct RecordingExecutor {
+    commands: Mutex<Vec<CppDepsCommand>>,
+}
+#[cfg(feature = "cc")]
+impl CppDepsExecutor for RecordingExecutor {
+    fn execute(&self, command: &CppDepsCommand) -> std::io::Result<CppDepsExecution> {
+        self.commands.lock().unwrap().push(command.clone());
+        let args = command.args().filter_map(|arg| arg.to_str()).collect::<Vec<_>>();
+        if !args
+            .iter()
+            .any(|arg| arg.starts_with("-fdeps-") || *arg == "-format=p1689")
+        {
+            return Ok(CppDepsExecution::new(Some(0), vec![], vec![]));
+        }
+        // NOTE: the probes only check that the scan is accepted
+        if args.iter().any(|arg| arg.contains(crate::probe::PROBE_DIR)) {
+            return Ok(CppDepsExecution::new(Some(0), vec![], vec![]));
+        }
+        let dep_text = crate::testing::corpus::dep_text::items()
+            .find_map(|item| {
+                let CppDepsItem::DepText { dep_path, dep_text, .. } = item else {
+                    return None;
+                };
+                let scanned = |ext| {
+                    args.iter()
+                        .any(|arg| Utf8Path::new(arg).ends_with(dep_path.with_extension(ext)))
+                };
+                (scanned("cppm") || scanned("cpp")).then(|| dep_text.into_owned())
+            })
+            .ok_or_else(|| std::io::Error::other("unknown source"))?;
+        match args.iter().find_map(|arg| arg.strip_prefix("-fdeps-file=")) {
+            Some(dep_path) => {
+                std::fs::write(dep_path, dep_text)?;
+                Ok(CppDepsExecution::new(Some(0), vec![], vec![]))
+            },
+            None => Ok(CppDepsExecution::new(Some(0), dep_text, vec![])),
+        }
+    }
+}
+#[cfg(feature = "cc")]
+impl RecordingExecutor {
+    pub fn commands(&self) -> Vec<CppDepsCommand> {
+        self.commands.lock().unwrap().clone()
+    }
+}
+
+// Configure the compiler like `configure`, but keep probing it when the analysis starts.
+#[cfg(feature = "cc")]
+pub fn configure_probed<P, B>(cpp_deps: &mut CppDeps<P, B>, out_dir: &Path) -> BoxResult<()>
+where
+    P: AsRef<Utf8Path> + Send + Sync + 'static,
+    B: AsRef<[u8]> + Send + Sync + 'static,
+{
+    let out_dir = Utf8Path::from_path(out_dir).ok_or("non-UTF-8 temp dir")?;
+    let mut build = cc::Build::new();
+    build.std("gnu++23").opt_level(3);
+    cpp_deps.compiler(build)?;
+    cpp_deps.out_dir(out_dir);
+    cpp_deps.target("x86_64-unknown-linux-gnu");
+    cpp_deps.host("x86_64-unknown-linux-gnu");
+    Ok(())
+}
+
+// Configure the compiler explicitly rather than through the environment of a build script, so that the tests can run
+// in parallel in their own output directories. The probes are disabled so that the recorded commands are only those of
+// the units; the `probe` tests enable them.
+#[cfg(feature = "cc")]
+pub fn configure<P, B>(cpp_deps: &mut CppDeps<P, B>, out_dir: &Path) -> BoxResult<()>
+where
+    P: AsRef<Utf8Path> + Send + Sync + 'static,
+    B: AsRef<[u8]> + Send + Sync + 'static,
+{
+    configure_probed(cpp_deps, out_dir)?;
+    cpp_deps.probe_compiler(false);
     Ok(())
 }
@@ -48,6 +48,35 @@ pub fn bar() -> CppDepsItem<Cow<'static, Utf8Path>, Cow<'static, [u8]>> {
     }
 }
 
+pub fn bar_duplicate() -> CppDepsItem<Cow<'static, Utf8Path>, Cow<'static, [u8]>> {
+    let src_file = None;
+    let dep_path = Cow::from(Utf8Path::new("baz/bar.ddi"));
+    let dep_text = Cow::from(
+        br#"{
+        "rules": [
+            {
+                "primary-output": "baz/bar.o",
+                "provides": [
+                    {
+                        "logical-name": "bar",
+                        "is-interface": true
+                    }
+                ],
+                "requires": [
+                ]
+            }
+        ],
+        "version": 0,
+        "revision": 0
+    }"#,
+    );
+    CppDepsItem::DepText {
+        src_file,
+        dep_path,
+        dep_text,
+    }
+}
+
 pub fn foo_part1() -> CppDepsItem<Cow<'static, Utf8Path>, Cow<'static, [u8]>> {
     let src_file = None;
     let dep_path = Cow::from(Utf8Path::new("foo/part1.ddi"));
@@ -77,6 +106,31 @@ pub fn foo_part1() -> CppDepsItem<Cow<'static, Utf8Path>, Cow<'static, [u8]>> {
     }
 }
 
+pub fn foo_part1_invalid() -> CppDepsItem<Cow<'static, Utf8Path>, Cow<'static, [u8]>> {
+    let src_file = None;
+    let dep_path = Cow::from(Utf8Path::new("foo/part1.ddi"));
+    let dep_text = Cow::from(
+        br#"{
+        "rules": [
+            {
+                "primary-output": "foo/part1.o",
+                "provides": [
+                    {
+                        "logical-name": "foo:part1",
+                        "is-interface": true
+                    }
+                ],
+        "version": 0,
+        "revision": 0
+    }"#,
+    );
+    CppDepsItem::DepText {
+        src_file,
+        dep_path,
+        dep_text,
+    }
+}
+
 pub fn foo_part2() -> CppDepsItem<Cow<'static, Utf8Path>, Cow<'static, [u8]>> {
     let src_file = None;
     let dep_path = Cow::from(Utf8Path::new("foo/part2.ddi"));
@@ -106,6 +160,39 @@ pub fn foo_part2() -> CppDepsItem<Cow<'static, Utf8Path>, Cow<'static, [u8]>> {
     }
 }
 
+pub fn foo_parts() -> CppDepsItem<Cow<'static, Utf8Path>, Cow<'static, [u8]>> {
+    let src_file = None;
+    let dep_path = Cow::from(Utf8Path::new("foo/parts.ddi"));
+    let dep_text = Cow::from(
+        br#"{
+        "rules": [
+            {
+                "primary-output": "foo/parts.o",
+                "provides": [
+                    {
+                        "logical-name": "foo:part1",
+                        "is-interface": true
+                    },
+                    {
+                        "logical-name": "foo:part2",
+                        "is-interface": true
+                    }
+                ],
+                "requires": [
+                ]
+            }
+        ],
+        "version": 0,
+        "revision": 0
+    }"#,
+    );
+    CppDepsItem::DepText {
+        src_file,
+        dep_path,
+        dep_text,
+    }
+}
+
 pub fn foo() -> CppDepsItem<Cow<'static, Utf8Path>, Cow<'static, [u8]>> {
     let src_file = None;
     let dep_path = Cow::from(Utf8Path::new("foo.ddi"));
@@ -25,10 +25,7 @@ pub fn bar(workspace_root: &Utf8PathBuf) -> CppDepsItem<Cow<'static, Utf8Path>,
     let src_proj = self::src_proj();
     let src_path = src_base.join(src_proj).join("bar.cppm");
     CppDepsItem::SrcFile {
-        src_file: CppDepsSrc {
-            src_base: src_base.into(),
-            src_path: src_path.into(),
-        },
+        src_file: CppDepsSrc::new(src_base.into(), src_path.into()),
     }
 }
 
@@ -37,10 +34,7 @@ pub fn foo_part1(workspace_root: &Utf8PathBuf) -> CppDepsItem<Cow<'static, Utf8P
     let src_proj = self::src_proj();
     let src_path = src_base.join(src_proj).join("foo").join("part1.cppm");
     CppDepsItem::SrcFile {
-        src_file: CppDepsSrc {
-            src_base: src_base.into(),
-            src_path: src_path.into(),
-        },
+        src_file: CppDepsSrc::new(src_base.into(), src_path.into()),
     }
 }
 
@@ -49,10 +43,7 @@ pub fn foo_part2(workspace_root: &Utf8PathBuf) -> CppDepsItem<Cow<'static, Utf8P
     let src_proj = self::src_proj();
     let src_path = src_base.join(src_proj).join("foo").join("part2.cppm");
     CppDepsItem::SrcFile {
-        src_file: CppDepsSrc {
-            src_base: src_base.into(),
-            src_path: src_path.into(),
-        },
+        src_file: CppDepsSrc::new(src_base.into(), src_path.into()),
     }
 }
 
@@ -61,10 +52,7 @@ pub fn foo(workspace_root: &Utf8PathBuf) -> CppDepsItem<Cow<'static, Utf8Path>,
     let src_proj = self::src_proj();
     let src_path = src_base.join(src_proj).join("foo.cppm");
     CppDepsItem::SrcFile {
-        src_file: CppDepsSrc {
-            src_base: src_base.into(),
-            src_path: src_path.into(),
-        },
+        src_file: CppDepsSrc::new(src_base.into(), src_path.into()),
     }
 }
 
@@ -73,10 +61,7 @@ pub fn main(workspace_root: &Utf8PathBuf) -> CppDepsItem<Cow<'static, Utf8Path>,
     let src_proj = self::src_proj();
     let src_path = src_base.join(src_proj).join("main.cpp");
     CppDepsItem::SrcFile {
-        src_file: CppDepsSrc {
-            src_base: src_base.into(),
-            src_path: src_path.into(),
-        },
+        src_file: CppDepsSrc::new(src_base.into(), src_path.into()),
     }
 }
 
@@ -0,0 +1,408 @@
+use alloc::sync::Arc;
+use core::time::Duration;
+use std::{collections::HashMap, sync::Mutex, thread::ThreadId, time::Instant};
+
+use p1689::r5;
+use serde_json::{json, Value};
+
+use crate::{CppDepsEvent, CppDepsObserver, Error, InnerError, InnerErrorKind};
+
+/// Records the scans and compilations of a build, as a [`CppDepsObserver`], for timing them.
+///
+/// The recording can be written as a Chrome Trace Event file, viewable with `chrome://tracing` or Perfetto, with a
+/// track for each worker thread, and summarized with [`CppDepsTrace::report`]. Clones share the same recording, so
+/// a clone is given to [`CppDeps::observer`](crate::CppDeps::observer) and the original is kept to read it back.
+#[derive(Clone)]
+pub struct CppDepsTrace {
+    start: Instant,
+    recording: Arc<Mutex<Recording>>,
+}
+
+#[derive(Default)]
+struct Recording {
+    threads: Vec<(ThreadId, String)>,
+    spans: Vec<Span>,
+}
+impl Recording {
+    fn thread(&mut self) -> usize {
+        let thread = std::thread::current();
+        if let Some(tid) = self.threads.iter().position(|(id, _)| *id == thread.id()) {
+            return tid;
+        }
+        let name = thread
+            .name()
+            .map_or_else(|| format!("thread-{}", self.threads.len()), ToOwned::to_owned);
+        self.threads.push((thread.id(), name));
+        self.threads.len() - 1
+    }
+}
+
+struct Span {
+    kind: SpanKind,
+    tid: usize,
+    src_path: r5::Utf8PathBuf,
+    provides: Vec<String>,
+    requires: Vec<String>,
+    start: Duration,
+    duration: Duration,
+    success: bool,
+}
+
+#[derive(Clone, Copy, Eq, PartialEq)]
+enum SpanKind {
+    Scan,
+    Compile,
+}
+impl SpanKind {
+    fn as_str(self) -> &'static str {
+        match self {
+            SpanKind::Scan => "scan",
+            SpanKind::Compile => "compile",
+        }
+    }
+}
+
+impl Default for CppDepsTrace {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl CppDepsTrace {
+    /// Start a recording. Timestamps in the trace are relative to this call.
+    pub fn new() -> Self {
+        Self {
+            start: Instant::now(),
+            recording: Arc::default(),
+        }
+    }
+
+    /// Serialize the recording in the Chrome Trace Event format.
+    ///
+    /// Each scan and compilation is a complete (`"X"`) event, in microseconds, on the track of the thread which ran it.
+    /// The event arguments hold the source path, the provided and required modules, and whether the command succeeded.
+    pub fn to_json(&self) -> String {
+        let recording = self.recording.lock().unwrap_or_else(|err| err.into_inner());
+        let threads = recording.threads.iter().enumerate().map(|(tid, (_, name))| {
+            json!({ "name": "thread_name", "ph": "M", "pid": 1, "tid": tid, "args": { "name": name } })
+        });
+        let spans = recording.spans.iter().map(|span| {
+            let name = span.src_path.file_name().unwrap_or(span.src_path.as_str());
+            json!({
+                "name": name,
+                "cat": span.kind.as_str(),
+                "ph": "X",
+                "ts": span.start.as_micros() as u64,
+                "dur": span.duration.as_micros() as u64,
+                "pid": 1,
+                "tid": span.tid,
+                "args": {
+                    "src": span.src_path.as_str(),
+                    "provides": span.provides,
+                    "requires": span.requires,
+                    "success": span.success,
+                },
+            })
+        });
+        let trace = json!({
+            "traceEvents": threads.chain(spans).collect::<Vec<Value>>(),
+            "displayTimeUnit": "ms",
+        });
+        format!("{trace:#}")
+    }
+
+    /// Write the recording to `path` in the Chrome Trace Event format. See [`CppDepsTrace::to_json`].
+    pub fn write(&self, path: impl AsRef<r5::Utf8Path>) -> Result<(), Error> {
+        std::fs::write(path.as_ref(), self.to_json())
+            .map_err(|err| InnerError::new(InnerErrorKind::FileWrite { err }).into())
+    }
+
+    /// Summarize the durations of the units and the critical path through the module graph.
+    pub fn report(&self) -> CppDepsTraceReport {
+        let recording = self.recording.lock().unwrap_or_else(|err| err.into_inner());
+        CppDepsTraceReport::new(&recording.spans)
+    }
+}
+
+impl CppDepsObserver for CppDepsTrace {
+    fn event(&self, event: CppDepsEvent) {
+        let now = self.start.elapsed();
+        let (kind, src_path, provides, requires, duration, success) = match event {
+            CppDepsEvent::ScanFinished {
+                src_path,
+                duration,
+                success,
+            } => (SpanKind::Scan, src_path, vec![], vec![], duration, success),
+            CppDepsEvent::CompileFinished {
+                src_path,
+                provides,
+                requires,
+                duration,
+                success,
+            } => (SpanKind::Compile, src_path, provides, requires, duration, success),
+            _ => return,
+        };
+        let mut recording = self.recording.lock().unwrap_or_else(|err| err.into_inner());
+        let tid = recording.thread();
+        recording.spans.push(Span {
+            kind,
+            tid,
+            src_path,
+            provides,
+            requires,
+            start: now.saturating_sub(duration),
+            duration,
+            success,
+        });
+    }
+}
+
+/// The timings of a unit in a [`CppDepsTraceReport`].
+#[derive(Clone, Debug)]
+pub struct CppDepsUnitTiming {
+    src_path: r5::Utf8PathBuf,
+    provides: Vec<String>,
+    scan: Option<Duration>,
+    compile: Option<Duration>,
+    finish: Duration,
+}
+impl CppDepsUnitTiming {
+    pub fn src_path(&self) -> &r5::Utf8Path {
+        &self.src_path
+    }
+
+    pub fn provides(&self) -> &[String] {
+        &self.provides
+    }
+
+    pub fn scan(&self) -> Option<Duration> {
+        self.scan
+    }
+
+    pub fn compile(&self) -> Option<Duration> {
+        self.compile
+    }
+
+    /// The sum of the compile durations along the longest chain of imports ending with this unit.
+    pub fn finish(&self) -> Duration {
+        self.finish
+    }
+}
+
+/// Timings of the units of a build and its critical path, produced by [`CppDepsTrace::report`].
+///
+/// The critical path is the chain of imports with the longest total compile duration: no amount of parallelism can
+/// build the modules faster than it. Its `Display` lists the critical path followed by the slowest units.
+#[derive(Clone, Debug)]
+pub struct CppDepsTraceReport {
+    units: Vec<CppDepsUnitTiming>,
+    critical_path: Vec<usize>,
+}
+impl CppDepsTraceReport {
+    fn new(spans: &[Span]) -> Self {
+        let mut compiles = spans
+            .iter()
+            .filter(|span| span.kind == SpanKind::Compile && span.success)
+            .collect::<Vec<_>>();
+        // NOTE: providers finish before their dependents start, so this visits them first
+        compiles.sort_by_key(|span| span.start);
+
+        let mut units = Vec::<CppDepsUnitTiming>::with_capacity(compiles.len());
+        let mut prev = Vec::<Option<usize>>::with_capacity(compiles.len());
+        let mut providers = HashMap::<&str, usize>::new();
+        for span in compiles {
+            let upstream = span
+                .requires
+                .iter()
+                .filter_map(|name| providers.get(name.as_str()).copied())
+                .max_by_key(|&provider| units[provider].finish);
+            let finish = upstream.map_or(Duration::ZERO, |provider| units[provider].finish) + span.duration;
+            let scan = spans
+                .iter()
+                .filter(|scan| scan.kind == SpanKind::Scan && scan.src_path == span.src_path)
+                .map(|scan| scan.duration)
+                .max();
+            for name in &span.provides {
+                providers.insert(name, units.len());
+            }
+            units.push(CppDepsUnitTiming {
+                src_path: span.src_path.clone(),
+                provides: span.provides.clone(),
+                scan,
+                compile: Some(span.duration),
+                finish,
+            });
+            prev.push(upstream);
+        }
+
+        let mut critical_path = vec![];
+        let mut last = (0 .. units.len()).max_by_key(|&unit| units[unit].finish);
+        while let Some(unit) = last {
+            critical_path.push(unit);
+            last = prev[unit];
+        }
+        critical_path.reverse();
+        Self { units, critical_path }
+    }
+
+    /// The successfully compiled units, in the order they started.
+    pub fn units(&self) -> &[CppDepsUnitTiming] {
+        &self.units
+    }
+
+    /// The units along the critical path, from the first provider to the last dependent.
+    pub fn critical_path(&self) -> impl Iterator<Item = &CppDepsUnitTiming> {
+        self.critical_path.iter().map(|&unit| &self.units[unit])
+    }
+
+    /// The total compile duration along the critical path.
+    pub fn critical_duration(&self) -> Duration {
+        self.critical_path
+            .last()
+            .map_or(Duration::ZERO, |&unit| self.units[unit].finish)
+    }
+}
+impl core::fmt::Display for CppDepsTraceReport {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        fn unit(f: &mut core::fmt::Formatter<'_>, unit: &CppDepsUnitTiming) -> core::fmt::Result {
+            let secs = |duration: Option<Duration>| duration.map_or(0.0, |duration| duration.as_secs_f64());
+            write!(
+                f,
+                "  scan {:>8.3}s  compile {:>8.3}s  {}",
+                secs(unit.scan),
+                secs(unit.compile),
+                unit.src_path
+            )?;
+            if !unit.provides.is_empty() {
+                write!(f, " [{}]", unit.provides.join(", "))?;
+            }
+            writeln!(f)
+        }
+        writeln!(
+            f,
+            "critical path: {:.3}s across {} units",
+            self.critical_duration().as_secs_f64(),
+            self.critical_path.len()
+        )?;
+        for timing in self.critical_path() {
+            unit(f, timing)?;
+        }
+        let mut slowest = self.units.iter().collect::<Vec<_>>();
+        slowest.sort_by_key(|timing| core::cmp::Reverse(timing.compile));
+        writeln!(f, "slowest units:")?;
+        for timing in slowest.into_iter().take(10) {
+            unit(f, timing)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use core::time::Duration;
+
+    use super::{CppDepsTrace, Span, SpanKind};
+    use crate::{testing::BoxResult, CppDepsObserver};
+
+    fn span(src: &str, provides: &[&str], requires: &[&str], start: u64, duration: u64) -> Span {
+        Span {
+            kind: SpanKind::Compile,
+            tid: 0,
+            src_path: src.into(),
+            provides: provides.iter().map(ToString::to_string).collect(),
+            requires: requires.iter().map(ToString::to_string).collect(),
+            start: Duration::from_millis(start),
+            duration: Duration::from_millis(duration),
+            success: true,
+        }
+    }
+
+    #[test]
+    fn report() -> BoxResult<()> {
+        let spans = [
+            span("bar.cppm", &["bar"], &[], 0, 30),
+            span("foo/part1.cppm", &["foo:part1"], &[], 0, 10),
+            span("foo/part2.cppm", &["foo:part2"], &["foo:part1"], 10, 10),
+            span("foo.cppm", &["foo"], &["foo:part1", "foo:part2"], 20, 5),
+            span("main.cpp", &[], &["foo", "bar"], 30, 20),
+        ];
+        let report = super::CppDepsTraceReport::new(&spans);
+        let path = report
+            .critical_path()
+            .map(|unit| unit.src_path().as_str())
+            .collect::<Vec<_>>();
+        assert_eq!(path, ["bar.cppm", "main.cpp"]);
+        assert_eq!(report.critical_duration(), Duration::from_millis(50));
+        let foo = report
+            .units()
+            .iter()
+            .find(|unit| unit.src_path() == "foo.cppm")
+            .ok_or("missing foo")?;
+        assert_eq!(foo.finish(), Duration::from_millis(25));
+        assert!(report.to_string().starts_with("critical path: 0.050s across 2 units\n"));
+        Ok(())
+    }
+
+    #[test]
+    fn trace_json() -> BoxResult<()> {
+        let trace = CppDepsTrace::new();
+        let observer = trace.clone();
+        std::thread::Builder::new()
+            .name(String::from("cpp-deps-worker-0"))
+            .spawn(move || {
+                observer.event(crate::CppDepsEvent::ScanFinished {
+                    src_path: "foo.cppm".into(),
+                    duration: Duration::from_millis(1),
+                    success: true,
+                });
+                observer.event(crate::CppDepsEvent::CompileFinished {
+                    src_path: "foo.cppm".into(),
+                    provides: vec![String::from("foo")],
+                    requires: vec![],
+                    duration: Duration::from_millis(2),
+                    success: true,
+                });
+            })?
+            .join()
+            .map_err(|_| "observer panicked")?;
+
+        let json = serde_json::from_str::<serde_json::Value>(&trace.to_json())?;
+        let events = json["traceEvents"].as_array().ok_or("missing events")?;
+        assert_eq!(events.len(), 3);
+        assert_eq!(events[0]["ph"], "M");
+        assert_eq!(events[0]["args"]["name"], "cpp-deps-worker-0");
+        assert_eq!(events[1]["cat"], "scan");
+        assert_eq!(events[2]["cat"], "compile");
+        assert_eq!(events[2]["dur"], 2000);
+        assert_eq!(events[2]["args"]["provides"], serde_json::json!(["foo"]));
+        assert_eq!(trace.report().units().len(), 1);
+        Ok(())
+    }
+
+    #[test]
+    fn trace_compile() -> BoxResult<()> {
+        let paths = crate::testing::corpus::src_file::items()?;
+        let mut validate = crate::testing::corpus::src_file::validate_order(paths)?;
+        validate.cpp_deps.executor(crate::testing::RecordingExecutor::default());
+        validate.cpp_deps.parallelism(2)?;
+        let trace = CppDepsTrace::new();
+        validate.cpp_deps.observer(trace.clone());
+        validate.run()?;
+
+        let report = trace.report();
+        assert_eq!(report.units().len(), 5);
+        let last = report.critical_path().last().ok_or("empty critical path")?;
+        assert!(last.src_path().ends_with("main.cpp"));
+        let json = serde_json::from_str::<serde_json::Value>(&trace.to_json())?;
+        let names = json["traceEvents"]
+            .as_array()
+            .ok_or("missing events")?
+            .iter()
+            .filter(|event| event["ph"] == "M")
+            .filter_map(|event| event["args"]["name"].as_str())
+            .collect::<Vec<_>>();
+        assert!(!names.is_empty());
+        assert!(names.iter().all(|name| name.starts_with("cpp-deps-worker-")));
+        Ok(())
+    }
+}
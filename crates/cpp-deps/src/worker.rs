@@ -1,5 +1,9 @@
+#[cfg(feature = "cc")]
+use std::time::Instant;
 use std::{collections::BTreeSet, fs::File, sync::Arc};
 
+#[cfg(feature = "cc")]
+use ::p1689::r5::yoke::DepInfoYokeExt;
 use memmap2::Mmap;
 use p1689::r5::{
     self,
@@ -7,25 +11,37 @@ use p1689::r5::{
 };
 use yoke::Yoke;
 
+#[cfg(feature = "cc")]
+use crate::{
+    analyzer::{names, CompileNode, ResolveNode},
+    compiler::Compiler,
+    state::BuildState,
+};
 use crate::{
-    analyzer::{AnalyzeNode, AnalyzerItem, WorkerItem},
+    analyzer::{AnalyzeNode, AnalyzerItem, FailureNode, WorkerItem},
+    schedule,
+    CppDepsCancel,
+    CppDepsEvent,
+    CppDepsExecution,
     CppDepsItem,
+    CppDepsObserver,
     CppDepsSrc,
     InnerError,
     InnerErrorKind,
 };
-#[cfg(feature = "cc")]
-use crate::{
-    analyzer::{CompileNode, ResolveNode},
-    compiler::Compiler,
-};
 
 pub struct Worker<P, B> {
     failure_tx: flume::Sender<InnerError>,
     analyze_tx: flume::Sender<AnalyzerItem<P>>,
-    compile_rx: flume::Receiver<WorkerItem<P, B>>,
+    compile_rx: schedule::Receiver<WorkerItem<P, B>>,
+    observer: Option<Arc<dyn CppDepsObserver>>,
+    cancel: CppDepsCancel,
     #[cfg(feature = "cc")]
     compiler: Arc<Compiler>,
+    #[cfg(feature = "cc")]
+    state: Option<Arc<BuildState>>,
+    #[cfg(feature = "cc")]
+    dry_run: bool,
 }
 
 impl<P, B> Worker<P, B>
@@ -33,24 +49,39 @@ where
     P: AsRef<r5::Utf8Path> + Send + Sync + 'static,
     B: AsRef<[u8]> + Send + Sync + 'static,
 {
+    #[allow(clippy::too_many_arguments)]
     pub(crate) fn new(
         failure_tx: flume::Sender<InnerError>,
         analyze_tx: flume::Sender<AnalyzerItem<P>>,
-        compile_rx: flume::Receiver<WorkerItem<P, B>>,
+        compile_rx: schedule::Receiver<WorkerItem<P, B>>,
+        observer: Option<Arc<dyn CppDepsObserver>>,
+        cancel: CppDepsCancel,
         #[cfg(feature = "cc")] compiler: Arc<Compiler>,
+        #[cfg(feature = "cc")] state: Option<Arc<BuildState>>,
+        #[cfg(feature = "cc")] dry_run: bool,
     ) -> Self {
         Self {
             failure_tx,
             analyze_tx,
             compile_rx,
+            observer,
+            cancel,
             #[cfg(feature = "cc")]
             compiler,
+            #[cfg(feature = "cc")]
+            state,
+            #[cfg(feature = "cc")]
+            dry_run,
         }
     }
 
     pub(crate) fn run(mut self) -> impl FnOnce() {
         move || {
-            while let Ok(item) = self.compile_rx.recv() {
+            while let Some(item) = self.compile_rx.recv() {
+                // NOTE: the items left in the queue are dropped with it
+                if self.cancel.is_cancelled() {
+                    break;
+                }
                 if let Err(err) = self.step(item) {
                     self.failure_tx.send(err).ok();
                 }
@@ -68,6 +99,21 @@ where
     }
 
     fn analyze(&mut self, item: CppDepsItem<P, B>) -> Result<(), InnerError> {
+        let path = match &item {
+            #[cfg(feature = "cc")]
+            CppDepsItem::SrcFile { src_file } => Some(src_file.src_path.as_ref()),
+            CppDepsItem::DepFile { dep_path, .. } | CppDepsItem::DepText { dep_path, .. } => Some(dep_path.as_ref()),
+            CppDepsItem::DepInfo { .. } => None,
+        }
+        .map(ToOwned::to_owned);
+        self.observe(|| CppDepsEvent::ItemReceived { path: path.clone() });
+        if let Err(err) = self.analyze_item(item) {
+            self.failure(FailureNode::Analyze { path, err })?;
+        }
+        Ok(())
+    }
+
+    fn analyze_item(&mut self, item: CppDepsItem<P, B>) -> Result<(), InnerError> {
         match item {
             #[cfg(feature = "cc")]
             CppDepsItem::SrcFile { src_file } => self.analyze_src_file(src_file)?,
@@ -86,15 +132,34 @@ where
     fn analyze_src_file(&mut self, src_file: CppDepsSrc<P>) -> Result<(), InnerError> {
         let src_base = src_file.src_base.as_ref();
         let src_path = src_file.src_path.as_ref();
-        let dep_path = self.compiler.compile_dep_file(src_base, src_path)?;
+        self.observe(|| CppDepsEvent::ScanStarted {
+            src_path: src_path.to_owned(),
+        });
+        // NOTE: sent before scanning so that a failing scan still reruns the build script once the source changes
+        self.inputs(vec![src_path.to_owned()])?;
+        let start = Instant::now();
+        let result = self
+            .compiler
+            .compile_dep_file(src_base, src_path, src_file.command(), self.state.as_deref());
+        self.observe(|| CppDepsEvent::ScanFinished {
+            src_path: src_path.to_owned(),
+            duration: start.elapsed(),
+            success: result.is_ok(),
+        });
+        let (dep_path, headers, scan_execution) = result?;
+        self.inputs(headers)?;
         let file = File::open(&dep_path).map_err(|err| InnerError::new(InnerErrorKind::FileOpen { err }))?;
         let mmap = unsafe { Mmap::map(&file) }.map_err(|err| InnerError::new(InnerErrorKind::MmapMap { err }))?;
         let cart = Arc::new(mmap) as DepFileCart;
-        self.parse_dep_file(Some(src_file), dep_path, cart)?;
+        self.parse_dep_file(Some(src_file), dep_path, cart, scan_execution)?;
         Ok(())
     }
 
     fn analyze_dep_file(&mut self, src_file: Option<CppDepsSrc<P>>, dep_path: P) -> Result<(), InnerError> {
+        let inputs = core::iter::once(dep_path.as_ref().to_owned())
+            .chain(src_file.iter().map(|src_file| src_file.src_path.as_ref().to_owned()))
+            .collect();
+        self.inputs(inputs)?;
         let file = {
             let path = AsRef::<r5::Utf8Path>::as_ref(&dep_path);
             let path = AsRef::<std::path::Path>::as_ref(&path);
@@ -102,7 +167,7 @@ where
         }?;
         let mmap = unsafe { Mmap::map(&file) }.map_err(|err| InnerError::new(InnerErrorKind::MmapMap { err }))?;
         let cart = Arc::new(mmap) as DepFileCart;
-        self.parse_dep_file(src_file, dep_path, cart)?;
+        self.parse_dep_file(src_file, dep_path, cart, None)?;
         Ok(())
     }
 
@@ -112,20 +177,33 @@ where
         dep_path: P,
         dep_text: B,
     ) -> Result<(), InnerError> {
+        let inputs = src_file
+            .iter()
+            .map(|src_file| src_file.src_path.as_ref().to_owned())
+            .collect();
+        self.inputs(inputs)?;
         let cart = Arc::new(dep_text) as DepFileCart;
-        self.parse_dep_file(src_file, dep_path, cart)?;
+        self.parse_dep_file(src_file, dep_path, cart, None)?;
         Ok(())
     }
 
     fn analyze_dep_info(&mut self, src_file: Option<CppDepsSrc<P>>, dep_info: DepInfoYoke) -> Result<(), InnerError> {
+        let inputs = src_file
+            .iter()
+            .map(|src_file| src_file.src_path.as_ref().to_owned())
+            .collect();
+        self.inputs(inputs)?;
         let src_file = src_file.map(Arc::new);
         let bmi_dirs = BTreeSet::default();
         let bmi_maps = Vec::default();
+        let bmi_inputs = BTreeSet::default();
         let item = AnalyzerItem::Analyze(AnalyzeNode {
             src_file,
             dep_info,
             bmi_dirs,
             bmi_maps,
+            bmi_inputs,
+            scan_execution: None,
         });
         self.analyze_tx
             .send(item)
@@ -140,18 +218,67 @@ where
             dep_info,
             mut bmi_dirs,
             bmi_maps,
+            bmi_inputs,
+            scan_execution,
         } = item;
         let src_base = src_file.src_base.as_ref();
         let src_path = src_file.src_path.as_ref();
-        let obj_path = self
-            .compiler
-            .compile_obj_file(dep_info.get(), src_base, src_path, &mut bmi_dirs, &bmi_maps)?;
-        let bmi_path = self.compiler.bmi_path(obj_path, &dep_info.get().provides);
+        let command = src_file.command();
+        let provides = || names(dep_info.provides());
+        let requires = || names(dep_info.requires());
+        self.observe(|| CppDepsEvent::CompileStarted {
+            src_path: src_path.to_owned(),
+            provides: provides(),
+            requires: requires(),
+        });
+        let start = Instant::now();
+        let result = if self.dry_run {
+            self.compiler
+                .plan_obj_file(&dep_info, src_base, src_path, command, &mut bmi_dirs, &bmi_maps)
+        } else {
+            self.compiler.compile_obj_file(
+                &dep_info,
+                src_base,
+                src_path,
+                command,
+                &mut bmi_dirs,
+                &bmi_maps,
+                &bmi_inputs,
+                self.state.as_deref(),
+            )
+        };
+        self.observe(|| CppDepsEvent::CompileFinished {
+            src_path: src_path.to_owned(),
+            provides: provides(),
+            requires: requires(),
+            duration: start.elapsed(),
+            success: result.is_ok(),
+        });
+        let (obj_path, command, execution) = match result {
+            Ok(compiled) => compiled,
+            Err(err) => {
+                let src_path = src_path.to_owned();
+                return self.failure(FailureNode::Compile {
+                    src_path,
+                    dep_info,
+                    err,
+                });
+            },
+        };
+        let bmi_outputs = self.compiler.bmi_outputs(&obj_path, &dep_info, &command);
+        let bmi_paths = self.compiler.bmi_paths(&obj_path, &dep_info);
         let node = ResolveNode {
-            bmi_path,
+            src_file: Some(src_file),
+            obj_path: Some(obj_path),
+            bmi_outputs,
+            bmi_paths,
+            command: Some(command),
+            execution,
             dep_info,
             bmi_dirs,
             bmi_maps,
+            bmi_inputs,
+            scan_execution,
         };
         let item = AnalyzerItem::Resolve(node);
         self.analyze_tx
@@ -160,6 +287,31 @@ where
         Ok(())
     }
 
+    fn observe(&self, event: impl FnOnce() -> CppDepsEvent) {
+        if let Some(observer) = &self.observer {
+            observer.event(event());
+        }
+    }
+
+    fn failure(&self, node: FailureNode) -> Result<(), InnerError> {
+        let item = AnalyzerItem::Failure(Box::new(node));
+        self.analyze_tx
+            .send(item)
+            .map_err(|_| InnerError::new(InnerErrorKind::WorkerFailedSendingAnalyzerItem))?;
+        Ok(())
+    }
+
+    fn inputs(&self, inputs: Vec<r5::Utf8PathBuf>) -> Result<(), InnerError> {
+        if inputs.is_empty() {
+            return Ok(());
+        }
+        let item = AnalyzerItem::Inputs(inputs);
+        self.analyze_tx
+            .send(item)
+            .map_err(|_| InnerError::new(InnerErrorKind::WorkerFailedSendingAnalyzerItem))?;
+        Ok(())
+    }
+
     fn expects(&self, count: usize) -> Result<(), InnerError> {
         let item = AnalyzerItem::Expects(count);
         self.analyze_tx
@@ -173,6 +325,7 @@ where
         src_file: Option<CppDepsSrc<P>>,
         dep_path: Path,
         dep_cart: DepFileCart,
+        scan_execution: Option<CppDepsExecution>,
     ) -> Result<(), InnerError>
     where
         Path: AsRef<r5::Utf8Path>,
@@ -188,11 +341,14 @@ where
             let src_file = src_file.clone();
             let bmi_dirs = BTreeSet::default();
             let bmi_maps = Vec::default();
+            let bmi_inputs = BTreeSet::default();
             let item = AnalyzerItem::Analyze(AnalyzeNode {
                 src_file,
                 dep_info,
                 bmi_dirs,
                 bmi_maps,
+                bmi_inputs,
+                scan_execution: scan_execution.clone(),
             });
             self.analyze_tx
                 .send(item)